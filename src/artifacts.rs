@@ -0,0 +1,290 @@
+// Each scenario run gets its own directory, holding the output of every
+// test along with any files the tests chose to save.  A manifest describing
+// the run is written out once the scenario finishes.
+//
+//   <artifact-root>/<run-id>/manifest.json
+//   <artifact-root>/<run-id>/<test-id>/stdout.log
+//   <artifact-root>/<run-id>/<test-id>/stderr.log
+//   <artifact-root>/<run-id>/<test-id>/<anything the test saves>
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json;
+
+use unit::UnitName;
+use unitbroadcaster::LogSource;
+
+/// Name of the manifest file in each run directory.
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TestState {
+    Pending,
+    Running,
+    Pass,
+    Fail,
+    Skip,
+}
+
+impl TestState {
+    pub fn as_str(&self) -> &str {
+        match *self {
+            TestState::Pending => "pending",
+            TestState::Running => "running",
+            TestState::Pass => "pass",
+            TestState::Fail => "fail",
+            TestState::Skip => "skip",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TestRecord {
+    /// The id of the test unit
+    pub id: String,
+
+    /// The human-readable name of the test
+    pub name: String,
+
+    pub state: TestState,
+
+    /// Last line printed by the test, or the reason it was skipped
+    pub message: String,
+
+    /// How long the test ran for, in milliseconds
+    pub duration_ms: Option<u64>,
+
+    /// Directory, relative to the run directory, holding this test's files
+    pub directory: String,
+
+    /// Files present in the test directory when the run finished
+    pub files: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunManifest {
+    pub run_id: String,
+    pub scenario: String,
+    pub scenario_name: String,
+    pub jig: Option<String>,
+
+    /// Serial number of the device under test, if it was declared
+    #[serde(default)]
+    pub serial: Option<String>,
+
+    /// The operator who ran it, if one was logged in
+    #[serde(default)]
+    pub operator: Option<String>,
+
+    /// Seconds since the epoch when the run started
+    pub started: u64,
+
+    /// Seconds since the epoch when the run finished
+    pub finished: Option<u64>,
+
+    /// How long the whole run took, in milliseconds
+    pub duration_ms: Option<u64>,
+
+    /// The scenario's result code, and the reason it finished
+    pub result: Option<u32>,
+    pub message: String,
+
+    /// Tests, in the order the scenario runs them
+    pub tests: Vec<TestRecord>,
+}
+
+impl RunManifest {
+    pub fn load(run_dir: &Path) -> io::Result<RunManifest> {
+        let file = File::open(run_dir.join(MANIFEST_NAME))?;
+        Ok(serde_json::from_reader(file)?)
+    }
+}
+
+pub struct RunArtifacts {
+    dir: PathBuf,
+    started: Instant,
+    test_starts: HashMap<UnitName, Instant>,
+    outputs: HashMap<(UnitName, &'static str), File>,
+    manifest: RunManifest,
+}
+
+impl RunArtifacts {
+    /// Create a new run directory underneath `root`.
+    pub fn create(root: &Path,
+                  run_id: &str,
+                  scenario: &UnitName,
+                  scenario_name: &str,
+                  jig: Option<&UnitName>,
+                  tests: Vec<(UnitName, String)>)
+                  -> io::Result<RunArtifacts> {
+        let dir = root.join(run_id);
+        fs::create_dir_all(&dir)?;
+
+        let tests = tests.into_iter()
+            .map(|(id, name)| TestRecord {
+                id: id.id().clone(),
+                name,
+                state: TestState::Pending,
+                message: String::new(),
+                duration_ms: None,
+                directory: id.id().clone(),
+                files: vec![],
+            })
+            .collect();
+
+        Ok(RunArtifacts {
+            dir,
+            started: Instant::now(),
+            test_starts: HashMap::new(),
+            outputs: HashMap::new(),
+            manifest: RunManifest {
+                run_id: run_id.to_owned(),
+                scenario: scenario.id().clone(),
+                scenario_name: scenario_name.to_owned(),
+                jig: jig.map(|j| j.id().clone()),
+                serial: None,
+                operator: None,
+                started: Self::unix_secs(),
+                finished: None,
+                duration_ms: None,
+                result: None,
+                message: String::new(),
+                tests,
+            },
+        })
+    }
+
+    /// The directory that holds this run.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Return the directory for a given test, creating it if necessary.
+    pub fn test_dir(&self, test: &UnitName) -> io::Result<PathBuf> {
+        let dir = self.dir.join(test.id());
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    /// Append a line of test output to the appropriate capture file.
+    pub fn append_output(&mut self, test: &UnitName, source: &LogSource, line: &str) -> io::Result<()> {
+        let file_name = match *source {
+            LogSource::Stdout => "stdout.log",
+            LogSource::Stderr => "stderr.log",
+            _ => return Ok(()),
+        };
+        let key = (test.clone(), file_name);
+        if !self.outputs.contains_key(&key) {
+            let path = self.test_dir(test)?.join(file_name);
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            self.outputs.insert(key.clone(), file);
+        }
+        writeln!(self.outputs.get_mut(&key).unwrap(), "{}", line)
+    }
+
+    pub fn set_serial(&mut self, serial: Option<String>) {
+        self.manifest.serial = serial;
+    }
+
+    pub fn set_operator(&mut self, operator: Option<String>) {
+        self.manifest.operator = operator;
+    }
+
+    pub fn test_started(&mut self, test: &UnitName) {
+        self.test_starts.insert(test.clone(), Instant::now());
+        self.set_state(test, TestState::Running, String::new());
+    }
+
+    pub fn test_finished(&mut self, test: &UnitName, result: i32, message: &str) {
+        let state = if result == 0 { TestState::Pass } else { TestState::Fail };
+        self.set_state(test, state, message.to_owned());
+    }
+
+    pub fn test_skipped(&mut self, test: &UnitName, reason: &str) {
+        self.set_state(test, TestState::Skip, reason.to_owned());
+    }
+
+    fn set_state(&mut self, test: &UnitName, state: TestState, message: String) {
+        let duration = match state {
+            TestState::Pass | TestState::Fail => self.test_starts.get(test).map(|s| Self::millis(&s.elapsed())),
+            _ => None,
+        };
+        for record in self.manifest.tests.iter_mut() {
+            if &record.id == test.id() {
+                record.state = state;
+                record.message = message;
+                if duration.is_some() {
+                    record.duration_ms = duration;
+                }
+                return;
+            }
+        }
+    }
+
+    /// Record the result of the scenario and write out the manifest.
+    pub fn finish(mut self, result: u32, message: &str) -> io::Result<PathBuf> {
+        self.outputs.clear();
+        self.manifest.finished = Some(Self::unix_secs());
+        self.manifest.duration_ms = Some(Self::millis(&self.started.elapsed()));
+        self.manifest.result = Some(result);
+        self.manifest.message = message.to_owned();
+
+        for record in self.manifest.tests.iter_mut() {
+            let mut files = vec![];
+            if let Ok(entries) = fs::read_dir(self.dir.join(&record.directory)) {
+                for entry in entries.filter_map(|e| e.ok()) {
+                    files.push(entry.file_name().to_string_lossy().into_owned());
+                }
+            }
+            files.sort();
+            record.files = files;
+        }
+
+        let manifest_path = self.dir.join(MANIFEST_NAME);
+        let mut file = File::create(&manifest_path)?;
+        serde_json::to_writer_pretty(&mut file, &self.manifest)?;
+        writeln!(file)?;
+        Ok(manifest_path)
+    }
+
+    fn unix_secs() -> u64 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs(),
+            Err(_) => 0,
+        }
+    }
+
+    fn millis(d: &Duration) -> u64 {
+        d.as_secs() * 1000 + d.subsec_millis() as u64
+    }
+}
+
+/// Return the run directories underneath `root` that have a manifest,
+/// oldest first.
+pub fn completed_runs(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut runs = vec![];
+    for entry in fs::read_dir(root)? {
+        let path = entry?.path();
+        if path.join(MANIFEST_NAME).is_file() {
+            runs.push(path);
+        }
+    }
+    // Run ids begin with the start time, so they sort chronologically.
+    runs.sort();
+    Ok(runs)
+}
+
+/// Remove all but the `keep` most recent completed runs.  Returns the
+/// directories that were removed.
+pub fn prune_runs(root: &Path, keep: usize) -> io::Result<Vec<PathBuf>> {
+    let mut runs = completed_runs(root)?;
+    let excess = runs.len().saturating_sub(keep);
+    let removed: Vec<PathBuf> = runs.drain(0..excess).collect();
+    for run in &removed {
+        fs::remove_dir_all(run)?;
+    }
+    Ok(removed)
+}
@@ -0,0 +1,407 @@
+// A tamper-evident audit log.  Every log entry and lifecycle event is
+// written as a line of JSON containing the hash of the previous line, so
+// that editing or removing any record breaks the chain.  Periodically, and
+// once everything has stopped at shutdown, a checkpoint record is written that is signed with an HMAC
+// of the current chain head.
+//
+// The HMAC key is a file on the station, and exclave must be able to read
+// it in order to sign.  This catches corruption, and changes made by anyone
+// who can write the log but can't read the key.  Anyone who can read the
+// key can rewrite the log and its anchor and sign them again, so the key
+// should only be readable by the user exclave runs as.  Verifying against a
+// copy of the anchor taken off the station also catches that, for the
+// records the copy covers.
+//
+// Each line has the form:
+//   {"seq":N,"prev":"<hash of record N-1>","kind":"log","record":{...},"hash":"<hash>"}
+// where the hash covers the sequence number, previous hash, kind, and record.
+//
+// Cutting the log back to an earlier checkpoint would leave a valid chain,
+// so each checkpoint is also recorded in an anchor file kept beside the log.
+// The anchor holds the number of records and the hash of the last one, and
+// is signed with the same key:
+//   {"records":N,"head":"<hash of record N-1>","signature":"<hmac>"}
+//
+// The whole chain is checked again whenever the log is opened, and a log that
+// doesn't verify is never continued.  If exclave stopped without writing a
+// final checkpoint, the records after the last one were never signed, so a
+// "resume" record noting how many there are is written, and then signed,
+// before anything new is added:
+//   {"unsigned":N}
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use hmac::{Hmac, Mac};
+use serde_json::{self, Value};
+use sha2::{Digest, Sha256};
+
+use state;
+use unitbroadcaster::UnitEvent;
+
+/// Number of records written between signed checkpoints.
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 100;
+
+/// The "previous hash" of the very first record.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug)]
+pub enum AuditError {
+    IoError(io::Error),
+    KeyError(String),
+    ParseError(u64 /* line */, String),
+    BadSequence(u64 /* line */, u64 /* expected */, u64 /* found */),
+    BrokenChain(u64 /* line */, u64 /* seq */),
+    BadHash(u64 /* line */, u64 /* seq */),
+    BadSignature(u64 /* line */, u64 /* seq */),
+    Unsigned(u64 /* number of records after the last checkpoint */),
+    MissingAnchor(PathBuf),
+    BadAnchor(PathBuf, String),
+    Truncated(u64 /* records in the log */, u64 /* records anchored */),
+    BadResume(u64 /* line */, u64 /* seq */),
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuditError::IoError(e) => write!(f, "i/o error: {}", e),
+            AuditError::KeyError(s) => write!(f, "unable to use key: {}", s),
+            AuditError::ParseError(line, s) => write!(f, "line {}: unable to parse record: {}", line, s),
+            AuditError::BadSequence(line, expected, found) => {
+                write!(f, "line {}: expected record {}, found record {} (records missing or reordered)", line, expected, found)
+            }
+            AuditError::BrokenChain(line, seq) => {
+                write!(f, "line {}: record {} does not follow the previous record (chain broken)", line, seq)
+            }
+            AuditError::BadHash(line, seq) => write!(f, "line {}: record {} has been modified (hash mismatch)", line, seq),
+            AuditError::BadSignature(line, seq) => write!(f, "line {}: checkpoint {} has an invalid signature", line, seq),
+            AuditError::Unsigned(count) => {
+                write!(f, "{} records follow the last signed checkpoint (log truncated or exclave did not shut down cleanly)", count)
+            }
+            AuditError::MissingAnchor(path) => {
+                write!(f, "anchor {} is missing, but the log has checkpoints", path.display())
+            }
+            AuditError::BadAnchor(path, s) => write!(f, "anchor {}: {}", path.display(), s),
+            AuditError::Truncated(found, anchored) => {
+                write!(f, "log has {} records, but its anchor says it had {} (log truncated)", found, anchored)
+            }
+            AuditError::BadResume(line, seq) => {
+                write!(f, "line {}: resume record {} does not match the unsigned records before it", line, seq)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for AuditError {
+    fn from(error: io::Error) -> Self {
+        AuditError::IoError(error)
+    }
+}
+
+/// Summary of a successfully-verified audit log.
+pub struct AuditSummary {
+    pub records: u64,
+    pub checkpoints: u64,
+
+    /// Records that were only signed after exclave restarted
+    pub resumed: u64,
+}
+
+/// Everything learned by reading an audit log from start to end.
+struct Scan {
+    records: u64,
+    checkpoints: u64,
+    resumed: u64,
+
+    /// Records after the last checkpoint
+    since_checkpoint: u64,
+
+    /// Hash of the last record
+    head: String,
+}
+
+/// The last checkpoint, as recorded outside of the log.
+#[derive(Serialize, Deserialize)]
+struct Anchor {
+    /// Number of records in the log, up to and including the checkpoint
+    records: u64,
+
+    /// Hash of the checkpoint record
+    head: String,
+
+    signature: String,
+}
+
+impl Anchor {
+    fn new(key: &[u8], records: u64, head: &str) -> Result<Anchor, AuditError> {
+        Ok(Anchor {
+            records,
+            head: head.to_owned(),
+            signature: sign(key, &format!("{}\t{}", records, head))?,
+        })
+    }
+
+    /// Load the anchor at `path`, making sure it was signed with `key`.
+    fn load(path: &Path, key: &[u8]) -> Result<Option<Anchor>, AuditError> {
+        let anchor: Anchor = match state::load(path) {
+            Ok(Some(a)) => a,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(AuditError::BadAnchor(path.to_owned(), format!("{}", e))),
+        };
+        if sign(key, &format!("{}\t{}", anchor.records, anchor.head))? != anchor.signature {
+            return Err(AuditError::BadAnchor(path.to_owned(), "invalid signature".to_owned()));
+        }
+        Ok(Some(anchor))
+    }
+
+    /// Make sure a log whose record `records - 1` has the hash
+    /// `anchored_hash`, and which has `checkpoints` checkpoints, still
+    /// contains everything that this anchor recorded.
+    fn check(anchor: &Option<Anchor>,
+             path: &Path,
+             records: u64,
+             anchored_hash: Option<&str>,
+             checkpoints: u64)
+             -> Result<(), AuditError> {
+        match *anchor {
+            None if checkpoints > 0 => Err(AuditError::MissingAnchor(path.to_owned())),
+            None => Ok(()),
+            Some(ref anchor) if records < anchor.records => Err(AuditError::Truncated(records, anchor.records)),
+            Some(ref anchor) if anchored_hash != Some(anchor.head.as_str()) => Err(AuditError::BadAnchor(
+                path.to_owned(),
+                format!("record {} is not the one that was anchored (log rewritten)", anchor.records - 1),
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+/// The anchor file that goes with the audit log at `log_path`, which is
+/// the log's name with ".anchor" added.
+pub fn default_anchor_path(log_path: &Path) -> PathBuf {
+    let mut name = OsString::from(log_path.as_os_str());
+    name.push(".anchor");
+    PathBuf::from(name)
+}
+
+pub struct AuditLog {
+    file: File,
+    key: Vec<u8>,
+    anchor_path: PathBuf,
+    seq: u64,
+    prev: String,
+    checkpoint_interval: u64,
+    since_checkpoint: u64,
+}
+
+impl AuditLog {
+    /// Open an audit log, continuing the chain if the file already exists.
+    /// The existing log must verify, and must still reach its anchor, or it
+    /// is refused rather than continued.
+    pub fn open(path: &Path, key_path: &Path, anchor_path: &Path, checkpoint_interval: u64) -> Result<AuditLog, AuditError> {
+        let key = read_key(key_path)?;
+        let scan = if path.exists() {
+            scan(path, &key, anchor_path)?
+        } else {
+            Anchor::check(&Anchor::load(anchor_path, &key)?, anchor_path, 0, None, 0)?;
+            Scan {
+                records: 0,
+                checkpoints: 0,
+                resumed: 0,
+                since_checkpoint: 0,
+                head: GENESIS_HASH.to_owned(),
+            }
+        };
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut log = AuditLog {
+            file,
+            key,
+            anchor_path: anchor_path.to_owned(),
+            seq: scan.records,
+            prev: scan.head,
+            checkpoint_interval,
+            since_checkpoint: 0,
+        };
+
+        // Records left unsigned by a crash are noted and signed before
+        // anything is added after them.
+        if scan.since_checkpoint > 0 {
+            log.append("resume", json!({ "unsigned": scan.since_checkpoint }))?;
+            log.checkpoint()?;
+        }
+        Ok(log)
+    }
+
+    pub fn process_message(&mut self, msg: &UnitEvent) -> Result<(), AuditError> {
+        match msg {
+            UnitEvent::Log(entry) => self.append("log", serde_json::to_value(entry).unwrap())?,
+            UnitEvent::Lifecycle(entry) => self.append("lifecycle", serde_json::to_value(entry).unwrap())?,
+            UnitEvent::Exit => return self.checkpoint(),
+            _ => return Ok(()),
+        }
+
+        self.since_checkpoint += 1;
+        if self.since_checkpoint >= self.checkpoint_interval {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Write a checkpoint, signing the current head of the chain.
+    pub fn checkpoint(&mut self) -> Result<(), AuditError> {
+        let head = self.prev.clone();
+        let signature = sign(&self.key, &head)?;
+        let record = json!({
+            "records": self.seq,
+            "head": head,
+            "signature": signature,
+        });
+        self.append("checkpoint", record)?;
+        self.since_checkpoint = 0;
+
+        let anchor = Anchor::new(&self.key, self.seq, &self.prev)?;
+        state::save(&self.anchor_path, &anchor)?;
+        Ok(())
+    }
+
+    fn append(&mut self, kind: &str, record: Value) -> Result<(), AuditError> {
+        let hash = record_hash(self.seq, &self.prev, kind, &record);
+        let line = json!({
+            "seq": self.seq,
+            "prev": self.prev,
+            "kind": kind,
+            "record": record,
+            "hash": hash,
+        });
+        writeln!(self.file, "{}", line)?;
+        self.file.flush()?;
+        self.seq += 1;
+        self.prev = hash;
+        Ok(())
+    }
+}
+
+/// Check every record in an audit log, and that it still reaches its
+/// anchor.  Returns an error describing the first problem found.
+pub fn verify(path: &Path, key_path: &Path, anchor_path: &Path) -> Result<AuditSummary, AuditError> {
+    let key = read_key(key_path)?;
+    let scan = scan(path, &key, anchor_path)?;
+    if scan.since_checkpoint > 0 {
+        return Err(AuditError::Unsigned(scan.since_checkpoint));
+    }
+    Ok(AuditSummary {
+        records: scan.records,
+        checkpoints: scan.checkpoints,
+        resumed: scan.resumed,
+    })
+}
+
+/// Check every record in an audit log, and that it still reaches its
+/// anchor, without minding records after the last checkpoint.
+fn scan(path: &Path, key: &[u8], anchor_path: &Path) -> Result<Scan, AuditError> {
+    let anchor = Anchor::load(anchor_path, key)?;
+    let file = File::open(path)?;
+    let mut anchored_hash = None;
+
+    let mut expected_seq = 0;
+    let mut prev = GENESIS_HASH.to_owned();
+    let mut checkpoints = 0;
+    let mut resumed = 0;
+    let mut since_checkpoint = 0;
+
+    for (idx, line) in BufReader::new(file).lines().enumerate() {
+        let line_no = (idx + 1) as u64;
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Value = serde_json::from_str(&line).map_err(|e| AuditError::ParseError(line_no, format!("{}", e)))?;
+        let (seq, record_prev, kind, hash) = match (record["seq"].as_u64(),
+                                                    record["prev"].as_str(),
+                                                    record["kind"].as_str(),
+                                                    record["hash"].as_str()) {
+            (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+            _ => return Err(AuditError::ParseError(line_no, "missing seq, prev, kind, or hash".to_owned())),
+        };
+
+        if seq != expected_seq {
+            return Err(AuditError::BadSequence(line_no, expected_seq, seq));
+        }
+        if record_prev != prev {
+            return Err(AuditError::BrokenChain(line_no, seq));
+        }
+        if record_hash(seq, record_prev, kind, &record["record"]) != hash {
+            return Err(AuditError::BadHash(line_no, seq));
+        }
+
+        match kind {
+            "checkpoint" => {
+                let head = record["record"]["head"].as_str().unwrap_or("");
+                let signature = record["record"]["signature"].as_str().unwrap_or("");
+                if head != prev || sign(key, head)? != signature {
+                    return Err(AuditError::BadSignature(line_no, seq));
+                }
+                checkpoints += 1;
+                since_checkpoint = 0;
+            }
+            "resume" => {
+                if record["record"]["unsigned"].as_u64() != Some(since_checkpoint) {
+                    return Err(AuditError::BadResume(line_no, seq));
+                }
+                resumed += since_checkpoint;
+                since_checkpoint += 1;
+            }
+            _ => since_checkpoint += 1,
+        }
+
+        prev = hash.to_owned();
+        expected_seq += 1;
+        if anchor.as_ref().map(|a| a.records == expected_seq).unwrap_or(false) {
+            anchored_hash = Some(prev.clone());
+        }
+    }
+
+    // The log may have grown since the anchor was written, if it is a copy
+    // taken off the station earlier.
+    Anchor::check(&anchor, anchor_path, expected_seq, anchored_hash.as_deref(), checkpoints)?;
+
+    Ok(Scan {
+        records: expected_seq,
+        checkpoints,
+        resumed,
+        since_checkpoint,
+        head: prev,
+    })
+}
+
+fn read_key(key_path: &Path) -> Result<Vec<u8>, AuditError> {
+    let mut key = vec![];
+    File::open(key_path)
+        .and_then(|mut f| f.read_to_end(&mut key))
+        .map_err(|e| AuditError::KeyError(format!("{}: {}", key_path.display(), e)))?;
+    if key.is_empty() {
+        return Err(AuditError::KeyError(format!("{} is empty", key_path.display())));
+    }
+    Ok(key)
+}
+
+fn record_hash(seq: u64, prev: &str, kind: &str, record: &Value) -> String {
+    // serde_json sorts object keys, so the serialized record is the same
+    // whether it came from a LogEntry or was parsed back out of the file.
+    let mut hasher = Sha256::new();
+    hasher.input(format!("{}\t{}\t{}\t{}", seq, prev, kind, record).as_bytes());
+    to_hex(hasher.result().as_slice())
+}
+
+fn sign(key: &[u8], head: &str) -> Result<String, AuditError> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).map_err(|_| AuditError::KeyError("invalid key length".to_owned()))?;
+    mac.input(head.as_bytes());
+    Ok(to_hex(mac.result().code().as_slice()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -1,42 +1,260 @@
+extern crate runny;
+
 use std::cell::RefCell;
 use std::env;
+use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::time::Duration;
 
+use detect::DEFAULT_SYSFS_ROOT;
+use expand::{Defines, Expansion};
+use layers::{ConfigLayers, Precedence};
+use pool::POOL_EXTENSION;
+use settings::Settings;
+use unit::{UnitKind, UnitName};
+
+use self::runny::{Runny, RunnyError};
+use self::runny::running::Running;
+
 const DEFAULT_TIMEOUT_SECS: u64 = 5;
 
+/// Where state is kept, relative to the first configuration directory,
+/// unless StateDirectory says otherwise.
+const DEFAULT_STATE_DIRECTORY: &str = ".exclave-state";
+
+/// A command that is started with extra variables in its environment.  It
+/// can be set up like the Runny it wraps.
+pub struct EnvironmentCommand {
+    runny: Runny,
+    #[cfg(windows)]
+    vars: Vec<(String, String)>,
+}
+
+/// Build a command that runs `cmd` with the given variables added to its
+/// environment.  Runny has no way to pass environment variables.  On Unix,
+/// the command is started through `env`, so that our own environment is
+/// left alone.
+#[cfg(unix)]
+pub fn command_with_environment(cmd: &str, vars: &[(String, String)]) -> EnvironmentCommand {
+    if vars.is_empty() {
+        return EnvironmentCommand { runny: Runny::new(cmd) };
+    }
+    let mut words = vec!["env".to_owned(), "--".to_owned()];
+    for &(ref key, ref value) in vars {
+        words.push(runny_quote(&format!("{}={}", key, value)));
+    }
+    words.push(cmd.to_owned());
+    EnvironmentCommand { runny: Runny::new(&words.join(" ")) }
+}
+
+/// Build a command that runs `cmd` with the given variables added to its
+/// environment.  Windows has no `env`, so the variables are set in our own
+/// environment while the command starts.
+#[cfg(windows)]
+pub fn command_with_environment(cmd: &str, vars: &[(String, String)]) -> EnvironmentCommand {
+    EnvironmentCommand {
+        runny: Runny::new(cmd),
+        vars: vars.to_vec(),
+    }
+}
+
+/// Quote `s` as a single word for Runny, which doubles every backslash before
+/// splitting the command line like a shell would.  Backslashes therefore stay
+/// as they are, and single quotes are wrapped in double quotes.
+#[cfg(unix)]
+fn runny_quote(s: &str) -> String {
+    format!("'{}'", s.replace("'", "'\"'\"'"))
+}
+
+impl EnvironmentCommand {
+    #[cfg(unix)]
+    pub fn start(&self) -> Result<Running, RunnyError> {
+        self.runny.start()
+    }
+
+    /// Start the command with its variables set in our environment, and put
+    /// the environment back afterwards.  Only one command at a time may do
+    /// this, so that they don't see each other's variables.
+    #[cfg(windows)]
+    pub fn start(&self) -> Result<Running, RunnyError> {
+        use std::sync::Mutex;
+
+        static STARTING: Mutex<()> = Mutex::new(());
+        let _starting = STARTING.lock().unwrap_or_else(|e| e.into_inner());
+
+        let saved: Vec<_> = self.vars.iter().map(|&(ref key, _)| (key, env::var_os(key))).collect();
+        for &(ref key, ref value) in &self.vars {
+            env::set_var(key, value);
+        }
+        let result = self.runny.start();
+        for (key, value) in saved {
+            match value {
+                Some(value) => env::set_var(key, value),
+                None => env::remove_var(key),
+            }
+        }
+        result
+    }
+}
+
+impl Deref for EnvironmentCommand {
+    type Target = Runny;
+    fn deref(&self) -> &Runny {
+        &self.runny
+    }
+}
+
+impl DerefMut for EnvironmentCommand {
+    fn deref_mut(&mut self) -> &mut Runny {
+        &mut self.runny
+    }
+}
+
 pub struct Config {
+    /// Timeout for tests without a Timeout of their own.  If None, such tests may run forever.
+    test_timeout: Option<Duration>,
     timeout: Duration,
     global_working_directory: PathBuf,
     jig_working_directory: Rc<RefCell<Option<PathBuf>>>,
     scenario_working_directory: Rc<RefCell<Option<PathBuf>>>,
-    paths: Vec<PathBuf>,
+
+    /// Directories to search for programs in.  If None, programs are found
+    /// using exclave's own PATH.
+    paths: Option<Vec<PathBuf>>,
     terminate_timeout: Duration,
+
+    /// Identifies this test station, and is passed to programs as EXCLAVE_STATION_ID.
+    station_id: Option<String>,
+
+    /// Addresses for control servers to listen on, passed to programs as EXCLAVE_LISTEN.
+    listen: Vec<String>,
+
+    /// Where to find sysfs when matching jigs.
+    sysfs_root: PathBuf,
+
+    /// Where to keep state that must survive a restart.
+    state_directory: PathBuf,
+
+    /// Whether scenarios may only be started once an operator has logged in.
+    require_login: bool,
+
+    /// File listing the operators who may log in.  If None, anyone may.
+    operator_roster: Option<PathBuf>,
+
+    /// Directory to store per-run artifacts in, if any.
+    artifact_directory: Option<PathBuf>,
+
+    /// Number of completed runs to keep in the artifact directory.
+    /// If None, runs are never removed.
+    artifact_retention: Option<usize>,
+
+    /// Variables that may be used in unit files, in addition to the environment.
+    defines: Defines,
+
+    /// The configuration directories, used to find the directory a unit came from.
+    layers: ConfigLayers,
+
+    /// The only jig that may be chosen, if one was given on the command line.
+    pinned_jig: Option<UnitName>,
+
+    /// The jig and scenario that were most recently activated, for %j and %s.
+    jig: Rc<RefCell<Option<UnitName>>>,
+    scenario: Rc<RefCell<Option<UnitName>>>,
 }
 
 impl Config {
     pub fn new() -> Config {
         Config {
+            test_timeout: None,
             timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
             terminate_timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
             global_working_directory: env::current_dir().expect("Couldn't get current working directory"),
             jig_working_directory: Rc::new(RefCell::new(None)),
             scenario_working_directory: Rc::new(RefCell::new(None)),
-            paths: vec![
-                Path::new("/usr/local/sbin").to_owned(),
-                Path::new("/usr/local/bin").to_owned(),
-                Path::new("/usr/sbin").to_owned(),
-                Path::new("/usr/bin").to_owned(),
-                Path::new("/sbin:/bin").to_owned(),
-            ],
+            paths: None,
+            station_id: None,
+            listen: vec![],
+            sysfs_root: PathBuf::from(DEFAULT_SYSFS_ROOT),
+            state_directory: PathBuf::from(DEFAULT_STATE_DIRECTORY),
+            require_login: false,
+            operator_roster: None,
+            artifact_directory: None,
+            artifact_retention: None,
+            defines: Defines::new(),
+            layers: ConfigLayers::new(Precedence::LastWins),
+            pinned_jig: None,
+            jig: Rc::new(RefCell::new(None)),
+            scenario: Rc::new(RefCell::new(None)),
         }
     }
 
+    /// Take on new global settings.  Anything left unset goes back to its default.
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        self.test_timeout = settings.test_timeout;
+        self.timeout = settings
+            .test_timeout
+            .unwrap_or_else(|| Duration::from_secs(DEFAULT_TIMEOUT_SECS));
+        self.terminate_timeout = settings
+            .terminate_timeout
+            .unwrap_or_else(|| Duration::from_secs(DEFAULT_TIMEOUT_SECS));
+        self.paths = settings.paths.clone();
+        self.station_id = settings.station_id.clone();
+        self.listen = settings.listen.clone().unwrap_or_default();
+        self.sysfs_root = settings
+            .sysfs_root
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_SYSFS_ROOT));
+        self.state_directory = settings
+            .state_directory
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_STATE_DIRECTORY));
+        self.require_login = settings.require_login.unwrap_or(false);
+        self.operator_roster = settings.operator_roster.clone();
+        self.artifact_directory = settings.artifact_directory.clone();
+        self.artifact_retention = settings.keep_runs;
+    }
+
+    /// The timeout for short-lived programs, such as a jig's TestProgram.
     pub fn timeout(&self) -> &Duration {
         &self.timeout
     }
 
+    pub fn sysfs_root(&self) -> &PathBuf {
+        &self.sysfs_root
+    }
+
+    /// Where to keep state that must survive a restart.  A relative path is
+    /// relative to the first configuration directory.
+    pub fn state_directory(&self) -> PathBuf {
+        self.settings_path(&self.state_directory)
+    }
+
+    /// Whether scenarios may only be started once an operator has logged in.
+    pub fn require_login(&self) -> bool {
+        self.require_login
+    }
+
+    /// The file listing who may log in, if any, resolved the same way as
+    /// the state directory.
+    pub fn operator_roster(&self) -> Option<PathBuf> {
+        self.operator_roster.as_ref().map(|path| self.settings_path(path))
+    }
+
+    /// Resolve a path given in the settings against the first configuration
+    /// directory.
+    fn settings_path(&self, path: &Path) -> PathBuf {
+        match self.layers.dirs().first() {
+            Some(dir) => dir.join(path),
+            None => self.global_working_directory.join(path),
+        }
+    }
+
+    /// The timeout for tests that don't specify their own.
+    pub fn test_timeout(&self) -> &Option<Duration> {
+        &self.test_timeout
+    }
+
     pub fn terminate_timeout(&self) -> &Duration {
         &self.terminate_timeout
     }
@@ -54,8 +272,31 @@ impl Config {
         }
     }
 
-    pub fn paths(&self) -> &Vec<PathBuf> {
-        &self.paths
+    /// Variables to set for every program that exclave runs.
+    pub fn environment(&self) -> Vec<(String, String)> {
+        let mut vars = vec![];
+        if let Some(ref paths) = self.paths {
+            if let Ok(path) = env::join_paths(paths) {
+                vars.push(("PATH".to_owned(), path.to_string_lossy().into_owned()));
+            }
+        }
+        if let Some(ref station_id) = self.station_id {
+            vars.push(("EXCLAVE_STATION_ID".to_owned(), station_id.clone()));
+        }
+        if !self.listen.is_empty() {
+            vars.push(("EXCLAVE_LISTEN".to_owned(), self.listen.join(" ")));
+        }
+        vars
+    }
+
+    /// Where to store the output of each run, if anywhere, resolved the
+    /// same way as the state directory.
+    pub fn artifact_directory(&self) -> Option<PathBuf> {
+        self.artifact_directory.as_ref().map(|path| self.settings_path(path))
+    }
+
+    pub fn artifact_retention(&self) -> &Option<usize> {
+        &self.artifact_retention
     }
 
     pub fn set_jig_working_directory(&self, new_buf: &Option<PathBuf>) {
@@ -65,4 +306,44 @@ impl Config {
     pub fn set_scenario_working_directory(&self, new_buf: &Option<PathBuf>) {
         *self.scenario_working_directory.borrow_mut() = new_buf.clone();
     }
+
+    pub fn set_defines(&mut self, defines: Defines) {
+        self.defines = defines;
+    }
+
+    pub fn set_layers(&mut self, layers: ConfigLayers) {
+        self.layers = layers;
+    }
+
+    pub fn pinned_jig(&self) -> &Option<UnitName> {
+        &self.pinned_jig
+    }
+
+    pub fn set_pinned_jig(&mut self, jig: Option<UnitName>) {
+        self.pinned_jig = jig;
+    }
+
+    pub fn set_jig(&self, jig: &UnitName) {
+        *self.jig.borrow_mut() = Some(jig.clone());
+    }
+
+    pub fn set_scenario(&self, scenario: &UnitName) {
+        *self.scenario.borrow_mut() = Some(scenario.clone());
+    }
+
+    /// The file that defines the named pool.
+    pub fn pool_path(&self, pool: &str) -> Option<PathBuf> {
+        self.layers.effective_file(&format!("{}.{}", pool, POOL_EXTENSION))
+    }
+
+    /// Prepare to expand variables and specifiers in the given unit's values.
+    /// A jig or scenario refers to itself with %j or %s.
+    pub fn expansion(&self, unit: &UnitName) -> Expansion {
+        let config_dir = self.layers.effective(unit).and_then(|path| path.parent().map(|p| p.to_owned()));
+        let jig = if unit.kind() == &UnitKind::Jig { Some(unit.clone()) } else { self.jig.borrow().clone() };
+        let scenario = if unit.kind() == &UnitKind::Scenario { Some(unit.clone()) } else { self.scenario.borrow().clone() };
+        Expansion::new(unit, config_dir, &self.defines)
+            .with_jig(jig.as_ref())
+            .with_scenario(scenario.as_ref())
+    }
 }
@@ -0,0 +1,288 @@
+// Besides TestFile and TestProgram, a jig may recognize the machine it's
+// running on by matching facts about it:
+//
+//   MatchHostname=GLOB...        the machine's hostname
+//   MatchEnvironment=NAME=GLOB...  an environment variable (NAME alone means it's set)
+//   MatchFileContent=PATH REGEX  the contents of a file
+//   MatchUsb=VID:PID...          a USB device that's plugged in
+//   MatchDmiProduct=GLOB...      the DMI product name, e.g. the model of a PC
+//
+// Each directive that takes a list passes if any value in the list matches,
+// and a directive may be given more than once.  As with systemd's
+// conditions, a value that starts with "|" makes the directive one of a
+// group, of which at least one must pass, while every other directive must
+// pass on its own.  "MatchMode=any" puts every directive in the group.  USB
+// devices and the DMI product name are read from sysfs, whose location may be
+// changed with the SysfsRoot setting.
+extern crate regex;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use self::regex::Regex;
+
+use unit::{UnitDescriptionError, UnitIncompatibleReason};
+
+/// Where sysfs is normally found.
+pub const DEFAULT_SYSFS_ROOT: &str = "/sys";
+
+#[derive(Clone, Debug)]
+enum Matcher {
+    Hostname(Vec<String>),
+    Environment(Vec<(String, String)>),
+    FileContent(PathBuf, Regex),
+    Usb(Vec<(u16, u16)>),
+    DmiProduct(Vec<String>),
+}
+
+/// Whether a shell-style glob, with "*", "?" and "[...]", matches all of `text`.
+pub fn glob_matches(glob: &str, text: &str) -> bool {
+    let mut pattern = "^".to_owned();
+    let mut in_class = false;
+    let mut class_start = false;
+    for c in glob.chars() {
+        let starting = class_start;
+        class_start = false;
+        match c {
+            '*' if !in_class => pattern.push_str(".*"),
+            '?' if !in_class => pattern.push('.'),
+            '[' if !in_class => {
+                in_class = true;
+                class_start = true;
+                pattern.push('[');
+            }
+            '!' if starting => pattern.push('^'),
+            ']' if in_class => {
+                in_class = false;
+                pattern.push(']');
+            }
+            '\\' | '^' | '[' if in_class => {
+                pattern.push('\\');
+                pattern.push(c);
+            }
+            c if in_class => pattern.push(c),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    if in_class {
+        return false;
+    }
+    pattern.push('$');
+    Regex::new(&pattern).map(|r| r.is_match(text)).unwrap_or(false)
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    let mut contents = String::new();
+    File::open(path).ok()?.read_to_string(&mut contents).ok()?;
+    Some(contents.trim().to_owned())
+}
+
+/// Linux keeps the hostname in /proc.  Elsewhere, the `hostname` command
+/// prints it on Unix and Windows alike, and the environment is a last resort.
+fn hostname() -> Option<String> {
+    read_trimmed(Path::new("/proc/sys/kernel/hostname"))
+        .or_else(|| {
+            Command::new("hostname")
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+                .map(|name| name.trim().to_owned())
+        })
+        .or_else(|| env::var("COMPUTERNAME").ok())
+        .or_else(|| env::var("HOSTNAME").ok())
+        .filter(|name| !name.is_empty())
+}
+
+/// The VID:PID of every USB device listed under `sysfs_root`.
+pub fn usb_devices(sysfs_root: &Path) -> Vec<(u16, u16)> {
+    let entries = match fs::read_dir(sysfs_root.join("bus").join("usb").join("devices")) {
+        Ok(e) => e,
+        Err(_) => return vec![],
+    };
+    let mut devices = vec![];
+    for entry in entries.filter_map(|e| e.ok()) {
+        let id = |name: &str| read_trimmed(&entry.path().join(name)).and_then(|s| u16::from_str_radix(&s, 16).ok());
+        if let (Some(vid), Some(pid)) = (id("idVendor"), id("idProduct")) {
+            devices.push((vid, pid));
+        }
+    }
+    devices
+}
+
+fn parse_usb_id(id: &str) -> Option<(u16, u16)> {
+    let pos = id.find(':')?;
+    let vid = u16::from_str_radix(&id[..pos], 16).ok()?;
+    let pid = u16::from_str_radix(&id[pos + 1..], 16).ok()?;
+    Some((vid, pid))
+}
+
+impl Matcher {
+    /// Matchers are checked in this order, cheapest first, no matter what
+    /// order they're given in.
+    fn order(&self) -> u8 {
+        match *self {
+            Matcher::Hostname(_) => 0,
+            Matcher::Environment(_) => 1,
+            Matcher::DmiProduct(_) => 2,
+            Matcher::FileContent(_, _) => 3,
+            Matcher::Usb(_) => 4,
+        }
+    }
+
+    /// Ok if the machine matches, or else a description of why it doesn't.
+    fn check(&self, sysfs_root: &Path) -> Result<(), String> {
+        match self {
+            Matcher::Hostname(globs) => {
+                let name = hostname().unwrap_or_default();
+                if globs.iter().any(|g| glob_matches(g, &name)) {
+                    Ok(())
+                } else {
+                    Err(format!("hostname {} does not match {}", name, globs.join(" ")))
+                }
+            }
+            Matcher::Environment(vars) => {
+                for (name, glob) in vars {
+                    if let Ok(value) = env::var(name) {
+                        if glob_matches(glob, &value) {
+                            return Ok(());
+                        }
+                    }
+                }
+                let wanted: Vec<String> = vars.iter().map(|(n, g)| format!("{}={}", n, g)).collect();
+                Err(format!("environment does not have {}", wanted.join(" or ")))
+            }
+            Matcher::FileContent(path, regex) => match read_trimmed(path) {
+                None => Err(format!("unable to read {}", path.display())),
+                Some(ref contents) if regex.is_match(contents) => Ok(()),
+                Some(_) => Err(format!("contents of {} do not match {}", path.display(), regex)),
+            },
+            Matcher::Usb(ids) => {
+                let present = usb_devices(sysfs_root);
+                if ids.iter().any(|id| present.contains(id)) {
+                    Ok(())
+                } else {
+                    let wanted: Vec<String> = ids.iter().map(|&(v, p)| format!("{:04x}:{:04x}", v, p)).collect();
+                    Err(format!("no USB device {} under {}", wanted.join(" or "), sysfs_root.display()))
+                }
+            }
+            Matcher::DmiProduct(globs) => {
+                let path = sysfs_root.join("class").join("dmi").join("id").join("product_name");
+                match read_trimmed(&path) {
+                    None => Err(format!("unable to read DMI product name from {}", path.display())),
+                    Some(ref product) if globs.iter().any(|g| glob_matches(g, product)) => Ok(()),
+                    Some(product) => Err(format!("DMI product {} does not match {}", product, globs.join(" "))),
+                }
+            }
+        }
+    }
+}
+
+/// The matchers given in a jig's unit file.
+#[derive(Clone, Debug, Default)]
+pub struct JigMatchers {
+    /// Each matcher, along with whether it's part of the "|" group.
+    matchers: Vec<(Matcher, bool)>,
+
+    /// If true, every matcher is part of the group.
+    any: bool,
+}
+
+impl JigMatchers {
+    pub fn new() -> JigMatchers {
+        Default::default()
+    }
+
+    /// Add the matcher described by a directive from the [Jig] section.
+    /// An empty value adds nothing.
+    pub fn parse(&mut self, key: &str, value: &str) -> Result<(), UnitDescriptionError> {
+        let value = value.trim();
+        let (grouped, value) = if key != "MatchMode" && value.starts_with('|') {
+            (true, value[1..].trim())
+        } else {
+            (false, value)
+        };
+        if value.is_empty() {
+            return Ok(());
+        }
+        let invalid = |value: &str, allowed: &str| {
+            UnitDescriptionError::InvalidValue("Jig".to_owned(), key.to_owned(), value.to_owned(), vec![allowed.to_owned()])
+        };
+        let list = || value.split_whitespace().map(|s| s.to_owned()).collect::<Vec<String>>();
+        let matcher = match key {
+            "MatchHostname" => Matcher::Hostname(list()),
+            "MatchDmiProduct" => Matcher::DmiProduct(list()),
+            "MatchEnvironment" => Matcher::Environment(
+                list()
+                    .into_iter()
+                    .map(|var| match var.find('=') {
+                        Some(pos) => (var[..pos].to_owned(), var[pos + 1..].to_owned()),
+                        None => (var, "*".to_owned()),
+                    })
+                    .collect(),
+            ),
+            "MatchUsb" => {
+                let mut ids = vec![];
+                for id in list() {
+                    ids.push(parse_usb_id(&id).ok_or_else(|| invalid(&id, "VID:PID"))?);
+                }
+                Matcher::Usb(ids)
+            }
+            "MatchFileContent" => {
+                let value = value.trim();
+                let pos = value.find(char::is_whitespace).ok_or_else(|| invalid(value, "PATH REGEX"))?;
+                Matcher::FileContent(PathBuf::from(&value[..pos]), Regex::new(value[pos..].trim())?)
+            }
+            "MatchMode" => {
+                self.any = match value.to_lowercase().as_ref() {
+                    "all" => false,
+                    "any" => true,
+                    other => {
+                        return Err(UnitDescriptionError::InvalidValue(
+                            "Jig".to_owned(),
+                            key.to_owned(),
+                            other.to_owned(),
+                            vec!["all".to_owned(), "any".to_owned()],
+                        ))
+                    }
+                };
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        let pos = self.matchers
+            .iter()
+            .position(|m| m.0.order() > matcher.order())
+            .unwrap_or(self.matchers.len());
+        self.matchers.insert(pos, (matcher, grouped));
+        Ok(())
+    }
+
+    /// Check the matchers against this machine.  Every matcher outside the
+    /// group must pass, and if there is a group, one of its matchers must
+    /// pass too.  A jig without any matchers always matches.
+    pub fn check(&self, sysfs_root: &Path) -> Result<(), UnitIncompatibleReason> {
+        let mut group_failures = vec![];
+        let mut group_passed = false;
+        for &(ref matcher, grouped) in &self.matchers {
+            let grouped = grouped || self.any;
+            if grouped && group_passed {
+                continue;
+            }
+            match matcher.check(sysfs_root) {
+                Ok(()) if grouped => group_passed = true,
+                Ok(()) => (),
+                Err(reason) if grouped => group_failures.push(reason),
+                Err(reason) => return Err(UnitIncompatibleReason::MatchFailed(reason)),
+            }
+        }
+        if group_passed || group_failures.is_empty() {
+            Ok(())
+        } else {
+            Err(UnitIncompatibleReason::MatchFailed(group_failures.join(", and ")))
+        }
+    }
+}
@@ -0,0 +1,170 @@
+// Drop-in fragments let a unit be adjusted without editing the unit file
+// itself.  Any "*.conf" files in a directory named after the unit with ".d"
+// appended (e.g. "foo.test.d/") are merged over the unit in lexical order.
+// A directive in a fragment replaces the same directive in the unit, except
+// for lists, which are appended to.  Giving a list an empty value clears it,
+// so that a fragment can replace a list entirely.
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Directives whose values are lists, and are appended to rather than replaced.
+pub const LIST_KEYS: &[&str] = &[
+    "Jigs",
+    "Requires",
+    "Suggests",
+    "Provides",
+    "After",
+    "Before",
+    "Conflicts",
+    "Tests",
+    "Assume",
+    "Prefer",
+];
+
+/// The extension that fragment files must have.
+pub const FRAGMENT_EXTENSION: &str = "conf";
+
+enum Entry {
+    Directive(String, String),
+
+    /// A line that isn't a directive, which is passed through so that the
+    /// unit parser can report it.
+    Other(String),
+}
+
+/// The directory that holds drop-in fragments for the given unit file.
+pub fn dropin_dir(unit_path: &Path) -> PathBuf {
+    let mut dir = unit_path.as_os_str().to_owned();
+    dir.push(".d");
+    PathBuf::from(dir)
+}
+
+/// Fragments for the given unit file, in the order they are applied.
+pub fn fragments(unit_path: &Path) -> Vec<PathBuf> {
+    let entries = match dropin_dir(unit_path).read_dir() {
+        Ok(e) => e,
+        Err(_) => return vec![],
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && p.extension().map(|e| e == FRAGMENT_EXTENSION).unwrap_or(false))
+        .collect();
+    paths.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    paths
+}
+
+/// If `path` is a drop-in directory or a fragment within one, return the
+/// path of the unit file it applies to.
+pub fn parent_unit(path: &Path) -> Option<PathBuf> {
+    let dir = if path.extension().map(|e| e == "d").unwrap_or(false) {
+        path
+    } else if path.extension().map(|e| e == FRAGMENT_EXTENSION).unwrap_or(false) {
+        path.parent()?
+    } else {
+        return None;
+    };
+    if dir.extension().map(|e| e != "d").unwrap_or(true) {
+        return None;
+    }
+    Some(dir.with_extension(""))
+}
+
+/// Read a unit file and merge any drop-in fragments over it.  If there
+/// are no fragments, the unit file is returned unchanged.
+pub fn read_unit(path: &Path) -> io::Result<String> {
+    let mut contents = String::with_capacity(8192);
+    File::open(path)?.read_to_string(&mut contents)?;
+
+    let fragment_paths = fragments(path);
+    if fragment_paths.is_empty() {
+        return Ok(contents);
+    }
+
+    let mut fragment_contents = vec![];
+    for fragment_path in fragment_paths {
+        let mut fragment = String::new();
+        File::open(&fragment_path)?.read_to_string(&mut fragment)?;
+        fragment_contents.push(fragment);
+    }
+    Ok(merge(&contents, &fragment_contents))
+}
+
+/// Merge fragments over the contents of a unit file, returning the
+/// combined unit file.
+pub fn merge(base: &str, fragments: &[String]) -> String {
+    let files: Vec<&str> = Some(base).into_iter().chain(fragments.iter().map(|f| f.as_str())).collect();
+    combine(&files, true)
+}
+
+/// Lay the contents of a unit file over the unit it inherits from.  Unlike
+/// fragments, every directive in the unit replaces the one in its base,
+/// including lists.
+pub fn overlay(base: &str, unit: &str) -> String {
+    combine(&[base, unit], false)
+}
+
+fn combine(files: &[&str], append_lists: bool) -> String {
+    let mut sections: Vec<(String, Vec<Entry>)> = vec![];
+    for contents in files {
+        // Each file starts outside of any section.
+        let mut section = String::new();
+        for raw_line in contents.lines() {
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].trim().to_owned();
+                continue;
+            }
+
+            let idx = match sections.iter().position(|(name, _)| name == &section) {
+                Some(i) => i,
+                None => {
+                    sections.push((section.clone(), vec![]));
+                    sections.len() - 1
+                }
+            };
+            let entries = &mut sections[idx].1;
+
+            let (key, value) = match line.find('=') {
+                Some(pos) => (line[..pos].trim(), line[pos + 1..].trim()),
+                None => {
+                    entries.push(Entry::Other(line.to_owned()));
+                    continue;
+                }
+            };
+            let existing = entries.iter_mut().find(|e| match *e {
+                &mut Entry::Directive(ref k, _) => k == key,
+                _ => false,
+            });
+            match existing {
+                Some(&mut Entry::Directive(_, ref mut old)) => {
+                    if append_lists && LIST_KEYS.contains(&key) && !value.is_empty() && !old.is_empty() {
+                        old.push_str(", ");
+                        old.push_str(value);
+                    } else {
+                        *old = value.to_owned();
+                    }
+                }
+                _ => entries.push(Entry::Directive(key.to_owned(), value.to_owned())),
+            }
+        }
+    }
+
+    let mut merged = String::new();
+    for (name, entries) in sections {
+        if !name.is_empty() {
+            merged.push_str(&format!("[{}]\n", name));
+        }
+        for entry in entries {
+            match entry {
+                Entry::Directive(key, value) => merged.push_str(&format!("{}={}\n", key, value)),
+                Entry::Other(line) => merged.push_str(&format!("{}\n", line)),
+            }
+        }
+    }
+    merged
+}
@@ -0,0 +1,204 @@
+// Values in unit files, such as ExecStart and WorkingDirectory, can refer to
+// variables and specifiers, which are expanded just before the value is used:
+//
+//   ${NAME}  NAME from the environment, or failing that, from the defines file
+//   %n       the name of the unit, without its suffix
+//   %j       the name of the current jig
+//   %s       the name of the current scenario
+//   %d       the configuration directory that the unit was loaded from
+//   %%       a single "%"
+//
+// Any other "%" is left alone, so that commands such as "date +%Y" continue
+// to work, and "$${" may be used to write a literal "${".
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use unit::UnitName;
+
+/// Directives whose values are expanded.
+pub const EXPANDED_KEYS: &[&str] = &[
+    "ExecStart",
+    "ExecStop",
+    "ExecReload",
+    "ExecStopSuccess",
+    "ExecStopFail",
+    "ExecStopFailure",
+    "TestProgram",
+    "TestFile",
+    "WorkingDirectory",
+    "DefaultWorkingDirectory",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExpandError {
+    /// ${NAME} is in neither the environment nor the defines file.
+    UndefinedVariable(String),
+
+    /// A "${" with no closing "}".
+    UnterminatedVariable(String /* the text after "${" */),
+
+    /// A specifier refers to something that isn't known, such as %s when
+    /// no scenario is running.
+    MissingSpecifier(char),
+}
+
+impl fmt::Display for ExpandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExpandError::UndefinedVariable(name) => {
+                write!(f, "${{{}}} is not set in the environment or the defines file", name)
+            }
+            ExpandError::UnterminatedVariable(rest) => write!(f, "\"${{{}\" is missing a closing \"}}\"", rest),
+            ExpandError::MissingSpecifier('j') => write!(f, "%j refers to the current jig, but there isn't one"),
+            ExpandError::MissingSpecifier('s') => write!(f, "%s refers to the current scenario, but there isn't one"),
+            ExpandError::MissingSpecifier('d') => {
+                write!(f, "%d refers to the unit's configuration directory, but it isn't in one")
+            }
+            ExpandError::MissingSpecifier(c) => write!(f, "%{} has no value", c),
+        }
+    }
+}
+
+/// Variables read from a defines file, which has one NAME=VALUE per line.
+/// Blank lines and lines starting with "#" are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct Defines {
+    values: HashMap<String, String>,
+}
+
+impl Defines {
+    pub fn new() -> Defines {
+        Defines { values: HashMap::new() }
+    }
+
+    pub fn from_path(path: &Path) -> io::Result<Defines> {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+        Self::from_string(&contents)
+    }
+
+    pub fn from_string(contents: &str) -> io::Result<Defines> {
+        let mut defines = Defines::new();
+        for (idx, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.find('=') {
+                Some(pos) if pos > 0 => {
+                    defines
+                        .values
+                        .insert(line[..pos].trim().to_owned(), line[pos + 1..].trim().to_owned());
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("line {} is not of the form NAME=VALUE", idx + 1),
+                    ))
+                }
+            }
+        }
+        Ok(defines)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.values.get(name)
+    }
+}
+
+/// Everything needed to expand the values of one unit.
+#[derive(Debug, Clone)]
+pub struct Expansion {
+    unit: UnitName,
+    jig: Option<UnitName>,
+    scenario: Option<UnitName>,
+    config_dir: Option<PathBuf>,
+    defines: Defines,
+}
+
+impl Expansion {
+    pub fn new(unit: &UnitName, config_dir: Option<PathBuf>, defines: &Defines) -> Expansion {
+        Expansion {
+            unit: unit.clone(),
+            jig: None,
+            scenario: None,
+            config_dir,
+            defines: defines.clone(),
+        }
+    }
+
+    pub fn with_jig(mut self, jig: Option<&UnitName>) -> Expansion {
+        self.jig = jig.cloned();
+        self
+    }
+
+    pub fn with_scenario(mut self, scenario: Option<&UnitName>) -> Expansion {
+        self.scenario = scenario.cloned();
+        self
+    }
+
+    fn variable(&self, name: &str) -> Option<String> {
+        env::var(name).ok().or_else(|| self.defines.get(name).cloned())
+    }
+
+    fn specifier(&self, c: char) -> Option<Result<String, ExpandError>> {
+        let value = match c {
+            'n' => Some(self.unit.id().clone()),
+            'j' => self.jig.as_ref().map(|j| j.id().clone()),
+            's' => self.scenario.as_ref().map(|s| s.id().clone()),
+            'd' => self.config_dir.as_ref().map(|d| d.to_string_lossy().into_owned()),
+            '%' => Some("%".to_owned()),
+            _ => return None,
+        };
+        Some(value.ok_or(ExpandError::MissingSpecifier(c)))
+    }
+
+    pub fn expand(&self, value: &str) -> Result<String, ExpandError> {
+        let mut expanded = String::with_capacity(value.len());
+        let mut rest = value;
+        while let Some(pos) = rest.find(&['$', '%'][..]) {
+            expanded.push_str(&rest[..pos]);
+            rest = &rest[pos..];
+
+            if rest.starts_with("$${") {
+                expanded.push_str("${");
+                rest = &rest[3..];
+            } else if rest.starts_with("${") {
+                let end = match rest.find('}') {
+                    Some(e) => e,
+                    None => return Err(ExpandError::UnterminatedVariable(rest[2..].to_owned())),
+                };
+                let name = &rest[2..end];
+                match self.variable(name) {
+                    Some(v) => expanded.push_str(&v),
+                    None => return Err(ExpandError::UndefinedVariable(name.to_owned())),
+                }
+                rest = &rest[end + 1..];
+            } else if rest.starts_with('%') {
+                match rest[1..].chars().next().and_then(|c| self.specifier(c)) {
+                    Some(result) => {
+                        expanded.push_str(&result?);
+                        rest = &rest[2..];
+                    }
+                    None => {
+                        expanded.push('%');
+                        rest = &rest[1..];
+                    }
+                }
+            } else {
+                expanded.push('$');
+                rest = &rest[1..];
+            }
+        }
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
+
+    pub fn expand_path(&self, path: &Path) -> Result<PathBuf, ExpandError> {
+        Ok(PathBuf::from(self.expand(&path.to_string_lossy())?))
+    }
+}
@@ -0,0 +1,100 @@
+// Every scenario run is recorded in the state directory, along with the
+// serial number of the device it tested, if one was declared.  This lets
+// a repair station see how a board has fared before as soon as its serial
+// is scanned.  Records are appended one JSON object per line, so that
+// adding a run never rewrites the runs before it.
+//
+//   <state-dir>/history.jsonl
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use serde_json;
+
+/// Name of the history file within the state directory.
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+/// Tests find the serial in this environment variable, and can declare it
+/// by printing a line of the form EXCLAVE_SERIAL=VALUE.
+pub const SERIAL_VARIABLE: &str = "EXCLAVE_SERIAL";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RunRecord {
+    pub run_id: String,
+
+    /// Serial number of the device under test, if it was declared
+    pub serial: Option<String>,
+
+    /// The operator who ran it, if one was logged in
+    #[serde(default)]
+    pub operator: Option<String>,
+
+    pub scenario: String,
+    pub jig: Option<String>,
+
+    /// Seconds since the epoch when the run finished
+    pub finished: u64,
+
+    /// The scenario's result code, and the reason it finished
+    pub result: u32,
+    pub message: String,
+}
+
+impl RunRecord {
+    pub fn passed(&self) -> bool {
+        self.result == 200
+    }
+}
+
+/// Add a finished run to the history in `state_dir`.
+pub fn append(state_dir: &Path, record: &RunRecord) -> io::Result<()> {
+    fs::create_dir_all(state_dir)?;
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(state_dir.join(HISTORY_FILE))?;
+    file.write_all(line.as_bytes())?;
+    file.sync_data()
+}
+
+/// Every run of the device with serial number `serial`, oldest first.
+pub fn for_serial(state_dir: &Path, serial: &str) -> io::Result<Vec<RunRecord>> {
+    let file = match File::open(state_dir.join(HISTORY_FILE)) {
+        Ok(f) => f,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let mut runs = vec![];
+    for line in BufReader::new(file).lines() {
+        // A line cut short by a crash won't parse, and is skipped.
+        let record: RunRecord = match serde_json::from_str(&line?) {
+            Ok(r) => r,
+            Err(_) => continue,
+        };
+        if record.serial.as_ref().map(|s| s == serial).unwrap_or(false) {
+            runs.push(record);
+        }
+    }
+    Ok(runs)
+}
+
+/// A one-line account of `runs`, to follow "SERIAL has", such as "failed 2
+/// of 3 runs, most recently run 1500000000-000000000 (at least one test
+/// failed)".
+pub fn summary(runs: &[RunRecord]) -> String {
+    let failures: Vec<&RunRecord> = runs.iter().filter(|r| !r.passed()).collect();
+    match failures.last() {
+        None if runs.is_empty() => "no recorded runs".to_owned(),
+        None if runs.len() == 1 => "passed its only run".to_owned(),
+        None => format!("passed all {} runs", runs.len()),
+        Some(last) => format!(
+            "failed {} of {} runs, most recently run {} ({})",
+            failures.len(),
+            runs.len(),
+            last.run_id,
+            last.message
+        ),
+    }
+}
@@ -0,0 +1,116 @@
+// A unit may take its defaults from another unit of the same kind by naming
+// it in Inherit=, so that directives such as Jigs, Timeout and
+// WorkingDirectory can be shared between many units.  Every directive in the
+// unit replaces the same directive in its base, including lists.  A base may
+// itself inherit from another unit, as long as this doesn't form a loop.
+// The base is looked for in the same directory as the unit that names it,
+// and drop-in fragments are applied to each unit before it is inherited from.
+use std::path::Path;
+
+use dropin;
+use unit::{UnitDescriptionError, UnitName};
+
+/// The directive that names a unit's base.
+pub const INHERIT_KEY: &str = "Inherit";
+
+/// A unit and the units it inherits from, nearest first.
+struct Chain {
+    /// Each unit's name and contents, with drop-in fragments applied
+    units: Vec<(UnitName, String)>,
+
+    /// The reason the chain couldn't be followed to the end, if it couldn't
+    broken: Option<UnitDescriptionError>,
+}
+
+/// The value of the last Inherit= in a unit, if it has one.  An empty value
+/// (e.g. from a drop-in fragment) means the unit inherits from nothing.
+fn inherit_value(contents: &str) -> Option<String> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            let pos = line.find('=')?;
+            if line[..pos].trim() == INHERIT_KEY {
+                Some(line[pos + 1..].trim().to_owned())
+            } else {
+                None
+            }
+        })
+        .next_back()
+        .and_then(|value| if value.is_empty() { None } else { Some(value) })
+}
+
+fn follow(path: &Path) -> Result<Chain, UnitDescriptionError> {
+    let name = UnitName::from_path(path)?;
+    let kind = name.kind().clone();
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut chain = Chain {
+        units: vec![(name, dropin::read_unit(path)?)],
+        broken: None,
+    };
+
+    while let Some(value) = inherit_value(&chain.units.last().unwrap().1) {
+        let base = match UnitName::from_str(&value, &format!("{}", kind)) {
+            Ok(b) => b,
+            Err(e) => {
+                chain.broken = Some(e.into());
+                break;
+            }
+        };
+        if base.kind() != &kind {
+            chain.broken = Some(UnitDescriptionError::InvalidBase(base, kind));
+            break;
+        }
+        if let Some(idx) = chain.units.iter().position(|(n, _)| n == &base) {
+            let cycle = chain.units[idx..].iter().map(|(n, _)| n.clone()).collect();
+            chain.broken = Some(UnitDescriptionError::InheritanceCycle(cycle));
+            break;
+        }
+
+        let base_path = dir.join(format!("{}", base));
+        if !base_path.is_file() {
+            chain.broken = Some(UnitDescriptionError::MissingBase(base));
+            break;
+        }
+        match dropin::read_unit(&base_path) {
+            Ok(contents) => chain.units.push((base, contents)),
+            Err(e) => {
+                chain.broken = Some(e.into());
+                break;
+            }
+        }
+    }
+    Ok(chain)
+}
+
+/// Read a unit file, along with its drop-in fragments, and lay it over
+/// every unit it inherits from.  A unit that doesn't inherit from anything
+/// is returned as it was read.
+pub fn read_unit(path: &Path) -> Result<String, UnitDescriptionError> {
+    let chain = follow(path)?;
+    if let Some(e) = chain.broken {
+        return Err(e);
+    }
+
+    let mut units = chain.units.into_iter().rev().map(|(_, contents)| contents);
+    let mut contents = units.next().unwrap();
+    for unit in units {
+        contents = dropin::overlay(&contents, &unit);
+    }
+    Ok(contents)
+}
+
+/// Every unit that the unit at `path` inherits from, nearest first.  This
+/// includes a base that doesn't exist, so that the unit can be reloaded
+/// once it does.
+pub fn bases(path: &Path) -> Vec<UnitName> {
+    let chain = match follow(path) {
+        Ok(c) => c,
+        Err(_) => return vec![],
+    };
+    let mut names: Vec<UnitName> = chain.units.into_iter().skip(1).map(|(name, _)| name).collect();
+    if let Some(UnitDescriptionError::MissingBase(base)) = chain.broken {
+        names.push(base);
+    }
+    names
+}
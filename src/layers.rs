@@ -0,0 +1,155 @@
+// Configuration can be split across several directories, for example one
+// each for vendor, product and station configuration.  When a unit file with
+// the same name exists in more than one directory, the copy in the directory
+// with the highest precedence is used and the others are shadowed.  If that
+// copy is removed, the next one takes its place.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use dropin;
+use unit::UnitName;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Precedence {
+    /// Directories given later override directories given earlier.
+    LastWins,
+
+    /// Directories given earlier override directories given later.
+    FirstWins,
+}
+
+impl Precedence {
+    pub fn from_str(s: &str) -> Option<Precedence> {
+        match s {
+            "last" => Some(Precedence::LastWins),
+            "first" => Some(Precedence::FirstWins),
+            _ => None,
+        }
+    }
+}
+
+/// Where a unit was loaded from.
+#[derive(Debug, Clone)]
+pub struct UnitSource {
+    pub name: UnitName,
+
+    /// The unit file that is used
+    pub path: PathBuf,
+
+    /// Drop-in fragments applied to the unit file
+    pub fragments: Vec<PathBuf>,
+
+    /// Files with the same name in lower-precedence directories
+    pub shadowed: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigLayers {
+    /// Each directory as it was given, along with its canonical path, in the
+    /// order they were given.
+    dirs: Vec<(PathBuf, PathBuf)>,
+    precedence: Precedence,
+}
+
+impl ConfigLayers {
+    pub fn new(precedence: Precedence) -> ConfigLayers {
+        ConfigLayers {
+            dirs: vec![],
+            precedence,
+        }
+    }
+
+    pub fn add_dir(&mut self, dir: &Path) {
+        let canonical = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_owned());
+        self.dirs.push((dir.to_owned(), canonical));
+    }
+
+    pub fn set_precedence(&mut self, precedence: Precedence) {
+        self.precedence = precedence;
+    }
+
+    /// Directories in the order they were given.
+    pub fn dirs(&self) -> Vec<&PathBuf> {
+        self.dirs.iter().map(|(dir, _)| dir).collect()
+    }
+
+    /// Indexes into `dirs`, from highest precedence to lowest.
+    fn ranked(&self) -> Vec<usize> {
+        match self.precedence {
+            Precedence::LastWins => (0..self.dirs.len()).rev().collect(),
+            Precedence::FirstWins => (0..self.dirs.len()).collect(),
+        }
+    }
+
+    /// The rank of the directory that directly contains `path`, where 0 is
+    /// the highest precedence.  Returns None if `path` isn't in any of them.
+    pub fn rank_of(&self, path: &Path) -> Option<usize> {
+        let parent = path.parent()?;
+        let parent = fs::canonicalize(parent).unwrap_or_else(|_| parent.to_owned());
+        self.ranked().iter().position(|&idx| self.dirs[idx].1 == parent || self.dirs[idx].0 == parent)
+    }
+
+    /// Every file that defines the named unit, highest precedence first.
+    pub fn candidates(&self, name: &UnitName) -> Vec<PathBuf> {
+        let file_name = format!("{}", name);
+        self.ranked()
+            .into_iter()
+            .map(|idx| self.dirs[idx].0.join(&file_name))
+            .filter(|path| path.is_file())
+            .collect()
+    }
+
+    /// The highest-precedence copy of a file that isn't a unit, such as a
+    /// pool definition.
+    pub fn effective_file(&self, file_name: &str) -> Option<PathBuf> {
+        self.ranked()
+            .into_iter()
+            .map(|idx| self.dirs[idx].0.join(file_name))
+            .find(|path| path.is_file())
+    }
+
+    /// The file that the named unit should be loaded from, if any.
+    pub fn effective(&self, name: &UnitName) -> Option<PathBuf> {
+        self.candidates(name).into_iter().next()
+    }
+
+    /// Every unit in every directory, sorted by name.
+    pub fn units(&self) -> Vec<UnitSource> {
+        let mut names = vec![];
+        for (dir, _) in &self.dirs {
+            let entries = match dir.read_dir() {
+                Ok(e) => e,
+                Err(_) => continue,
+            };
+            for entry in entries.filter_map(|e| e.ok()) {
+                let path = entry.path();
+                if !path.is_file() {
+                    continue;
+                }
+                if let Ok(name) = UnitName::from_path(&path) {
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+            }
+        }
+        names.sort();
+
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let mut candidates = self.candidates(&name);
+                if candidates.is_empty() {
+                    return None;
+                }
+                let path = candidates.remove(0);
+                Some(UnitSource {
+                    fragments: dropin::fragments(&path),
+                    name,
+                    path,
+                    shadowed: candidates,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,727 @@
+// Offline validation of a configuration directory.  Every unit is parsed
+// without loading it, and the whole set is then checked for problems that
+// would otherwise only show up as a LoadFailed event on a running jig:
+// unknown directives, bad values, references to units that don't exist,
+// dependency cycles, and tests that no scenario will ever run.  Values that
+// contain variables or specifiers are expanded, and reported as notes.
+extern crate humantime;
+extern crate regex;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use self::humantime::parse_duration;
+use self::regex::Regex;
+
+use dropin;
+use expand::{self, Defines, ExpandError, Expansion};
+use inherit;
+use layers::ConfigLayers;
+use plan;
+use pool::{self, PoolDescription};
+use unit::{UnitDescriptionError, UnitKind, UnitName};
+use units::interface::{self, InterfaceDescription};
+use units::jig::{self, JigDescription};
+use units::logger::{self, LoggerDescription};
+use units::scenario::{self, ScenarioDescription};
+use units::test::{self, TestDescription};
+use units::trigger::{self, TriggerDescription};
+use wear::parse_thresholds;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+
+    /// Not a problem, but something that may be useful to know
+    Note,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &str {
+        match *self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}: {}", self.path.display(), line, self.severity.as_str(), self.message),
+            None => write!(f, "{}: {}: {}", self.path.display(), self.severity.as_str(), self.message),
+        }
+    }
+}
+
+/// A single "Key=Value" line from a unit file or one of its drop-in fragments.
+struct Directive {
+    path: PathBuf,
+    line: usize,
+    section: String,
+    key: String,
+    value: String,
+}
+
+/// A unit file as read from disk, before it has been interpreted.
+struct UnitSource {
+    name: UnitName,
+    path: PathBuf,
+    directives: Vec<Directive>,
+}
+
+impl UnitSource {
+    /// The section that holds this unit's settings, e.g. "Test" for a .test file.
+    fn section(&self) -> &'static str {
+        section_name(self.name.kind())
+    }
+
+    /// A problem with the directive `key`, at the place it was set.
+    fn at(&self, key: &str, message: String) -> (PathBuf, Option<usize>, String) {
+        let (path, line) = self.location(key);
+        (path, line, message)
+    }
+
+    /// The file and line of the last occurrence of `key`, which is the one
+    /// that takes effect.  This may be in a drop-in fragment.
+    fn location(&self, key: &str) -> (PathBuf, Option<usize>) {
+        match self.directives.iter().rev().find(|d| d.section == self.section() && d.key == key) {
+            Some(d) => (d.path.clone(), Some(d.line)),
+            None => (self.path.clone(), None),
+        }
+    }
+}
+
+enum Description {
+    Interface(InterfaceDescription),
+    Jig(JigDescription),
+    Logger(LoggerDescription),
+    Scenario(ScenarioDescription),
+    Test(TestDescription),
+    Trigger(TriggerDescription),
+}
+
+impl Description {
+    fn jigs(&self) -> &[UnitName] {
+        match self {
+            Description::Interface(d) => d.jigs(),
+            Description::Logger(d) => d.jigs(),
+            Description::Scenario(d) => d.jigs(),
+            Description::Test(d) => d.jigs(),
+            Description::Trigger(d) => d.jigs(),
+            Description::Jig(_) => &[],
+        }
+    }
+}
+
+struct Linter {
+    units: Vec<UnitSource>,
+    descriptions: HashMap<UnitName, Description>,
+    diagnostics: Vec<Diagnostic>,
+
+    /// Units that other units inherit from.  These may be incomplete.
+    bases: HashSet<UnitName>,
+
+    /// Variables that values may refer to, in addition to the environment.
+    defines: Defines,
+}
+
+/// Check every unit in the given directories, returning a sorted list of
+/// problems.  Units that are shadowed by a unit of the same name in a
+/// directory with higher precedence are skipped, the same way they are
+/// when exclave loads them.
+pub fn check(layers: &ConfigLayers, defines: &Defines) -> Vec<Diagnostic> {
+    let mut linter = Linter {
+        units: vec![],
+        descriptions: HashMap::new(),
+        diagnostics: vec![],
+        bases: HashSet::new(),
+        defines: defines.clone(),
+    };
+
+    for dir in layers.dirs() {
+        if let Err(e) = dir.read_dir() {
+            linter.error(dir, None, format!("unable to read directory: {}", e));
+        }
+    }
+    let sources = layers.units();
+    for source in &sources {
+        linter.bases.extend(inherit::bases(&source.path));
+    }
+    for source in sources {
+        linter.read_unit(source.name, &source.path);
+    }
+    linter.check_units();
+    linter.check_references();
+    linter.check_cycles();
+    linter.check_scenarios();
+    linter.check_pools(layers);
+    linter.check_reachable();
+
+    let mut diagnostics = linter.diagnostics;
+    diagnostics.sort_by(|a, b| (&a.path, a.line, a.severity).cmp(&(&b.path, b.line, b.severity)));
+    diagnostics
+}
+
+fn section_name(kind: &UnitKind) -> &'static str {
+    match *kind {
+        UnitKind::Interface => "Interface",
+        UnitKind::Jig => "Jig",
+        UnitKind::Logger => "Logger",
+        UnitKind::Scenario => "Scenario",
+        UnitKind::Test => "Test",
+        UnitKind::Trigger => "Trigger",
+        UnitKind::Internal => "",
+    }
+}
+
+fn known_directives(kind: &UnitKind) -> &'static [&'static str] {
+    match *kind {
+        UnitKind::Interface => interface::DIRECTIVES,
+        UnitKind::Jig => jig::DIRECTIVES,
+        UnitKind::Logger => logger::DIRECTIVES,
+        UnitKind::Scenario => scenario::DIRECTIVES,
+        UnitKind::Test => test::DIRECTIVES,
+        UnitKind::Trigger => trigger::DIRECTIVES,
+        UnitKind::Internal => &[],
+    }
+}
+
+/// Split a unit file into its directives, remembering the line and section
+/// each came from.
+fn scan_directives(path: &Path, contents: &str) -> Vec<Directive> {
+    let mut directives = vec![];
+    let mut section = String::new();
+    let mut continued = false;
+    for (idx, raw_line) in contents.lines().enumerate() {
+        let line = raw_line.trim();
+        let was_continued = continued;
+        continued = line.ends_with('\\');
+        if was_continued || line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].trim().to_owned();
+            continue;
+        }
+        if let Some(pos) = line.find('=') {
+            directives.push(Directive {
+                path: path.to_owned(),
+                line: idx + 1,
+                section: section.clone(),
+                key: line[..pos].trim().to_owned(),
+                value: line[pos + 1..].trim().to_owned(),
+            });
+        }
+    }
+    directives
+}
+
+fn parse_time(time_str: &str) -> Result<Duration, String> {
+    if let Ok(val) = time_str.parse::<u64>() {
+        return Ok(Duration::from_secs(val));
+    }
+    parse_duration(time_str).map_err(|e| format!("{}", e))
+}
+
+/// Number of single-character edits needed to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..b_chars.len() + 1).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b_chars.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            let val = *[prev[j] + cost, prev[j + 1] + 1, cur[j] + 1].iter().min().unwrap();
+            cur.push(val);
+        }
+        prev = cur;
+    }
+    prev[b_chars.len()]
+}
+
+/// Find the known directive that `key` was most likely meant to be.
+fn suggest(key: &str, known: &[&'static str]) -> Option<&'static str> {
+    let lower = key.to_lowercase();
+    if let Some(k) = known.iter().find(|k| k.to_lowercase() == lower) {
+        return Some(k);
+    }
+    let limit = ::std::cmp::max(2, key.len() / 4);
+    known
+        .iter()
+        .map(|k| (edit_distance(&lower, &k.to_lowercase()), *k))
+        .filter(|&(distance, _)| distance <= limit)
+        .min()
+        .map(|(_, k)| k)
+}
+
+impl Linter {
+    fn error(&mut self, path: &Path, line: Option<usize>, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            path: path.to_owned(),
+            line,
+            message,
+        });
+    }
+
+    fn warning(&mut self, path: &Path, line: Option<usize>, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Warning,
+            path: path.to_owned(),
+            line,
+            message,
+        });
+    }
+
+    /// Read a unit file, along with any drop-in fragments and the units it
+    /// inherits from, and parse it.
+    fn read_unit(&mut self, name: UnitName, path: &Path) {
+        let mut directives = vec![];
+        for file in Some(path.to_owned()).into_iter().chain(dropin::fragments(path)) {
+            let mut file_contents = String::new();
+            if let Err(e) = File::open(&file).and_then(|mut f| f.read_to_string(&mut file_contents)) {
+                self.error(&file, None, format!("unable to read file: {}", e));
+                return;
+            }
+            directives.extend(scan_directives(&file, &file_contents));
+        }
+        self.units.push(UnitSource {
+            name: name.clone(),
+            path: path.to_owned(),
+            directives,
+        });
+
+        let contents = match inherit::read_unit(path) {
+            Ok(c) => c,
+            Err(UnitDescriptionError::FileOpenError(e)) => {
+                self.error(path, None, format!("unable to read file: {}", e));
+                return;
+            }
+            Err(e) => {
+                self.parse_failed(&name, e);
+                return;
+            }
+        };
+
+        let unit_name = name.clone();
+        let description = match *name.kind() {
+            UnitKind::Interface => InterfaceDescription::from_string(&contents, unit_name).map(Description::Interface),
+            UnitKind::Jig => JigDescription::from_string(&contents, unit_name).map(Description::Jig),
+            UnitKind::Logger => LoggerDescription::from_string(&contents, unit_name).map(Description::Logger),
+            UnitKind::Scenario => ScenarioDescription::from_string(&contents, unit_name).map(Description::Scenario),
+            UnitKind::Test => TestDescription::from_string(&contents, unit_name).map(Description::Test),
+            UnitKind::Trigger => TriggerDescription::from_string(&contents, unit_name).map(Description::Trigger),
+            UnitKind::Internal => return,
+        };
+        match description {
+            Ok(d) => {
+                self.descriptions.insert(name, d);
+            }
+            Err(e) => self.parse_failed(&name, e),
+        }
+    }
+
+    /// Report a unit that could not be parsed.  Value errors on individual
+    /// lines are found by check_units(), so only report the parser's error if
+    /// it found something that the line checks didn't.
+    fn parse_failed(&mut self, name: &UnitName, error: UnitDescriptionError) {
+        // Bases only need to hold the directives they share.
+        if let UnitDescriptionError::MissingValue(_, _) = error {
+            if self.bases.contains(name) {
+                return;
+            }
+        }
+        let unit = self.units.iter().find(|u| &u.name == name).unwrap();
+        let (path, line) = match error {
+            UnitDescriptionError::MissingBase(_) => unit.location(inherit::INHERIT_KEY),
+            UnitDescriptionError::InvalidBase(_, _) => unit.location(inherit::INHERIT_KEY),
+            UnitDescriptionError::InheritanceCycle(_) => unit.location(inherit::INHERIT_KEY),
+            UnitDescriptionError::MissingValue(_, ref key) => unit.location(key),
+            UnitDescriptionError::InvalidValue(_, ref key, _, _) => unit.location(key),
+            UnitDescriptionError::InvalidNumber(_, ref key, _) => unit.location(key),
+            _ => (unit.path.clone(), None),
+        };
+        let has_value_error = unit.directives.iter().any(|d| d.section == unit.section() && value_error(&d.key, &d.value).is_some());
+        if line.is_some() || !has_value_error {
+            self.error(&path, line, format!("{}", error));
+        }
+    }
+
+    fn check_units(&mut self) {
+        let mut found = vec![];
+        for unit in &self.units {
+            let section = unit.section();
+            let known = known_directives(unit.name.kind());
+            let mut ignored_sections = HashSet::new();
+            for directive in &unit.directives {
+                if directive.section != section {
+                    if ignored_sections.insert(directive.section.clone()) {
+                        found.push((Severity::Warning, directive.path.clone(), Some(directive.line),
+                                    format!("section [{}] is not used by .{} units and will be ignored",
+                                            directive.section, unit.name.kind())));
+                    }
+                    continue;
+                }
+                if !known.contains(&directive.key.as_str()) && directive.key != inherit::INHERIT_KEY {
+                    let message = match suggest(&directive.key, known) {
+                        Some(s) => format!("unknown directive '{}' in [{}] (did you mean '{}'?)", directive.key, section, s),
+                        None => format!("unknown directive '{}' in [{}]", directive.key, section),
+                    };
+                    found.push((Severity::Error, directive.path.clone(), Some(directive.line), message));
+                    continue;
+                }
+                if let Some(message) = value_error(&directive.key, &directive.value) {
+                    found.push((Severity::Error, directive.path.clone(), Some(directive.line),
+                                format!("invalid value for {}: {}", directive.key, message)));
+                }
+                if expand::EXPANDED_KEYS.contains(&directive.key.as_str()) {
+                    let expansion = Expansion::new(&unit.name, unit.path.parent().map(|p| p.to_owned()), &self.defines);
+                    match expansion.expand(&directive.value) {
+                        Ok(ref expanded) if expanded != &directive.value => {
+                            found.push((Severity::Note, directive.path.clone(), Some(directive.line),
+                                        format!("{} expands to: {}", directive.key, expanded)));
+                        }
+                        Ok(_) => (),
+                        // The jig and scenario are only known once the unit is run.
+                        Err(ExpandError::MissingSpecifier('j')) | Err(ExpandError::MissingSpecifier('s')) => (),
+                        Err(e) => found.push((Severity::Error, directive.path.clone(), Some(directive.line),
+                                              format!("invalid value for {}: {}", directive.key, e))),
+                    }
+                }
+            }
+        }
+        for (severity, path, line, message) in found {
+            match severity {
+                Severity::Error => self.error(&path, line, message),
+                Severity::Warning => self.warning(&path, line, message),
+                Severity::Note => self.diagnostics.push(Diagnostic {
+                    severity: Severity::Note,
+                    path,
+                    line,
+                    message,
+                }),
+            }
+        }
+    }
+
+    /// Returns true if a unit file exists, even if it couldn't be parsed.
+    fn exists(&self, name: &UnitName) -> bool {
+        self.units.iter().any(|u| &u.name == name)
+    }
+
+    /// Names that tests may refer to: every test, plus everything a test provides.
+    fn test_names(&self) -> HashMap<UnitName, Vec<UnitName>> {
+        let mut names: HashMap<UnitName, Vec<UnitName>> = HashMap::new();
+        for unit in &self.units {
+            if unit.name.kind() == &UnitKind::Test {
+                names.entry(unit.name.clone()).or_default();
+            }
+        }
+        for (name, description) in &self.descriptions {
+            if let Description::Test(test) = description {
+                for provided in test.provides() {
+                    names.entry(provided.clone()).or_default().push(name.clone());
+                }
+            }
+        }
+        names
+    }
+
+    /// The tests that satisfy a reference to `name`.
+    fn resolve(&self, names: &HashMap<UnitName, Vec<UnitName>>, name: &UnitName) -> Vec<UnitName> {
+        if let Some(&Description::Test(_)) = self.descriptions.get(name) {
+            return vec![name.clone()];
+        }
+        let mut providers = names.get(name).cloned().unwrap_or_default();
+        providers.sort();
+        providers
+    }
+
+    fn check_references(&mut self) {
+        let test_names = self.test_names();
+        let capabilities: HashSet<&String> = self.descriptions
+            .values()
+            .filter_map(|d| match d {
+                Description::Jig(j) => Some(j.capabilities()),
+                _ => None,
+            })
+            .flat_map(|c| c.iter())
+            .collect();
+        let mut found = vec![];
+        let mut warnings = vec![];
+        for unit in &self.units {
+            let description = match self.descriptions.get(&unit.name) {
+                Some(d) => d,
+                None => continue,
+            };
+
+            for jig_name in description.jigs() {
+                if !self.exists(jig_name) {
+                    found.push(unit.at("Jigs", format!("Jigs refers to {}, which does not exist", jig_name)));
+                }
+            }
+
+            let test_refs: Vec<(&str, &UnitName)> = match description {
+                Description::Test(t) => t.requires().iter().map(|r| ("Requires", r))
+                    .chain(t.suggests().iter().map(|s| ("Suggests", s)))
+                    .collect(),
+                Description::Scenario(s) => s.tests().iter().map(|t| ("Tests", t))
+                    .chain(s.assumptions().iter().map(|a| ("Assume", a)))
+                    .collect(),
+                _ => vec![],
+            };
+            for (key, name) in test_refs {
+                if !test_names.contains_key(name) {
+                    found.push(unit.at(key, format!("{} refers to {}, which does not exist and is not provided by any test", key, name)));
+                }
+            }
+
+            // Ordering and conflicts only matter if the other test is in the
+            // scenario, so a missing one isn't an error.
+            if let Description::Test(t) = description {
+                let order_refs = t.after().iter().map(|a| ("After", a))
+                    .chain(t.before().iter().map(|b| ("Before", b)))
+                    .chain(t.conflicts().iter().map(|c| ("Conflicts", c)));
+                for (key, name) in order_refs {
+                    if !test_names.contains_key(name) {
+                        warnings.push(unit.at(key, format!("{} refers to {}, which does not exist and is not provided by any test", key, name)));
+                    }
+                }
+            }
+
+            let required = match description {
+                Description::Test(t) => t.requires_capability().clone(),
+                Description::Scenario(s) => s.requires_capability().clone(),
+                _ => vec![],
+            };
+            for capability in required {
+                if !capabilities.contains(&capability) {
+                    warnings.push(unit.at("RequiresCapability", format!("RequiresCapability refers to {}, which no jig has", capability)));
+                }
+            }
+
+            let prefer = match description {
+                Description::Jig(j) => j.prefer().clone(),
+                Description::Scenario(s) => s.prefer().clone(),
+                _ => vec![],
+            };
+            for name in prefer {
+                if !self.exists(&name) {
+                    warnings.push(unit.at("Prefer", format!("Prefer refers to {}, which does not exist", name)));
+                }
+            }
+
+            if let Description::Jig(j) = description {
+                if let Some(scenario_name) = j.default_scenario() {
+                    if !self.exists(scenario_name) {
+                        found.push(unit.at("DefaultScenario", format!("DefaultScenario refers to {}, which does not exist", scenario_name)));
+                    }
+                }
+                if let Some(scenario_name) = j.self_test_scenario() {
+                    if !self.exists(scenario_name) {
+                        found.push(unit.at("SelfTestScenario", format!("SelfTestScenario refers to {}, which does not exist", scenario_name)));
+                    }
+                }
+                for (counter, thresholds) in j.wear_thresholds() {
+                    if counter != j.id() && !self.exists(counter) {
+                        let key = if thresholds.limit.is_some() { "WearLimit" } else { "WearWarning" };
+                        warnings.push(unit.at(key, format!("{} refers to {}, which does not exist", key, counter)));
+                    }
+                }
+            }
+        }
+        for (path, line, message) in found {
+            self.error(&path, line, message);
+        }
+        for (path, line, message) in warnings {
+            self.warning(&path, line, message);
+        }
+    }
+
+    /// Tests that `test` must run after, with virtual names expanded to
+    /// every test that provides them.
+    fn dependencies(&self, names: &HashMap<UnitName, Vec<UnitName>>, test: &TestDescription) -> Vec<UnitName> {
+        let mut deps = vec![];
+        for name in test.requires().iter().chain(test.suggests().iter()) {
+            for resolved in self.resolve(names, name) {
+                if !deps.contains(&resolved) {
+                    deps.push(resolved);
+                }
+            }
+        }
+        deps
+    }
+
+    fn check_cycles(&mut self) {
+        let names = self.test_names();
+        let mut tests: Vec<&UnitName> = self.descriptions.keys().filter(|n| n.kind() == &UnitKind::Test).collect();
+        tests.sort();
+
+        let mut edges = HashMap::new();
+        for name in &tests {
+            if let Some(Description::Test(t)) = self.descriptions.get(name) {
+                edges.insert((*name).clone(), self.dependencies(&names, t));
+            }
+        }
+
+        // Depth-first search, recording the path so that any cycle found
+        // can be reported with all of its members.
+        let mut done = HashSet::new();
+        let mut cycles: Vec<Vec<UnitName>> = vec![];
+        for start in &tests {
+            let mut stack: Vec<(UnitName, usize)> = vec![((*start).clone(), 0)];
+            while let Some((node, idx)) = stack.pop() {
+                if done.contains(&node) {
+                    continue;
+                }
+                let next = edges.get(&node).and_then(|e| e.get(idx)).cloned();
+                match next {
+                    None => {
+                        done.insert(node);
+                    }
+                    Some(next) => {
+                        stack.push((node, idx + 1));
+                        if let Some(pos) = stack.iter().position(|(n, _)| n == &next) {
+                            let mut cycle: Vec<UnitName> = stack[pos..].iter().map(|(n, _)| n.clone()).collect();
+                            // Rotate so the same cycle is always reported the same way.
+                            let min = cycle.iter().enumerate().min_by_key(|&(_, n)| n).map(|(i, _)| i).unwrap();
+                            cycle.rotate_left(min);
+                            if !cycles.contains(&cycle) {
+                                cycles.push(cycle);
+                            }
+                        } else if !done.contains(&next) {
+                            stack.push((next, 0));
+                        }
+                    }
+                }
+            }
+        }
+
+        for cycle in cycles {
+            let mut members: Vec<String> = cycle.iter().map(|n| format!("{}", n)).collect();
+            members.push(format!("{}", cycle[0]));
+            let (path, line) = match self.units.iter().find(|u| u.name == cycle[0]) {
+                Some(u) => match u.location("Requires") {
+                    (_, None) => u.location("Suggests"),
+                    location => location,
+                },
+                None => continue,
+            };
+            self.error(&path, line, format!("dependency cycle: {}", members.join(" -> ")));
+        }
+    }
+
+    /// Resolve each scenario the way the loader would, and show how any
+    /// missing or circular dependency was reached.  Scenarios that resolve
+    /// are also checked for conflicting tests and ordering loops.
+    fn check_scenarios(&mut self) {
+        let mut tests = vec![];
+        for description in self.descriptions.values() {
+            if let Description::Test(t) = description {
+                tests.push(t.clone());
+            }
+        }
+
+        let mut found = vec![];
+        for unit in &self.units {
+            if let Some(Description::Scenario(s)) = self.descriptions.get(&unit.name) {
+                if let Some(reason) = plan::diagnose(s, &tests, None) {
+                    found.push(unit.at("Tests", format!("{}", reason)));
+                } else if let Err(reason) = s.resolve_tests(&tests, None) {
+                    found.push(unit.at("Tests", format!("{}", reason)));
+                }
+            }
+        }
+        for (path, line, message) in found {
+            self.error(&path, line, message);
+        }
+    }
+
+    /// Make sure every pool a scenario uses is defined, and makes sense.
+    fn check_pools(&mut self, layers: &ConfigLayers) {
+        let mut found = vec![];
+        let mut checked = HashSet::new();
+        for unit in &self.units {
+            if let Some(Description::Scenario(s)) = self.descriptions.get(&unit.name) {
+                for name in s.pools() {
+                    match layers.effective_file(&format!("{}.{}", name, pool::POOL_EXTENSION)) {
+                        None => found.push(unit.at("Pools", format!("Pools refers to {}, which has no {}.{} file", name, name, pool::POOL_EXTENSION))),
+                        Some(path) => if checked.insert(path.clone()) {
+                            if let Err(e) = PoolDescription::from_path(name, &path) {
+                                found.push((path, None, format!("{}", e)));
+                            }
+                        },
+                    }
+                }
+            }
+        }
+        for (path, line, message) in found {
+            self.error(&path, line, message);
+        }
+    }
+
+    fn check_reachable(&mut self) {
+        let names = self.test_names();
+        let mut reached = HashSet::new();
+        let mut pending = vec![];
+        for description in self.descriptions.values() {
+            if let Description::Scenario(s) = description {
+                for name in s.tests().iter().chain(s.assumptions().iter()) {
+                    pending.extend(self.resolve(&names, name));
+                }
+            }
+        }
+        while let Some(name) = pending.pop() {
+            if !reached.insert(name.clone()) {
+                continue;
+            }
+            if let Some(Description::Test(t)) = self.descriptions.get(&name) {
+                pending.extend(self.dependencies(&names, t));
+            }
+        }
+
+        let mut unreached = vec![];
+        for unit in &self.units {
+            if unit.name.kind() == &UnitKind::Test && self.descriptions.contains_key(&unit.name)
+                && !reached.contains(&unit.name) && !self.bases.contains(&unit.name) {
+                unreached.push((unit.path.clone(), format!("{} is not run by any scenario", unit.name)));
+            }
+        }
+        for (path, message) in unreached {
+            self.warning(&path, None, message);
+        }
+    }
+}
+
+/// Check the value of a directive whose format is known, returning a
+/// description of the problem, if any.
+fn value_error(key: &str, value: &str) -> Option<String> {
+    let result = match key {
+        "Jigs" => UnitName::from_list(value, "jig").map(|_| ()).map_err(|e| format!("{}", e)),
+        "Requires" | "Suggests" | "Provides" | "After" | "Before" | "Conflicts" | "Tests" | "Assume" | "Prefer" => {
+            UnitName::from_list(value, "test").map(|_| ()).map_err(|e| format!("{}", e))
+        }
+        "DefaultScenario" | "SelfTestScenario" if !value.is_empty() => UnitName::from_str(value, "scenario").map(|_| ()).map_err(|e| format!("{}", e)),
+        "Priority" if !value.is_empty() => value.trim().parse::<i32>().map(|_| ()).map_err(|_| "must be a whole number".to_owned()),
+        "WearWarning" | "WearLimit" => parse_thresholds(&UnitName::internal("jig"), value).map(|_| ()),
+        "DaemonReadyText" if !value.is_empty() => Regex::new(value).map(|_| ()).map_err(|e| format!("{}", e)),
+        k if (k.ends_with("Timeout") || k == "SelfTestValidity") && !value.is_empty() => parse_time(value).map(|_| ()),
+        _ => Ok(()),
+    };
+    result.err()
+}
@@ -4,12 +4,19 @@ extern crate ctrlc;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde;
+#[macro_use]
 extern crate serde_json;
+extern crate hmac;
+extern crate sha2;
 
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
 
+mod artifacts;
+mod audit;
 mod unit;
 mod unitbroadcaster;
 mod unitlibrary;
@@ -19,14 +26,316 @@ mod units;
 mod unitwatcher;
 mod terminal;
 mod config;
+mod detect;
+mod dropin;
+mod expand;
+mod history;
+mod inherit;
+mod layers;
+mod lint;
+mod operator;
+mod plan;
+mod pool;
 mod quiesce;
+mod report;
+mod selftest;
+mod settings;
+mod state;
+mod wear;
 
+use expand::Defines;
+use layers::{ConfigLayers, Precedence};
+use settings::Settings;
 use unitbroadcaster::{UnitBroadcaster, UnitEvent};
 use unitwatcher::UnitWatcher;
 use unitloader::UnitLoader;
 use unitlibrary::UnitLibrary;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+fn config_dir_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("CONFIG_DIR")
+        .short("c")
+        .long("config-dir")
+        .value_name("CONFIG_DIR")
+        .number_of_values(1)
+        .required(true)
+        .multiple(true)
+        .takes_value(true)
+        .help("Directory where configuration unit files are stored")
+}
+
+fn precedence_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("CONFIG_PRECEDENCE")
+        .long("config-precedence")
+        .value_name("ORDER")
+        .takes_value(true)
+        .possible_values(&["last", "first"])
+        .default_value("last")
+        .help("Whether units in later or earlier config directories override units with the same name")
+}
+
+fn defines_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("DEFINES")
+        .long("defines")
+        .value_name("FILE")
+        .takes_value(true)
+        .help("File of NAME=VALUE lines that unit files may refer to as ${NAME}")
+}
+
+fn settings_file_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("SETTINGS_FILE")
+        .long("config-file")
+        .value_name("FILE")
+        .takes_value(true)
+        .help("Global settings file (default: exclave.conf in the first config directory, if present)")
+}
+
+fn state_dir_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("STATE_DIR")
+        .long("state-dir")
+        .value_name("DIR")
+        .takes_value(true)
+        .help("Where to keep state across restarts (default: .exclave-state in the first config dir)")
+}
+
+/// Read the defines file named in `matches`, if any, exiting if it can't be read.
+fn defines(matches: &ArgMatches) -> Defines {
+    match matches.value_of("DEFINES") {
+        None => Defines::new(),
+        Some(path) => match Defines::from_path(Path::new(path)) {
+            Ok(d) => d,
+            Err(e) => {
+                println!("Unable to read defines file {}: {}", path, e);
+                process::exit(1);
+            }
+        },
+    }
+}
+
+/// Parse the time given for `arg`, if any, exiting if it isn't valid.
+fn time_arg(matches: &ArgMatches, arg: &str, name: &str) -> Option<Duration> {
+    let value = matches.value_of(arg)?;
+    match settings::parse_time(value) {
+        Ok(t) => Some(t),
+        Err(e) => {
+            println!("Invalid {}: {}", name, e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Collect the settings given on the command line, which take the place of
+/// those in the settings file.  Exits if any of them are invalid.
+fn settings_overrides(matches: &ArgMatches) -> Settings {
+    let mut overrides = Settings::new();
+    overrides.test_timeout = time_arg(matches, "TEST_TIMEOUT", "test timeout");
+    overrides.terminate_timeout = time_arg(matches, "TERMINATE_TIMEOUT", "terminate timeout");
+    if let Some(paths) = matches.value_of("SEARCH_PATH") {
+        overrides.paths = Some(settings::parse_paths(paths));
+    }
+    if let Some(history) = matches.value_of("LOG_HISTORY") {
+        match history.parse() {
+            Ok(h) => overrides.log_history = Some(h),
+            Err(e) => {
+                println!("Invalid log history: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(root) = matches.value_of("SYSFS_ROOT") {
+        overrides.sysfs_root = Some(env::current_dir().expect("Couldn't get current working directory").join(root));
+    }
+    if let Some(dir) = matches.value_of("STATE_DIR") {
+        overrides.state_directory = Some(env::current_dir().expect("Couldn't get current working directory").join(dir));
+    }
+    if matches.is_present("REQUIRE_LOGIN") {
+        overrides.require_login = Some(true);
+    }
+    if let Some(dir) = matches.value_of("ARTIFACT_DIR") {
+        overrides.artifact_directory = Some(env::current_dir().expect("Couldn't get current working directory").join(dir));
+    }
+    if let Some(keep) = matches.value_of("ARTIFACT_KEEP") {
+        match keep.parse() {
+            Ok(k) => overrides.keep_runs = Some(k),
+            Err(e) => {
+                println!("Invalid number of runs to keep: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(roster) = matches.value_of("OPERATOR_ROSTER") {
+        overrides.operator_roster = Some(env::current_dir().expect("Couldn't get current working directory").join(roster));
+    }
+    if let Some(station_id) = matches.value_of("STATION_ID") {
+        overrides.station_id = Some(station_id.to_owned());
+    }
+    if let Some(addresses) = matches.values_of("LISTEN") {
+        let mut listen = vec![];
+        for address in addresses {
+            match settings::parse_listen(address) {
+                Ok(l) => listen.extend(l),
+                Err(bad) => {
+                    println!("Invalid listen address {}: expected HOST:PORT", bad);
+                    process::exit(1);
+                }
+            }
+        }
+        overrides.listen = Some(listen);
+    }
+    overrides
+}
+
+/// Collect the config directories named in `matches`, layered as requested.
+fn config_layers(matches: &ArgMatches) -> ConfigLayers {
+    let precedence = Precedence::from_str(matches.value_of("CONFIG_PRECEDENCE").unwrap()).unwrap();
+    let mut layers = ConfigLayers::new(precedence);
+    for dir in matches.values_of("CONFIG_DIR").unwrap() {
+        layers.add_dir(Path::new(dir));
+    }
+    layers
+}
+
+/// The anchor file for the audit log `log`, as given by `matches`.
+fn audit_anchor_path(matches: &ArgMatches, log: &str) -> PathBuf {
+    match matches.value_of("AUDIT_ANCHOR") {
+        Some(anchor) => PathBuf::from(anchor),
+        None => audit::default_anchor_path(Path::new(log)),
+    }
+}
+
+/// The state directory that exclave would use if started with `matches`.
+/// Exits if the settings file can't be read.
+fn state_directory(matches: &ArgMatches) -> PathBuf {
+    let layers = config_layers(matches);
+    let settings_file = match matches.value_of("SETTINGS_FILE") {
+        Some(file) => PathBuf::from(file),
+        None => layers.dirs()[0].join(settings::SETTINGS_FILE),
+    };
+    let settings = match settings::load(&settings_file, &settings_overrides(matches)) {
+        Ok(s) => s,
+        Err(e) => {
+            println!("Unable to read {}: {}", settings_file.display(), e);
+            process::exit(1);
+        }
+    };
+    let mut config = config::Config::new();
+    config.set_layers(layers);
+    config.apply_settings(&settings);
+    config.state_directory()
+}
+
+/// List the wear counters, or reset them.  Returns the exit code.
+fn wear_command(matches: &ArgMatches) -> i32 {
+    let state_dir = state_directory(matches);
+    let mut counters = match wear::WearCounters::open(&state_dir) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Unable to read wear counters in {}: {}", state_dir.display(), e);
+            return 1;
+        }
+    };
+    let jig = match matches.value_of("JIG").map(|j| unit::UnitName::from_str(j, "jig")) {
+        None => None,
+        Some(Ok(j)) => Some(j),
+        Some(Err(e)) => {
+            println!("Invalid jig name: {}", e);
+            return 1;
+        }
+    };
+
+    if matches.is_present("RESET") {
+        let jig = jig.unwrap();
+        let counter = match matches.value_of("COUNTER").map(|c| unit::UnitName::from_str(c, "test")) {
+            None => None,
+            Some(Ok(c)) => Some(c),
+            Some(Err(e)) => {
+                println!("Invalid counter name: {}", e);
+                return 1;
+            }
+        };
+        return match counters.reset(&jig, counter.as_ref()) {
+            Err(e) => {
+                println!("Unable to reset wear counters: {}", e);
+                1
+            }
+            Ok(ref reset) if reset.is_empty() => {
+                println!("{} has no counters to reset", jig);
+                1
+            }
+            Ok(reset) => {
+                for counter in reset {
+                    println!("Reset {}: {}", jig, counter);
+                }
+                0
+            }
+        };
+    }
+
+    let layers = config_layers(matches);
+    for name in counters.jigs() {
+        let jig_name = match unit::UnitName::from_str(&name, "jig") {
+            Ok(n) => n,
+            Err(_) => continue,
+        };
+        if jig.as_ref().map(|j| *j != jig_name).unwrap_or(false) {
+            continue;
+        }
+        // Show the thresholds too, if the jig's unit file can be found.
+        let description = layers.effective(&jig_name)
+            .and_then(|path| units::jig::JigDescription::from_path(&path).ok());
+        println!("{}:", jig_name);
+        for (counter, count) in counters.counters(&jig_name) {
+            let thresholds = description
+                .as_ref()
+                .and_then(|d| d.wear_thresholds().iter().find(|&(c, _)| format!("{}", c) == counter).map(|(_, t)| t.clone()))
+                .unwrap_or_default();
+            let mut line = format!("    {}: {}", counter, count);
+            if let Some(warning) = thresholds.warning {
+                line.push_str(&format!(", warning at {}", warning));
+            }
+            if let Some(limit) = thresholds.limit {
+                line.push_str(&format!(", limit {}", limit));
+                if count >= limit {
+                    line.push_str(" (LIMIT REACHED)");
+                }
+            }
+            println!("{}", line);
+        }
+    }
+    0
+}
+
+/// Show every run of a device.  Returns the exit code.
+fn history_command(matches: &ArgMatches) -> i32 {
+    let state_dir = state_directory(matches);
+    let serial = matches.value_of("SERIAL").unwrap();
+    let runs = match history::for_serial(&state_dir, serial) {
+        Ok(r) => r,
+        Err(e) => {
+            println!("Unable to read run history in {}: {}", state_dir.display(), e);
+            return 1;
+        }
+    };
+    for run in &runs {
+        let operator = match run.operator {
+            Some(ref id) => format!(" by {}", id),
+            None => String::new(),
+        };
+        println!(
+            "{}  {}  {} on {}{}: {} {}",
+            run.run_id,
+            if run.passed() { "PASS" } else { "FAIL" },
+            run.scenario,
+            run.jig.as_deref().unwrap_or("no jig"),
+            operator,
+            run.result,
+            run.message
+        );
+    }
+    println!("{} has {}", serial, history::summary(&runs));
+    0
+}
 
 fn main() {
     let config = Arc::new(Mutex::new(config::Config::new()));
@@ -48,17 +357,10 @@ fn main() {
         .version("1.0")
         .author("Sean Cross <sean@xobs.io>")
         .about("Orchestrates the Common Factory Test Interface server")
-        .arg(
-            Arg::with_name("CONFIG_DIR")
-                .short("c")
-                .long("config-dir")
-                .value_name("CONFIG_DIR")
-                .number_of_values(1)
-                .required(true)
-                .multiple(true)
-                .takes_value(true)
-                .help("Directory where configuration unit files are stored"),
-        )
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .arg(config_dir_arg())
+        .arg(precedence_arg())
+        .arg(defines_arg())
         .arg(
             Arg::with_name("PLAIN")
                 .short("p")
@@ -71,6 +373,115 @@ fn main() {
                 .long("no-output")
                 .help("Prevent console output entirely"),
         )
+        .arg(
+            Arg::with_name("ARTIFACT_DIR")
+                .long("artifact-dir")
+                .value_name("ARTIFACT_DIR")
+                .takes_value(true)
+                .help("Directory to store the output and files of each run in"),
+        )
+        .arg(
+            Arg::with_name("ARTIFACT_KEEP")
+                .long("keep-runs")
+                .value_name("COUNT")
+                .takes_value(true)
+                .help("Number of runs to keep in the artifact directory (default: keep all)"),
+        )
+        .arg(
+            Arg::with_name("AUDIT_LOG")
+                .long("audit-log")
+                .value_name("AUDIT_LOG")
+                .takes_value(true)
+                .requires("AUDIT_KEY")
+                .help("Write a tamper-evident, hash-chained log of all records to this file"),
+        )
+        .arg(
+            Arg::with_name("AUDIT_KEY")
+                .long("audit-key")
+                .value_name("KEY_FILE")
+                .takes_value(true)
+                .help("File containing the key used to sign audit log checkpoints"),
+        )
+        .arg(
+            Arg::with_name("AUDIT_ANCHOR")
+                .long("audit-anchor")
+                .value_name("ANCHOR_FILE")
+                .takes_value(true)
+                .requires("AUDIT_LOG")
+                .help("File recording the audit log's last checkpoint (default: the log's name with .anchor added)"),
+        )
+        .arg(
+            Arg::with_name("PIN_JIG")
+                .long("jig")
+                .value_name("JIG")
+                .takes_value(true)
+                .help("Only ever use this jig, even if others are compatible"),
+        )
+        .arg(settings_file_arg())
+        .arg(
+            Arg::with_name("TEST_TIMEOUT")
+                .long("test-timeout")
+                .value_name("TIME")
+                .takes_value(true)
+                .help("Timeout for tests that don't set their own, and for jig TestPrograms"),
+        )
+        .arg(
+            Arg::with_name("TERMINATE_TIMEOUT")
+                .long("terminate-timeout")
+                .value_name("TIME")
+                .takes_value(true)
+                .help("How long to wait for a program to stop before killing it"),
+        )
+        .arg(
+            Arg::with_name("SEARCH_PATH")
+                .long("path")
+                .value_name("DIRS")
+                .takes_value(true)
+                .help("Colon-separated directories to search for programs in (default: inherit PATH)"),
+        )
+        .arg(
+            Arg::with_name("LOG_HISTORY")
+                .long("log-history")
+                .value_name("LINES")
+                .takes_value(true)
+                .help("Number of log lines to show on the terminal"),
+        )
+        .arg(
+            Arg::with_name("SYSFS_ROOT")
+                .long("sysfs-root")
+                .value_name("DIR")
+                .takes_value(true)
+                .help("Where to find sysfs when matching jigs by USB device or DMI product (default: /sys)"),
+        )
+        .arg(state_dir_arg())
+        .arg(
+            Arg::with_name("STATION_ID")
+                .long("station-id")
+                .value_name("ID")
+                .takes_value(true)
+                .help("Identifies this test station, and is passed to programs as EXCLAVE_STATION_ID"),
+        )
+        .arg(
+            Arg::with_name("LISTEN")
+                .long("listen")
+                .value_name("HOST:PORT")
+                .number_of_values(1)
+                .multiple(true)
+                .takes_value(true)
+                .help("Address for control servers to listen on, passed to programs as EXCLAVE_LISTEN"),
+        )
+        .arg(
+            Arg::with_name("REQUIRE_LOGIN")
+                .long("require-login")
+                .help("Refuse to start scenarios until an operator has logged in"),
+        )
+        .arg(
+            Arg::with_name("OPERATOR_ROSTER")
+                .long("operator-roster")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("File listing the operators who may log in (default: anyone may)"),
+        )
         .arg(
             Arg::with_name("DEBUG_LOGFILE")
                 .short("9")
@@ -79,9 +490,257 @@ fn main() {
                 .required(false)
                 .takes_value(true),
         )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Check unit files for errors without running them")
+                .arg(config_dir_arg())
+                .arg(precedence_arg())
+                .arg(defines_arg())
+                .arg(
+                    Arg::with_name("SHOW_EXPANDED")
+                        .long("show-expanded")
+                        .help("Also show what each value containing variables or specifiers expands to"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("plan")
+                .about("Show the order a scenario's tests will run in, and why each is included")
+                .arg(config_dir_arg())
+                .arg(precedence_arg())
+                .arg(defines_arg())
+                .arg(
+                    Arg::with_name("DOT")
+                        .long("dot")
+                        .help("Print the dependency graph in Graphviz format"),
+                )
+                .arg(
+                    Arg::with_name("JIG")
+                        .short("j")
+                        .long("jig")
+                        .value_name("JIG")
+                        .takes_value(true)
+                        .help("Resolve the scenario as it would be on this jig"),
+                )
+                .arg(
+                    Arg::with_name("SCENARIO")
+                        .required(true)
+                        .help("Scenario to resolve"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("units")
+                .about("Show which file each unit is loaded from, and which files it shadows")
+                .arg(config_dir_arg())
+                .arg(precedence_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("report")
+                .about("Regenerate HTML reports from stored run results")
+                .arg(
+                    Arg::with_name("RUN_DIR")
+                        .required(true)
+                        .multiple(true)
+                        .help("A run directory, or an artifact directory containing many runs"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("wear")
+                .about("Show how many times each jig and test has been used, or reset the counts after maintenance")
+                .arg(config_dir_arg())
+                .arg(precedence_arg())
+                .arg(settings_file_arg())
+                .arg(state_dir_arg())
+                .arg(
+                    Arg::with_name("RESET")
+                        .long("reset")
+                        .requires("JIG")
+                        .help("Reset the jig's counters, or only COUNTER if it's given"),
+                )
+                .arg(
+                    Arg::with_name("JIG")
+                        .help("Only show this jig"),
+                )
+                .arg(
+                    Arg::with_name("COUNTER")
+                        .help("The counter to reset, which is the jig itself or one of its tests"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("history")
+                .about("Show every run of a device, by its serial number")
+                .arg(config_dir_arg())
+                .arg(precedence_arg())
+                .arg(settings_file_arg())
+                .arg(state_dir_arg())
+                .arg(
+                    Arg::with_name("SERIAL")
+                        .required(true)
+                        .help("Serial number of the device"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Verify that an audit log has not been modified or truncated")
+                .arg(
+                    Arg::with_name("AUDIT_LOG")
+                        .required(true)
+                        .help("Audit log to verify"),
+                )
+                .arg(
+                    Arg::with_name("AUDIT_KEY")
+                        .long("audit-key")
+                        .value_name("KEY_FILE")
+                        .takes_value(true)
+                        .required(true)
+                        .help("File containing the key used to sign checkpoints"),
+                )
+                .arg(
+                    Arg::with_name("AUDIT_ANCHOR")
+                        .long("audit-anchor")
+                        .value_name("ANCHOR_FILE")
+                        .takes_value(true)
+                        .help("File recording the log's last checkpoint (default: the log's name with .anchor added)"),
+                ),
+        )
         .get_matches();
 
-    let config_dirs: Vec<_> = matches.values_of("CONFIG_DIR").unwrap().collect();
+    if let Some(verify_matches) = matches.subcommand_matches("verify") {
+        let log = verify_matches.value_of("AUDIT_LOG").unwrap();
+        let key = verify_matches.value_of("AUDIT_KEY").unwrap();
+        let anchor = audit_anchor_path(verify_matches, log);
+        match audit::verify(Path::new(log), Path::new(key), &anchor) {
+            Ok(summary) => {
+                println!("{}: OK ({} records, {} checkpoints)", log, summary.records, summary.checkpoints);
+                if summary.resumed > 0 {
+                    println!("{}: {} records were only signed after exclave restarted without shutting down", log, summary.resumed);
+                }
+                process::exit(0);
+            }
+            Err(e) => {
+                println!("{}: FAILED: {}", log, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(check_matches) = matches.subcommand_matches("check") {
+        let diagnostics = lint::check(&config_layers(check_matches), &defines(check_matches));
+        let errors = diagnostics.iter().filter(|d| d.severity == lint::Severity::Error).count();
+        let warnings = diagnostics.iter().filter(|d| d.severity == lint::Severity::Warning).count();
+        for diagnostic in &diagnostics {
+            if diagnostic.severity != lint::Severity::Note || check_matches.is_present("SHOW_EXPANDED") {
+                println!("{}", diagnostic);
+            }
+        }
+        println!("{} errors, {} warnings", errors, warnings);
+        process::exit(if errors > 0 { 1 } else { 0 });
+    }
+
+    if let Some(plan_matches) = matches.subcommand_matches("plan") {
+        let scenario_name = match unit::UnitName::from_str(plan_matches.value_of("SCENARIO").unwrap(), "scenario") {
+            Ok(n) => n,
+            Err(e) => {
+                println!("Invalid scenario name: {}", e);
+                process::exit(1);
+            }
+        };
+        let jig_name = match plan_matches.value_of("JIG").map(|j| unit::UnitName::from_str(j, "jig")) {
+            None => None,
+            Some(Ok(n)) => Some(n),
+            Some(Err(e)) => {
+                println!("Invalid jig name: {}", e);
+                process::exit(1);
+            }
+        };
+        match plan::Plan::from_layers(&config_layers(plan_matches), &defines(plan_matches), &scenario_name, jig_name.as_ref()) {
+            Ok(plan) => {
+                if plan_matches.is_present("DOT") {
+                    print!("{}", plan.to_dot());
+                } else {
+                    print!("{}", plan);
+                }
+                process::exit(0);
+            }
+            Err(e) => {
+                println!("{}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(units_matches) = matches.subcommand_matches("units") {
+        for source in config_layers(units_matches).units() {
+            println!("{}: {}", source.name, source.path.display());
+            for fragment in &source.fragments {
+                println!("    with {}", fragment.display());
+            }
+            for shadowed in &source.shadowed {
+                println!("    shadows {}", shadowed.display());
+            }
+        }
+        process::exit(0);
+    }
+
+    if let Some(wear_matches) = matches.subcommand_matches("wear") {
+        process::exit(wear_command(wear_matches));
+    }
+
+    if let Some(history_matches) = matches.subcommand_matches("history") {
+        process::exit(history_command(history_matches));
+    }
+
+    if let Some(report_matches) = matches.subcommand_matches("report") {
+        let mut failed = false;
+        for dir in report_matches.values_of("RUN_DIR").unwrap() {
+            match report::regenerate(Path::new(dir)) {
+                Ok(reports) => for report in reports {
+                    println!("Wrote {}", report.display());
+                },
+                Err(e) => {
+                    println!("Unable to generate report for {}: {}", dir, e);
+                    failed = true;
+                }
+            }
+        }
+        process::exit(if failed { 1 } else { 0 });
+    }
+
+    // Make the config directories absolute, since the current directory is
+    // about to change.
+    let current_dir = env::current_dir().expect("Couldn't get current working directory");
+    let config_dirs: Vec<PathBuf> = matches.values_of("CONFIG_DIR").unwrap().map(|d| current_dir.join(d)).collect();
+    let precedence = Precedence::from_str(matches.value_of("CONFIG_PRECEDENCE").unwrap()).unwrap();
+    unit_watcher.set_precedence(precedence);
+    {
+        let mut layers = ConfigLayers::new(precedence);
+        for dir in &config_dirs {
+            layers.add_dir(dir);
+        }
+        let mut config = config.lock().unwrap();
+        config.set_layers(layers);
+        config.set_defines(defines(&matches));
+        if let Some(jig) = matches.value_of("PIN_JIG") {
+            match unit::UnitName::from_str(jig, "jig") {
+                Ok(j) => config.set_pinned_jig(Some(j)),
+                Err(e) => {
+                    println!("Invalid jig name: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+    let overrides = settings_overrides(&matches);
+    let settings_file = match matches.value_of("SETTINGS_FILE") {
+        Some(file) => {
+            let file = current_dir.join(file);
+            if !file.is_file() {
+                println!("Settings file {} does not exist", file.display());
+                return;
+            }
+            file
+        }
+        None => config_dirs[0].join(settings::SETTINGS_FILE),
+    };
     let output_type = if matches.is_present("PLAIN") {
         Some(terminal::TerminalOutputType::Plain)
     } else if matches.is_present("QUIET") {
@@ -90,6 +749,20 @@ fn main() {
         None
     };
 
+    // Open the audit log before changing directories, so that relative
+    // paths are relative to where we were started.
+    let mut audit_log = match (matches.value_of("AUDIT_LOG"), matches.value_of("AUDIT_KEY")) {
+        (Some(log), Some(key)) => match audit::AuditLog::open(Path::new(log), Path::new(key), &audit_anchor_path(&matches, log),
+                                                              audit::DEFAULT_CHECKPOINT_INTERVAL) {
+            Ok(a) => Some(a),
+            Err(e) => {
+                println!("Unable to open audit log {}: {}", log, e);
+                return;
+            }
+        },
+        _ => None,
+    };
+
     // Set the current working directory to the first-specified path.
     // That way, relative paths will continue to work.
     if let Some(path) = config_dirs.get(0) {
@@ -100,9 +773,12 @@ fn main() {
 
     terminal::TerminalInterface::start(output_type, unit_broadcaster.subscribe());
 
+    // Load the settings before any units, so that they apply from the start.
+    settings::watch(&settings_file, overrides, &unit_broadcaster);
+
     for config_dir in config_dirs {
         unit_watcher
-            .add_path(config_dir)
+            .add_path(&config_dir)
             .expect("Unable to add config directory");
     }
 
@@ -147,6 +823,11 @@ fn main() {
         unit_loader.process_message(&msg);
         unit_library.process_message(&msg);
         quiesce.process_message(&msg);
+        if let Some(audit) = audit_log.as_mut() {
+            if let Err(e) = audit.process_message(&msg) {
+                eprintln!("Unable to write to audit log: {}", e);
+            }
+        }
     }
 }
 
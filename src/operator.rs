@@ -0,0 +1,89 @@
+// Operators log in at an interface before running scenarios, so that every
+// run and log entry records who was at the station.  If an OperatorRoster
+// is set, only the operators it lists may log in.  The roster has one
+// operator per line: an id, optionally followed by the operator's name.
+// Blank lines and lines beginning with '#' are ignored.
+//
+//   # id    name
+//   alice   Alice Smith
+//   bob
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq)]
+pub enum LoginError {
+    /// The operator id is empty or contains whitespace.
+    InvalidId(String),
+
+    /// The roster doesn't list this operator.
+    NotOnRoster(String),
+
+    /// The roster couldn't be read.
+    RosterUnreadable(PathBuf, String),
+
+    /// Operators can't change while a scenario is running.
+    ScenarioRunning,
+
+    /// RequireLogin is set, and nobody has logged in.
+    NotLoggedIn,
+}
+
+impl fmt::Display for LoginError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoginError::InvalidId(id) => write!(f, "invalid operator id \"{}\"", id),
+            LoginError::NotOnRoster(id) => write!(f, "{} is not on the operator roster", id),
+            LoginError::RosterUnreadable(path, err) => {
+                write!(f, "unable to read operator roster {}: {}", path.display(), err)
+            }
+            LoginError::ScenarioRunning => write!(f, "a scenario is running"),
+            LoginError::NotLoggedIn => write!(f, "no operator is logged in"),
+        }
+    }
+}
+
+/// Make sure `id` can be written in a log or protocol line as one word.
+pub fn check_id(id: &str) -> Result<(), LoginError> {
+    if id.is_empty() || id.contains(char::is_whitespace) {
+        return Err(LoginError::InvalidId(id.to_owned()));
+    }
+    Ok(())
+}
+
+pub struct Roster {
+    /// Each operator's id, and their name if the roster gives one
+    operators: BTreeMap<String, Option<String>>,
+}
+
+impl Roster {
+    pub fn from_path(path: &Path) -> io::Result<Roster> {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+        Ok(Self::from_string(&contents))
+    }
+
+    pub fn from_string(contents: &str) -> Roster {
+        let mut operators = BTreeMap::new();
+        for line in contents.lines().map(|l| l.trim()) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.splitn(2, char::is_whitespace);
+            let id = fields.next().unwrap().to_owned();
+            let name = fields.next().map(|n| n.trim().to_owned());
+            operators.insert(id, name);
+        }
+        Roster { operators }
+    }
+
+    /// Look up an operator, returning their name if the roster gives one.
+    pub fn find(&self, id: &str) -> Result<Option<String>, LoginError> {
+        match self.operators.get(id) {
+            None => Err(LoginError::NotOnRoster(id.to_owned())),
+            Some(name) => Ok(name.clone()),
+        }
+    }
+}
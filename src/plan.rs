@@ -0,0 +1,414 @@
+// Describes how a scenario's tests were resolved: the order they will run
+// in, why each one is part of the scenario, which tests were substituted
+// for virtual names through Provides, and which tests are assumed to have
+// passed, along with the command each test will run.  The same information can be rendered as a Graphviz graph.
+use std::collections::HashMap;
+use std::fmt;
+
+use expand::{Defines, Expansion};
+use layers::ConfigLayers;
+use unit::{DependencyKind, DependencyLink, UnitIncompatibleReason, UnitKind, UnitName};
+use units::jig::JigDescription;
+use units::scenario::{ProviderSelection, ScenarioDescription};
+use units::test::TestDescription;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeKind {
+    Requires,
+    Suggests,
+
+    /// Only affects the order, through After= or Before=
+    After,
+}
+
+impl EdgeKind {
+    pub fn as_str(&self) -> &str {
+        match *self {
+            EdgeKind::Requires => "Requires",
+            EdgeKind::Suggests => "Suggests",
+            EdgeKind::After => "After",
+        }
+    }
+}
+
+/// A test that `from` depends on, and the name `from` referred to it by.
+#[derive(Debug, Clone)]
+pub struct PlanEdge {
+    pub from: UnitName,
+    pub to: UnitName,
+    pub kind: EdgeKind,
+    pub via: Option<UnitName>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlanStep {
+    pub test: UnitName,
+
+    /// The test is listed in the scenario's Assume, and won't be run
+    pub assumed: bool,
+
+    /// The test is listed in the scenario's Tests
+    pub requested: bool,
+
+    /// Tests that have this one in their Requires
+    pub required_by: Vec<UnitName>,
+
+    /// Tests that have this one in their Suggests
+    pub suggested_by: Vec<UnitName>,
+
+    /// Virtual names that this test was chosen to satisfy
+    pub provides: Vec<UnitName>,
+
+    /// The command the test will run, once expanded
+    pub command: Option<String>,
+}
+
+impl PlanStep {
+    /// Why the test is part of the plan, as a list of short phrases.
+    pub fn reasons(&self) -> Vec<String> {
+        let mut reasons = vec![];
+        if self.assumed {
+            reasons.push("assumed".to_owned());
+        }
+        if self.requested {
+            reasons.push("requested".to_owned());
+        }
+        if !self.required_by.is_empty() {
+            reasons.push(format!("required by {}", join(&self.required_by)));
+        }
+        if !self.suggested_by.is_empty() {
+            reasons.push(format!("suggested by {}", join(&self.suggested_by)));
+        }
+        if !self.provides.is_empty() {
+            reasons.push(format!("provides {}", join(&self.provides)));
+        }
+        reasons
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub scenario: UnitName,
+
+    /// Every test the scenario resolved to, in order, including assumed tests
+    pub steps: Vec<PlanStep>,
+
+    pub edges: Vec<PlanEdge>,
+
+    /// Names in the plan that more than one test could have provided
+    pub providers: Vec<ProviderSelection>,
+}
+
+/// Walk the dependencies of a scenario looking for the first missing test
+/// or circular dependency, and describe how it was reached.  Used to
+/// explain a failure to resolve the scenario.
+pub fn diagnose(scenario: &ScenarioDescription,
+                tests: &[TestDescription],
+                jig: Option<&JigDescription>)
+                -> Option<UnitIncompatibleReason> {
+    let by_name: HashMap<&UnitName, &TestDescription> = tests.iter().map(|t| (t.id(), t)).collect();
+    let providers: HashMap<UnitName, UnitName> = scenario.select_providers(tests, jig)
+        .into_iter()
+        .map(|(name, selection)| (name, selection.provider))
+        .collect();
+
+    let mut done = vec![];
+    let mut chain = vec![];
+    for name in scenario.tests() {
+        let link = DependencyLink {
+            kind: DependencyKind::Tests,
+            name: name.clone(),
+            provider: None,
+        };
+        if let Some(reason) = diagnose_link(scenario, &by_name, &providers, link, &mut chain, &mut done) {
+            return Some(reason);
+        }
+    }
+    None
+}
+
+fn diagnose_link(scenario: &ScenarioDescription,
+                 by_name: &HashMap<&UnitName, &TestDescription>,
+                 providers: &HashMap<UnitName, UnitName>,
+                 mut link: DependencyLink,
+                 chain: &mut Vec<DependencyLink>,
+                 done: &mut Vec<UnitName>)
+                 -> Option<UnitIncompatibleReason> {
+    let test = match by_name.get(&link.name) {
+        Some(t) => *t,
+        None => match providers.get(&link.name).and_then(|p| by_name.get(p)) {
+            Some(t) => {
+                link.provider = Some(t.id().clone());
+                *t
+            }
+            None => {
+                chain.push(link);
+                return Some(UnitIncompatibleReason::MissingDependency(scenario.id().clone(), chain.clone()));
+            }
+        },
+    };
+
+    let id = test.id();
+    if let Some(start) = chain.iter().position(|l| l.provider.as_ref().unwrap_or(&l.name) == id) {
+        chain.push(link);
+        return Some(UnitIncompatibleReason::CircularDependency(scenario.id().clone(), chain.clone(), start));
+    }
+    // Assumed tests aren't run, so their dependencies don't matter.
+    if done.contains(id) || scenario.assumptions().contains(id) {
+        return None;
+    }
+
+    chain.push(link);
+    let deps = test.requires().iter().map(|r| (DependencyKind::Requires, r))
+        .chain(test.suggests().iter().map(|s| (DependencyKind::Suggests, s)));
+    for (kind, name) in deps {
+        let next = DependencyLink {
+            kind,
+            name: name.clone(),
+            provider: None,
+        };
+        if let Some(reason) = diagnose_link(scenario, by_name, providers, next, chain, done) {
+            return Some(reason);
+        }
+    }
+    chain.pop();
+    done.push(id.clone());
+    None
+}
+
+fn join(names: &[UnitName]) -> String {
+    names.iter().map(|n| format!("{}", n)).collect::<Vec<String>>().join(", ")
+}
+
+fn push_unique(list: &mut Vec<UnitName>, name: &UnitName) {
+    if !list.contains(name) {
+        list.push(name.clone());
+    }
+}
+
+impl Plan {
+    /// Resolve `scenario` against the given set of tests, as it would be on `jig`.
+    pub fn build(scenario: &ScenarioDescription,
+                 tests: &[TestDescription],
+                 jig: Option<&JigDescription>)
+                 -> Result<Plan, UnitIncompatibleReason> {
+        let (sequence, _) = scenario.resolve_tests(tests, jig)?;
+        let selections = scenario.select_providers(tests, jig);
+
+        let descriptions: HashMap<&UnitName, &TestDescription> = tests.iter().map(|t| (t.id(), t)).collect();
+        let mut steps: Vec<PlanStep> = sequence
+            .iter()
+            .map(|name| PlanStep {
+                test: name.clone(),
+                assumed: scenario.assumptions().contains(name),
+                requested: false,
+                required_by: vec![],
+                suggested_by: vec![],
+                provides: vec![],
+                command: None,
+            })
+            .collect();
+
+        // Figure out which test in the plan a name refers to, which is
+        // either the test itself or the test chosen to provide it.
+        let resolve = |name: &UnitName| -> Option<usize> {
+            if let Some(idx) = sequence.iter().position(|t| t == name) {
+                return Some(idx);
+            }
+            let provider = &selections.get(name)?.provider;
+            sequence.iter().position(|t| t == provider)
+        };
+
+        for name in scenario.tests() {
+            if let Some(idx) = resolve(name) {
+                steps[idx].requested = true;
+                if name != &steps[idx].test {
+                    push_unique(&mut steps[idx].provides, name);
+                }
+            }
+        }
+
+        let mut edges = vec![];
+        for test_name in &sequence {
+            // Assumed tests are never run, so their dependencies aren't pulled in.
+            if scenario.assumptions().contains(test_name) {
+                continue;
+            }
+            let description = match descriptions.get(test_name) {
+                Some(d) => d,
+                None => continue,
+            };
+            let deps = description.requires().iter().map(|r| (EdgeKind::Requires, r))
+                .chain(description.suggests().iter().map(|s| (EdgeKind::Suggests, s)));
+            for (kind, name) in deps {
+                let idx = match resolve(name) {
+                    Some(i) => i,
+                    None => continue,
+                };
+                let target = steps[idx].test.clone();
+                match kind {
+                    EdgeKind::Requires => push_unique(&mut steps[idx].required_by, test_name),
+                    EdgeKind::Suggests => push_unique(&mut steps[idx].suggested_by, test_name),
+                    EdgeKind::After => (),
+                }
+                let via = if name != &target {
+                    push_unique(&mut steps[idx].provides, name);
+                    Some(name.clone())
+                } else {
+                    None
+                };
+                edges.push(PlanEdge {
+                    from: test_name.clone(),
+                    to: target,
+                    kind,
+                    via,
+                });
+            }
+
+            // Ordering edges point from the test that runs later to the one
+            // that runs earlier, just like dependencies.
+            for idx in description.after().iter().filter_map(&resolve) {
+                edges.push(PlanEdge {
+                    from: test_name.clone(),
+                    to: sequence[idx].clone(),
+                    kind: EdgeKind::After,
+                    via: None,
+                });
+            }
+            for idx in description.before().iter().filter_map(&resolve) {
+                edges.push(PlanEdge {
+                    from: sequence[idx].clone(),
+                    to: test_name.clone(),
+                    kind: EdgeKind::After,
+                    via: None,
+                });
+            }
+        }
+
+        let mut providers: Vec<ProviderSelection> = selections
+            .values()
+            .filter(|s| s.candidates.len() > 1 && sequence.contains(&s.provider))
+            .cloned()
+            .collect();
+        providers.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(Plan {
+            scenario: scenario.id().clone(),
+            steps,
+            edges,
+            providers,
+        })
+    }
+
+    /// Load the units in the configuration directories and resolve the
+    /// named scenario, optionally as it would be on the named jig.
+    pub fn from_layers(layers: &ConfigLayers,
+                       defines: &Defines,
+                       scenario_name: &UnitName,
+                       jig_name: Option<&UnitName>)
+                       -> Result<Plan, String> {
+        for dir in layers.dirs() {
+            dir.read_dir().map_err(|e| format!("unable to read {}: {}", dir.display(), e))?;
+        }
+        let scenario = match layers.effective(scenario_name) {
+            Some(path) => ScenarioDescription::from_path(&path).map_err(|e| format!("unable to load {}: {}", path.display(), e))?,
+            None => return Err(format!("scenario {} not found", scenario_name)),
+        };
+        let jig = match jig_name {
+            None => None,
+            Some(name) => match layers.effective(name) {
+                Some(path) => Some(JigDescription::from_path(&path).map_err(|e| format!("unable to load {}: {}", path.display(), e))?),
+                None => return Err(format!("jig {} not found", name)),
+            },
+        };
+        let tests: Vec<TestDescription> = layers
+            .units()
+            .into_iter()
+            .filter(|source| source.name.kind() == &UnitKind::Test)
+            .filter_map(|source| TestDescription::from_path(&source.path).ok())
+            .collect();
+        let mut plan = Plan::build(&scenario, &tests, jig.as_ref()).map_err(|e| format!("unable to resolve {}: {}", scenario_name, e))?;
+        plan.expand_commands(&tests, |test| {
+            let config_dir = layers.effective(test).and_then(|path| path.parent().map(|p| p.to_owned()));
+            Expansion::new(test, config_dir, defines)
+                .with_jig(jig_name)
+                .with_scenario(Some(scenario_name))
+        });
+        Ok(plan)
+    }
+
+    /// Fill in the command that each test will run, expanded by the
+    /// Expansion that `expansion` returns for it.  A command that can't be
+    /// expanded is shown as it was written, along with the reason.
+    pub fn expand_commands<F>(&mut self, tests: &[TestDescription], expansion: F)
+        where F: Fn(&UnitName) -> Expansion
+    {
+        for step in self.steps.iter_mut().filter(|s| !s.assumed) {
+            let test = match tests.iter().find(|t| t.id() == &step.test) {
+                Some(t) => t,
+                None => continue,
+            };
+            step.command = Some(match expansion(&step.test).expand(test.exec_start()) {
+                Ok(cmd) => cmd,
+                Err(e) => format!("{} ({})", test.exec_start(), e),
+            });
+        }
+    }
+
+    /// Render the plan as a Graphviz graph.  Edges point from a test to the
+    /// tests it depends on.
+    pub fn to_dot(&self) -> String {
+        let mut dot = format!("digraph \"{}\" {{\n", self.scenario);
+        dot.push_str(&format!("    \"{}\" [shape=box];\n", self.scenario));
+
+        let mut position = 0;
+        for step in &self.steps {
+            if step.assumed {
+                dot.push_str(&format!("    \"{}\" [label=\"{} (assumed)\", style=dashed];\n", step.test, step.test));
+            } else {
+                position += 1;
+                dot.push_str(&format!("    \"{}\" [label=\"{}. {}\"];\n", step.test, position, step.test));
+            }
+        }
+        for step in self.steps.iter().filter(|s| s.requested) {
+            dot.push_str(&format!("    \"{}\" -> \"{}\" [label=\"Tests\"];\n", self.scenario, step.test));
+        }
+        for edge in &self.edges {
+            let label = match edge.via {
+                Some(ref v) => format!("{} {}", edge.kind.as_str(), v),
+                None => edge.kind.as_str().to_owned(),
+            };
+            let style = match edge.kind {
+                EdgeKind::Requires => "solid",
+                EdgeKind::Suggests => "dashed",
+                EdgeKind::After => "dotted",
+            };
+            dot.push_str(&format!("    \"{}\" -> \"{}\" [label=\"{}\", style={}];\n", edge.from, edge.to, label, style));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}:", self.scenario)?;
+        let mut position = 0;
+        for step in &self.steps {
+            let index = if step.assumed {
+                " - ".to_owned()
+            } else {
+                position += 1;
+                format!("{:2}.", position)
+            };
+            writeln!(f, "  {} {}  ({})", index, step.test, step.reasons().join("; "))?;
+            if let Some(ref command) = step.command {
+                writeln!(f, "      $ {}", command)?;
+            }
+        }
+        for selection in &self.providers {
+            writeln!(f, "  {}", selection)?;
+        }
+        Ok(())
+    }
+}
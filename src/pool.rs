@@ -0,0 +1,367 @@
+// Pools hand out values that must be unique to each device, such as serial
+// numbers and MAC addresses.  A pool is defined by a NAME.pool file in a
+// config directory, and scenarios list the pools they need with Pools=.
+//
+// When a scenario starts, one value is checked out of each of its pools.
+// If the scenario passes the values are committed, and otherwise they are
+// returned to be issued again.  Every step is appended to a journal in the
+// state directory and synced to disk before the value is used, so a value
+// checked out by a run that never finished (because exclave crashed, say)
+// is never issued again.
+//
+//   <state-dir>/pools/<name>.journal
+extern crate systemd_parser;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use unit::UnitDescriptionError;
+
+use self::systemd_parser::items::{DirectiveEntry, UnitDirective};
+
+/// Pool files are named NAME.pool.
+pub const POOL_EXTENSION: &str = "pool";
+
+/// Directory within the state directory that holds pool journals.
+pub const JOURNAL_DIRECTORY: &str = "pools";
+
+#[derive(Debug)]
+pub enum PoolError {
+    /// There is no NAME.pool file in any config directory.
+    NotFound(String),
+
+    /// The pool file couldn't be read, or doesn't make sense.
+    Invalid(String /* pool */, String /* reason */),
+
+    /// Every value in the pool has been issued.
+    Exhausted(String),
+
+    /// The pool's journal couldn't be read or written.
+    Journal(String, io::Error),
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolError::NotFound(pool) => write!(f, "pool {} not found (no {}.{} file)", pool, pool, POOL_EXTENSION),
+            PoolError::Invalid(pool, reason) => write!(f, "pool {} is invalid: {}", pool, reason),
+            PoolError::Exhausted(pool) => write!(f, "pool {} has no values left", pool),
+            PoolError::Journal(pool, e) => write!(f, "unable to update journal for pool {}: {}", pool, e),
+        }
+    }
+}
+
+/// Where a pool's values come from.
+#[derive(Debug, Clone, PartialEq)]
+enum Source {
+    /// Decimal numbers, zero-padded to `width` digits.
+    Decimal(u64, u64, usize),
+
+    /// MAC addresses, as 48-bit numbers.
+    Mac(u64, u64),
+
+    /// Values given one by one.
+    List(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolDescription {
+    name: String,
+
+    /// Environment variable that tests find the value in
+    variable: String,
+
+    /// Prepended to values from decimal ranges, e.g. "SN"
+    prefix: String,
+
+    sources: Vec<Source>,
+}
+
+/// A value checked out of a pool for a run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Allocation {
+    pub pool: String,
+    pub variable: String,
+    pub value: String,
+    pub run: String,
+}
+
+/// The environment variable a pool's value is given in, if the pool
+/// doesn't name one: EXCLAVE_POOL_ followed by the pool name in capitals.
+pub fn default_variable(pool: &str) -> String {
+    let name: String = pool
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    format!("EXCLAVE_POOL_{}", name)
+}
+
+fn parse_mac(s: &str) -> Option<u64> {
+    let octets: Vec<&str> = s.split(':').collect();
+    if octets.len() != 6 {
+        return None;
+    }
+    let mut mac = 0;
+    for octet in octets {
+        if octet.len() != 2 {
+            return None;
+        }
+        mac = (mac << 8) | u64::from_str_radix(octet, 16).ok()?;
+    }
+    Some(mac)
+}
+
+fn format_mac(mac: u64) -> String {
+    let octets: Vec<String> = (0..6).rev().map(|i| format!("{:02x}", (mac >> (i * 8)) & 0xff)).collect();
+    octets.join(":")
+}
+
+fn parse_range(range: &str) -> Result<Source, String> {
+    let (start, end) = match range.find('-') {
+        Some(pos) => (&range[..pos], &range[pos + 1..]),
+        None => return Err(format!("{}: expected START-END", range)),
+    };
+    let source = if start.contains(':') {
+        match (parse_mac(start), parse_mac(end)) {
+            (Some(s), Some(e)) => Source::Mac(s, e),
+            _ => return Err(format!("{}: expected MAC addresses such as 02:00:00:00:00:00", range)),
+        }
+    } else {
+        match (start.parse(), end.parse()) {
+            (Ok(s), Ok(e)) => {
+                let width = if start.starts_with('0') { start.len() } else { 0 };
+                Source::Decimal(s, e, width)
+            }
+            _ => return Err(format!("{}: expected whole numbers", range)),
+        }
+    };
+    match source {
+        Source::Decimal(s, e, _) | Source::Mac(s, e) if s > e => Err(format!("{}: range ends before it starts", range)),
+        s => Ok(s),
+    }
+}
+
+fn split_list(s: &str) -> Vec<&str> {
+    s.split(|c: char| c == ',' || c.is_whitespace()).filter(|i| !i.is_empty()).collect()
+}
+
+impl PoolDescription {
+    pub fn from_path(name: &str, path: &Path) -> Result<PoolDescription, PoolError> {
+        let mut contents = String::with_capacity(8192);
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut contents))
+            .map_err(|e| PoolError::Invalid(name.to_owned(), format!("{}", e)))?;
+        Self::from_string(name, &contents)
+    }
+
+    pub fn from_string(name: &str, contents: &str) -> Result<PoolDescription, PoolError> {
+        let invalid = |reason: String| PoolError::Invalid(name.to_owned(), reason);
+        let pool_file = systemd_parser::parse_string(contents)
+            .map_err(|e| invalid(format!("{}", UnitDescriptionError::from(e))))?;
+        if !pool_file.has_category("Pool") {
+            return Err(invalid(format!("{}", UnitDescriptionError::MissingSection("Pool".to_owned()))));
+        }
+
+        let mut pool = PoolDescription {
+            name: name.to_owned(),
+            variable: default_variable(name),
+            prefix: "".to_owned(),
+            sources: vec![],
+        };
+        // Directives come back in no particular order, so keep the Values
+        // aside to issue after the Ranges.  A repeated directive keeps its
+        // lines in file order, and every Range and Values line counts.
+        let mut values = vec![];
+        for entry in pool_file.lookup_by_category("Pool") {
+            let directives: Vec<&UnitDirective> = match entry {
+                DirectiveEntry::Solo(directive) => vec![directive],
+                DirectiveEntry::Many(directives) => directives.iter().collect(),
+            };
+            for directive in directives {
+                let value = directive.value().unwrap_or("");
+                match directive.key() {
+                    "Variable" if !value.is_empty() => pool.variable = value.to_owned(),
+                    "Prefix" if value.contains(char::is_whitespace) => {
+                        return Err(invalid("Prefix may not contain spaces".to_owned()))
+                    }
+                    "Prefix" => pool.prefix = value.to_owned(),
+                    "Range" => for range in split_list(value) {
+                        pool.sources.push(parse_range(range).map_err(&invalid)?);
+                    },
+                    "Values" => values.extend(split_list(value).into_iter().map(|v| v.to_owned())),
+                    _ => (),
+                }
+            }
+        }
+        if !values.is_empty() {
+            pool.sources.push(Source::List(values));
+        }
+        if pool.sources.is_empty() {
+            return Err(invalid("no Range or Values given".to_owned()));
+        }
+        Ok(pool)
+    }
+
+    pub fn variable(&self) -> &String {
+        &self.variable
+    }
+
+    /// The first value, in the order the pool lists them, that isn't in `used`.
+    fn first_available(&self, used: &HashSet<String>) -> Option<String> {
+        for source in &self.sources {
+            match *source {
+                Source::Decimal(start, end, width) => for n in start..=end {
+                    let value = format!("{}{:0width$}", self.prefix, n, width = width);
+                    if !used.contains(&value) {
+                        return Some(value);
+                    }
+                },
+                Source::Mac(start, end) => for n in start..=end {
+                    let value = format_mac(n);
+                    if !used.contains(&value) {
+                        return Some(value);
+                    }
+                },
+                Source::List(ref values) => {
+                    if let Some(value) = values.iter().find(|v| !used.contains(*v)) {
+                        return Some(value.clone());
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Every value a pool has ever issued, read back from its journal.
+pub struct PoolJournal {
+    pool: String,
+    path: PathBuf,
+
+    /// Values that must not be issued again
+    used: HashSet<String>,
+
+    /// Values that were checked out but not yet committed or returned, and
+    /// the run that checked them out
+    pending: HashMap<String, String>,
+}
+
+impl PoolJournal {
+    pub fn open(state_dir: &Path, pool: &str) -> Result<PoolJournal, PoolError> {
+        let path = state_dir.join(JOURNAL_DIRECTORY).join(format!("{}.journal", pool));
+        let mut journal = PoolJournal {
+            pool: pool.to_owned(),
+            path,
+            used: HashSet::new(),
+            pending: HashMap::new(),
+        };
+        let file = match File::open(&journal.path) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(journal),
+            Err(e) => return Err(PoolError::Journal(pool.to_owned(), e)),
+        };
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| PoolError::Journal(pool.to_owned(), e))?;
+            let fields: Vec<&str> = line.split(' ').collect();
+            // A line cut short by a crash was never acted on, so it can be ignored.
+            if fields.len() != 3 {
+                continue;
+            }
+            let (value, run) = (fields[1].to_owned(), fields[2].to_owned());
+            match fields[0] {
+                "checkout" => {
+                    journal.used.insert(value.clone());
+                    journal.pending.insert(value, run);
+                }
+                "commit" | "void" => {
+                    journal.pending.remove(&value);
+                }
+                "return" => {
+                    journal.used.remove(&value);
+                    journal.pending.remove(&value);
+                }
+                _ => (),
+            }
+        }
+        Ok(journal)
+    }
+
+    /// Values checked out but never committed or returned, with the run
+    /// that checked each one out.
+    pub fn pending(&self) -> Vec<(String, String)> {
+        let mut pending: Vec<(String, String)> = self.pending.iter().map(|(v, r)| (v.clone(), r.clone())).collect();
+        pending.sort();
+        pending
+    }
+
+    /// Append a record, and make sure it's on disk before going any further.
+    fn append(&mut self, op: &str, value: &str, run: &str) -> Result<(), PoolError> {
+        let pool = self.pool.clone();
+        let journal_error = |e| PoolError::Journal(pool.clone(), e);
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(&journal_error)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&self.path)
+            .map_err(&journal_error)?;
+
+        // If a crash cut the last line short, finish it off so this record
+        // isn't mistaken for part of it.
+        let len = file.metadata().map_err(&journal_error)?.len();
+        let mut line = format!("{} {} {}\n", op, value, run);
+        if len > 0 {
+            let mut last = [0; 1];
+            file.seek(SeekFrom::Start(len - 1)).and_then(|_| file.read_exact(&mut last)).map_err(&journal_error)?;
+            if last[0] != b'\n' {
+                line.insert(0, '\n');
+            }
+        }
+        file.write_all(line.as_bytes()).and_then(|_| file.sync_data()).map_err(&journal_error)
+    }
+
+    /// Take the next free value from `pool` for `run`.
+    pub fn check_out(&mut self, pool: &PoolDescription, run: &str) -> Result<Allocation, PoolError> {
+        let value = match pool.first_available(&self.used) {
+            None => return Err(PoolError::Exhausted(self.pool.clone())),
+            Some(v) => v,
+        };
+        self.append("checkout", &value, run)?;
+        self.used.insert(value.clone());
+        self.pending.insert(value.clone(), run.to_owned());
+        Ok(Allocation {
+            pool: self.pool.clone(),
+            variable: pool.variable().clone(),
+            value,
+            run: run.to_owned(),
+        })
+    }
+
+    /// Mark a checked-out value as used for good.
+    pub fn commit(&mut self, allocation: &Allocation) -> Result<(), PoolError> {
+        self.append("commit", &allocation.value, &allocation.run)?;
+        self.pending.remove(&allocation.value);
+        Ok(())
+    }
+
+    /// Put a checked-out value back, so that it will be issued again.
+    pub fn give_back(&mut self, allocation: &Allocation) -> Result<(), PoolError> {
+        self.append("return", &allocation.value, &allocation.run)?;
+        self.pending.remove(&allocation.value);
+        self.used.remove(&allocation.value);
+        Ok(())
+    }
+
+    /// Give up on a value checked out by a run that never finished.  It
+    /// may have been programmed into a device, so it stays used.
+    pub fn void(&mut self, value: &str, run: &str) -> Result<(), PoolError> {
+        self.append("void", value, run)?;
+        self.pending.remove(value);
+        Ok(())
+    }
+}
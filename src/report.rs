@@ -0,0 +1,175 @@
+// Renders a self-contained HTML summary of a run from the results stored
+// in its artifact directory.  Reports are written when a scenario finishes,
+// and can be regenerated later with the "report" subcommand.
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use artifacts::{self, RunManifest, TestRecord};
+
+/// Name of the report file in each run directory.
+pub const REPORT_NAME: &str = "report.html";
+
+/// Name of the file tests may write measurements to, one per line, as
+/// "name<TAB>value" or "name<TAB>value<TAB>unit".
+pub const MEASUREMENTS_NAME: &str = "measurements.tsv";
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }
+th { background: #eee; }
+.pass { color: #fff; background: #2a2; }
+.fail { color: #fff; background: #c22; }
+.skip { color: #000; background: #ec3; }
+.pending, .running { color: #000; background: #ddd; }
+pre { background: #f6f6f6; padding: 0.5em; overflow-x: auto; }
+";
+
+/// Write the report for the run stored in `run_dir`, returning its path.
+pub fn write_report(run_dir: &Path) -> io::Result<PathBuf> {
+    let manifest = RunManifest::load(run_dir)?;
+    let path = run_dir.join(REPORT_NAME);
+    let mut file = File::create(&path)?;
+    file.write_all(render(&manifest, run_dir).as_bytes())?;
+    Ok(path)
+}
+
+/// Regenerate reports for `path`, which may either be a single run
+/// directory or an artifact directory containing many runs.
+pub fn regenerate(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if path.join(artifacts::MANIFEST_NAME).is_file() {
+        return Ok(vec![write_report(path)?]);
+    }
+    let mut reports = vec![];
+    for run_dir in artifacts::completed_runs(path)? {
+        reports.push(write_report(&run_dir)?);
+    }
+    Ok(reports)
+}
+
+fn render(manifest: &RunManifest, run_dir: &Path) -> String {
+    let mut html = String::new();
+    let result = match manifest.result {
+        Some(200) => "pass",
+        Some(_) => "fail",
+        None => "pending",
+    };
+
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<title>{} - {}</title>\n", escape(&manifest.scenario_name), escape(&manifest.run_id)));
+    html.push_str(&format!("<style>{}</style>\n</head>\n<body>\n", STYLE));
+    html.push_str(&format!("<h1>{}</h1>\n", escape(&manifest.scenario_name)));
+
+    html.push_str("<table>\n");
+    row(&mut html, "Run", &escape(&manifest.run_id));
+    row(&mut html, "Jig", &escape(manifest.jig.as_deref().unwrap_or("(none)")));
+    row(&mut html, "Serial", &escape(manifest.serial.as_deref().unwrap_or("(not declared)")));
+    row(&mut html, "Operator", &escape(manifest.operator.as_deref().unwrap_or("(not logged in)")));
+    row(&mut html, "Scenario", &escape(&manifest.scenario));
+    row(&mut html, "Started", &utc_time(manifest.started));
+    row(&mut html, "Duration", &duration(manifest.duration_ms));
+    row(&mut html, "Result", &format!("<span class=\"{}\">{}</span> {}", result, result, escape(&manifest.message)));
+    html.push_str("</table>\n");
+
+    html.push_str("<h2>Tests</h2>\n<table>\n");
+    html.push_str("<tr><th>#</th><th>Test</th><th>State</th><th>Duration</th><th>Message</th><th>Measurements</th></tr>\n");
+    for (idx, test) in manifest.tests.iter().enumerate() {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}<br><small>{}</small></td><td class=\"{}\">{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            idx + 1,
+            escape(&test.name),
+            escape(&test.id),
+            test.state.as_str(),
+            test.state.as_str(),
+            duration(test.duration_ms),
+            escape(&test.message),
+            measurements_html(&run_dir.join(&test.directory)),
+        ));
+    }
+    html.push_str("</table>\n");
+
+    html.push_str("<h2>Logs</h2>\n");
+    for test in &manifest.tests {
+        html.push_str(&log_html(run_dir, test));
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn row(html: &mut String, name: &str, value: &str) {
+    html.push_str(&format!("<tr><th>{}</th><td>{}</td></tr>\n", name, value));
+}
+
+/// Format seconds since the epoch as e.g. "2017-07-14 02:40:00 UTC".
+pub fn utc_time(secs: u64) -> String {
+    // Convert days since the epoch to a date in the proleptic Gregorian
+    // calendar, counting years from March so that leap days come last.
+    let days = secs / 86400 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            year, month, day, secs / 3600 % 24, secs / 60 % 60, secs % 60)
+}
+
+fn duration(ms: Option<u64>) -> String {
+    match ms {
+        None => "-".to_owned(),
+        Some(ms) => format!("{}.{:03} s", ms / 1000, ms % 1000),
+    }
+}
+
+fn measurements_html(test_dir: &Path) -> String {
+    let file = match File::open(test_dir.join(MEASUREMENTS_NAME)) {
+        Err(_) => return String::new(),
+        Ok(f) => f,
+    };
+    let mut html = String::new();
+    for line in BufReader::new(file).lines().map_while(Result::ok) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 2 {
+            continue;
+        }
+        html.push_str(&format!("{}: {}", escape(fields[0]), escape(fields[1])));
+        if let Some(unit) = fields.get(2) {
+            html.push_str(&format!(" {}", escape(unit)));
+        }
+        html.push_str("<br>");
+    }
+    html
+}
+
+fn log_html(run_dir: &Path, test: &TestRecord) -> String {
+    let mut html = String::new();
+    for name in &["stdout.log", "stderr.log"] {
+        let mut contents = String::new();
+        if let Ok(mut f) = File::open(run_dir.join(&test.directory).join(name)) {
+            f.read_to_string(&mut contents).ok();
+        }
+        if contents.is_empty() {
+            continue;
+        }
+        html.push_str(&format!(
+            "<details><summary>{} ({})</summary>\n<pre>{}</pre>\n</details>\n",
+            escape(&test.name),
+            name,
+            escape(&contents)
+        ));
+    }
+    html
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
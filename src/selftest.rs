@@ -0,0 +1,135 @@
+// A jig may name a self-test scenario, which is run against a known-good
+// ("golden") unit to prove the fixture itself is working.  Until that
+// scenario has passed within the jig's validity period, every other
+// scenario is refused.  When each jig last passed is kept in the state
+// directory so that restarting exclave doesn't reopen the gate.
+//
+//   <state-dir>/selftest.json
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use state;
+use unit::UnitName;
+
+/// Name of the self-test state file within the state directory.
+pub const STATE_FILE: &str = "selftest.json";
+
+#[derive(Debug, PartialEq)]
+pub enum GateError {
+    /// The jig's self-test has never passed.
+    NeverPassed(UnitName /* jig */, UnitName /* self-test scenario */),
+
+    /// The jig's self-test passed, but too long ago.
+    Expired(UnitName /* jig */, UnitName /* self-test scenario */, Duration /* since passing */, Duration /* validity */),
+
+    /// The state file couldn't be read, so the gate stays shut.
+    StateUnreadable(PathBuf, String),
+}
+
+impl fmt::Display for GateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GateError::NeverPassed(jig, scenario) => write!(
+                f,
+                "jig {} has not passed its self-test; run {} first",
+                jig, scenario
+            ),
+            GateError::Expired(jig, scenario, ago, validity) => write!(
+                f,
+                "jig {} last passed its self-test {} ago, but it is only good for {}; run {} again",
+                jig,
+                brief_duration(ago),
+                brief_duration(validity),
+                scenario
+            ),
+            GateError::StateUnreadable(path, err) => {
+                write!(f, "unable to read self-test state {}: {}", path.display(), err)
+            }
+        }
+    }
+}
+
+/// Render a duration as e.g. "2d 3h", "9h 5m" or "42s".
+pub fn brief_duration(d: &Duration) -> String {
+    let secs = d.as_secs();
+    let (days, hours, mins) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, mins)
+    } else if mins > 0 {
+        format!("{}m {}s", mins, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+pub struct SelfTestGate {
+    path: PathBuf,
+
+    /// When each jig last passed its self-test, in seconds since the epoch
+    passed: BTreeMap<String, u64>,
+}
+
+impl SelfTestGate {
+    /// Load the gate state from `state_dir`.  A missing file means no jig
+    /// has passed yet.
+    pub fn open(state_dir: &Path) -> io::Result<SelfTestGate> {
+        let path = state_dir.join(STATE_FILE);
+        let passed = state::load(&path)?.unwrap_or_default();
+        Ok(SelfTestGate {
+            path,
+            passed,
+        })
+    }
+
+    /// Like `open()`, but reports failure as a reason to keep the gate shut.
+    pub fn open_for_check(state_dir: &Path) -> Result<SelfTestGate, GateError> {
+        SelfTestGate::open(state_dir)
+            .map_err(|e| GateError::StateUnreadable(state_dir.join(STATE_FILE), format!("{}", e)))
+    }
+
+    pub fn last_passed(&self, jig: &UnitName) -> Option<SystemTime> {
+        self.passed
+            .get(&jig.to_string())
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(*secs))
+    }
+
+    /// Record that `jig` passed its self-test at `when`.
+    pub fn record_pass(&mut self, jig: &UnitName, when: SystemTime) -> io::Result<()> {
+        let secs = when.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        self.passed.insert(jig.to_string(), secs);
+        state::save(&self.path, &self.passed)
+    }
+
+    /// Record that `jig` failed its self-test, closing the gate.
+    pub fn record_fail(&mut self, jig: &UnitName) -> io::Result<()> {
+        if self.passed.remove(&jig.to_string()).is_none() {
+            return Ok(());
+        }
+        state::save(&self.path, &self.passed)
+    }
+
+    /// Whether scenarios other than `scenario` may run on `jig` at `now`.
+    pub fn check(
+        &self,
+        jig: &UnitName,
+        scenario: &UnitName,
+        validity: Duration,
+        now: SystemTime,
+    ) -> Result<(), GateError> {
+        let passed = match self.last_passed(jig) {
+            None => return Err(GateError::NeverPassed(jig.clone(), scenario.clone())),
+            Some(t) => t,
+        };
+        // A pass recorded in the future (e.g. the clock went backwards) counts as just now.
+        let ago = now.duration_since(passed).unwrap_or(Duration::from_secs(0));
+        if ago > validity {
+            return Err(GateError::Expired(jig.clone(), scenario.clone(), ago, validity));
+        }
+        Ok(())
+    }
+}
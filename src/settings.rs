@@ -0,0 +1,268 @@
+// Global settings that apply to exclave as a whole, rather than to any one
+// unit, are read from an exclave.conf file with an [Exclave] section.  The
+// file is watched, and changes take effect without restarting.  Any setting
+// may also be given on the command line, which takes precedence over the
+// file.
+extern crate humantime;
+extern crate notify;
+extern crate systemd_parser;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+use unit::UnitDescriptionError;
+use unitbroadcaster::{UnitBroadcaster, UnitEvent};
+
+use self::humantime::{parse_duration, DurationError};
+use self::notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use self::systemd_parser::items::DirectiveEntry;
+
+/// The name of the settings file that is looked for in the first
+/// configuration directory, if no other file is given.
+pub const SETTINGS_FILE: &str = "exclave.conf";
+
+/// Each setting is None if it wasn't given, so that the default is used.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Settings {
+    /// Timeout for tests that don't have their own Timeout, and for jig TestPrograms
+    pub test_timeout: Option<Duration>,
+
+    /// How long to wait after asking a program to stop before killing it
+    pub terminate_timeout: Option<Duration>,
+
+    /// Directories to search for programs in, in place of exclave's own PATH
+    pub paths: Option<Vec<PathBuf>>,
+
+    /// Number of log lines the terminal shows
+    pub log_history: Option<usize>,
+
+    /// Identifies this test station to tests, interfaces, and loggers
+    pub station_id: Option<String>,
+
+    /// Addresses that control servers, such as interfaces, should listen on
+    pub listen: Option<Vec<String>>,
+
+    /// Where to find sysfs when matching jigs by their USB devices or DMI product name
+    pub sysfs_root: Option<PathBuf>,
+
+    /// Where to keep state that must survive a restart, such as when each jig's self-test last passed
+    pub state_directory: Option<PathBuf>,
+
+    /// Whether an operator must log in before scenarios may be started
+    pub require_login: Option<bool>,
+
+    /// File listing the operators who may log in
+    pub operator_roster: Option<PathBuf>,
+
+    /// Directory to store the output and files of each run in
+    pub artifact_directory: Option<PathBuf>,
+
+    /// Number of runs to keep in the artifact directory
+    pub keep_runs: Option<usize>,
+}
+
+pub fn parse_time(time_str: &str) -> Result<Duration, DurationError> {
+    if let Ok(val) = time_str.parse::<u64>() {
+        Ok(Duration::from_secs(val))
+    } else {
+        parse_duration(time_str)
+    }
+}
+
+/// Parse a PATH-style list of directories.
+pub fn parse_paths(paths: &str) -> Vec<PathBuf> {
+    env::split_paths(paths).filter(|p| !p.as_os_str().is_empty()).collect()
+}
+
+/// Parse a comma- or space-separated list of HOST:PORT addresses.
+pub fn parse_listen(addresses: &str) -> Result<Vec<String>, String> {
+    let mut listen = vec![];
+    for address in addresses.split(|c: char| c == ',' || c.is_whitespace()).filter(|a| !a.is_empty()) {
+        let valid = match address.rfind(':') {
+            Some(pos) => pos > 0 && address[pos + 1..].parse::<u16>().is_ok(),
+            None => false,
+        };
+        if !valid {
+            return Err(address.to_owned());
+        }
+        listen.push(address.to_owned());
+    }
+    Ok(listen)
+}
+
+impl Settings {
+    pub fn new() -> Settings {
+        Default::default()
+    }
+
+    pub fn from_path(path: &Path) -> Result<Settings, UnitDescriptionError> {
+        let mut contents = String::with_capacity(8192);
+        File::open(path)?.read_to_string(&mut contents)?;
+        Self::from_string(&contents)
+    }
+
+    pub fn from_string(contents: &str) -> Result<Settings, UnitDescriptionError> {
+        let settings_file = systemd_parser::parse_string(contents)?;
+        if !settings_file.has_category("Exclave") {
+            return Err(UnitDescriptionError::MissingSection("Exclave".to_owned()));
+        }
+
+        let mut settings = Settings::new();
+        for entry in settings_file.lookup_by_category("Exclave") {
+            if let DirectiveEntry::Solo(directive) = entry {
+                let value = match directive.value() {
+                    Some(v) => v,
+                    None => continue,
+                };
+                match directive.key() {
+                    "TestTimeout" => settings.test_timeout = Some(parse_time(value)?),
+                    "TerminateTimeout" => settings.terminate_timeout = Some(parse_time(value)?),
+                    "Path" => settings.paths = Some(parse_paths(value)),
+                    "LogHistory" => {
+                        settings.log_history = match value.parse() {
+                            Ok(n) => Some(n),
+                            Err(_) => {
+                                return Err(UnitDescriptionError::InvalidNumber(
+                                    "Exclave".to_owned(),
+                                    "LogHistory".to_owned(),
+                                    value.to_owned(),
+                                ))
+                            }
+                        }
+                    }
+                    "StationId" => settings.station_id = Some(value.to_owned()),
+                    "Listen" => {
+                        settings.listen = match parse_listen(value) {
+                            Ok(l) => Some(l),
+                            Err(address) => {
+                                return Err(UnitDescriptionError::InvalidValue(
+                                    "Exclave".to_owned(),
+                                    "Listen".to_owned(),
+                                    address,
+                                    vec!["HOST:PORT".to_owned()],
+                                ))
+                            }
+                        }
+                    }
+                    "SysfsRoot" => settings.sysfs_root = Some(PathBuf::from(value)),
+                    "StateDirectory" => settings.state_directory = Some(PathBuf::from(value)),
+                    "RequireLogin" => {
+                        settings.require_login = match value.to_lowercase().as_ref() {
+                            "true" | "yes" | "1" => Some(true),
+                            "false" | "no" | "0" => Some(false),
+                            other => {
+                                return Err(UnitDescriptionError::InvalidValue(
+                                    "Exclave".to_owned(),
+                                    "RequireLogin".to_owned(),
+                                    other.to_owned(),
+                                    vec!["true".to_owned(), "false".to_owned()],
+                                ))
+                            }
+                        }
+                    }
+                    "OperatorRoster" => settings.operator_roster = Some(PathBuf::from(value)),
+                    "ArtifactDirectory" => settings.artifact_directory = Some(PathBuf::from(value)),
+                    "KeepRuns" => {
+                        settings.keep_runs = match value.parse() {
+                            Ok(n) => Some(n),
+                            Err(_) => {
+                                return Err(UnitDescriptionError::InvalidNumber(
+                                    "Exclave".to_owned(),
+                                    "KeepRuns".to_owned(),
+                                    value.to_owned(),
+                                ))
+                            }
+                        }
+                    }
+                    &_ => (),
+                }
+            }
+        }
+        Ok(settings)
+    }
+
+    /// These settings, with any given in `overrides` taking their place.
+    pub fn merged(&self, overrides: &Settings) -> Settings {
+        Settings {
+            test_timeout: overrides.test_timeout.or(self.test_timeout),
+            terminate_timeout: overrides.terminate_timeout.or(self.terminate_timeout),
+            paths: overrides.paths.clone().or_else(|| self.paths.clone()),
+            log_history: overrides.log_history.or(self.log_history),
+            station_id: overrides.station_id.clone().or_else(|| self.station_id.clone()),
+            listen: overrides.listen.clone().or_else(|| self.listen.clone()),
+            sysfs_root: overrides.sysfs_root.clone().or_else(|| self.sysfs_root.clone()),
+            state_directory: overrides.state_directory.clone().or_else(|| self.state_directory.clone()),
+            require_login: overrides.require_login.or(self.require_login),
+            operator_roster: overrides.operator_roster.clone().or_else(|| self.operator_roster.clone()),
+            artifact_directory: overrides.artifact_directory.clone().or_else(|| self.artifact_directory.clone()),
+            keep_runs: overrides.keep_runs.or(self.keep_runs),
+        }
+    }
+}
+
+/// Read the settings file, if there is one, and lay `overrides` over it.
+pub fn load(path: &Path, overrides: &Settings) -> Result<Settings, UnitDescriptionError> {
+    if !path.exists() {
+        return Ok(overrides.clone());
+    }
+    Ok(Settings::from_path(path)?.merged(overrides))
+}
+
+/// Broadcast the settings now, and again each time the settings file changes.
+/// If the file can't be read, the previous settings are kept.
+pub fn watch(path: &Path, overrides: Settings, broadcaster: &UnitBroadcaster) {
+    match load(path, &overrides) {
+        Ok(settings) => broadcaster.broadcast(&UnitEvent::SettingsChanged(settings)),
+        Err(e) => broadcaster.log("settings", format!("unable to read {}: {}", path.display(), e)),
+    }
+
+    // Watch the directory rather than the file, since editors often replace
+    // the file rather than writing to it.
+    let dir = match path.parent() {
+        Some(d) => d.to_owned(),
+        None => return,
+    };
+    let path = path.to_owned();
+    let broadcaster = broadcaster.clone();
+    let (watcher_tx, watcher_rx) = channel();
+    let mut watcher: RecommendedWatcher = match Watcher::new(watcher_tx, Duration::from_secs(0)) {
+        Ok(w) => w,
+        Err(e) => {
+            broadcaster.log("settings", format!("unable to watch {}: {}", path.display(), e));
+            return;
+        }
+    };
+    if let Err(e) = watcher.watch(&dir, RecursiveMode::NonRecursive) {
+        broadcaster.log("settings", format!("unable to watch {}: {}", path.display(), e));
+        return;
+    }
+
+    thread::spawn(move || {
+        // Keep the watcher alive for as long as the thread runs.
+        let _watcher = watcher;
+        while let Ok(event) = watcher_rx.recv() {
+            let changed = match event {
+                notify::DebouncedEvent::Create(ref p)
+                | notify::DebouncedEvent::Write(ref p)
+                | notify::DebouncedEvent::Remove(ref p) => p == &path,
+                notify::DebouncedEvent::Rename(ref old, ref new) => old == &path || new == &path,
+                _ => false,
+            };
+            if !changed {
+                continue;
+            }
+            match load(&path, &overrides) {
+                Ok(settings) => {
+                    broadcaster.log("settings", format!("reloaded {}", path.display()));
+                    broadcaster.broadcast(&UnitEvent::SettingsChanged(settings));
+                }
+                Err(e) => broadcaster.log("settings", format!("unable to read {}, keeping previous settings: {}", path.display(), e)),
+            }
+        }
+    });
+}
@@ -0,0 +1,38 @@
+// State that must survive a restart is kept as JSON files in the state
+// directory.  Files are replaced by writing a new copy and renaming it over
+// the old one, so that a crash part way through leaves either the old state
+// or the new state, and never half of each.
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json;
+
+/// Read a state file, or return None if it doesn't exist yet.
+pub fn load<T: DeserializeOwned>(path: &Path) -> io::Result<Option<T>> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(Some(serde_json::from_reader(file)?))
+}
+
+/// Replace a state file, creating the state directory if needed.
+pub fn save<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = Path::new(&tmp_name);
+    {
+        let mut file = File::create(tmp_path)?;
+        serde_json::to_writer_pretty(&mut file, value)?;
+        file.write_all(b"\n")?;
+        file.sync_all()?;
+    }
+    fs::rename(tmp_path, path)
+}
@@ -2,7 +2,7 @@ extern crate console;
 
 use self::console::Term;
 use unit::{UnitKind, UnitName};
-use unitbroadcaster::{LogEntry, UnitCategoryStatus, UnitEvent, UnitStatus};
+use unitbroadcaster::{LogEntry, LogType, UnitCategoryStatus, UnitEvent, UnitStatus};
 use std::collections::{BTreeMap, HashMap};
 use std::sync::mpsc::Receiver;
 use std::thread;
@@ -99,10 +99,19 @@ impl TerminalInterface {
                     self.logs.remove(0);
                 }
             }
+            UnitEvent::SettingsChanged(ref settings) => {
+                self.log_history = settings.log_history.unwrap_or(MAX_LOG_HISTORY);
+                if self.logs.len() > self.log_history {
+                    let excess = self.logs.len() - self.log_history;
+                    self.logs.drain(..excess);
+                }
+            }
+            UnitEvent::Lifecycle(_) => (),
             UnitEvent::RescanStart => (),
             UnitEvent::RescanFinish => (),
             UnitEvent::RescanRequest => (),
             UnitEvent::Shutdown => (),
+            UnitEvent::Exit => (),
             UnitEvent::ManagerRequest(_) => (),
         }
 
@@ -121,8 +130,11 @@ impl TerminalInterface {
             UnitEvent::RescanStart => println!("Started unit recsan..."),
             UnitEvent::RescanFinish => println!("Finished rescanning units"),
             UnitEvent::Shutdown => println!("Shutting down"),
+            UnitEvent::Exit => (),
             UnitEvent::Log(log) => println!("{}", log),
+            UnitEvent::Lifecycle(entry) => println!("{}", entry),
             UnitEvent::ManagerRequest(_) => (),
+            UnitEvent::SettingsChanged(_) => (),
         };
     }
 
@@ -174,7 +186,13 @@ impl TerminalInterface {
         }
         for log_line in self.logs.iter() {
             line_count = line_count + 1;
-            self.terminal.write_line(format!("  {}", log_line).as_str()).expect("Unable to write log");
+            let styled = match log_line.kind() {
+                &LogType::Debug => console::style(format!("{}", log_line)).dim(),
+                &LogType::Info => console::style(format!("{}", log_line)),
+                &LogType::Warn => console::style(format!("{}", log_line)).yellow(),
+                &LogType::Error => console::style(format!("{}", log_line)).red(),
+            };
+            self.terminal.write_line(format!("  {}", styled).as_str()).expect("Unable to write log");
         }
         self.terminal.flush().expect("Couldn't redraw screen");
         self.last_line_count = line_count;
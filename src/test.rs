@@ -1,16 +1,43 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::{Arc, Mutex};
-use std::sync::mpsc::Receiver;
-use std::time::Duration;
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, UNIX_EPOCH};
 use std::thread;
 
-use config::Config;
-use unit::{UnitKind, UnitName};
-use unitbroadcaster::{UnitBroadcaster, UnitEvent};
+use serde_json;
+
+use artifacts::{self, RunArtifacts, RunManifest, TestRecord, TestState};
+use audit::{self, AuditError, AuditLog};
+use config::{self, Config};
+use detect;
+use dropin;
+use expand::{Defines, ExpandError, Expansion};
+use history::{self, RunRecord};
+use inherit;
+use layers::{ConfigLayers, Precedence};
+use lint;
+use operator::{LoginError, Roster};
+use plan::Plan;
+use pool::{PoolDescription, PoolJournal};
+use quiesce::Quiesce;
+use report;
+use selftest::{GateError, SelfTestGate};
+use settings::Settings;
+use unit::{UnitDescriptionError, UnitKind, UnitName};
+use unitbroadcaster::{LogEntry, LogSource, LogType, UnitBroadcaster, UnitEvent, UnitStatus, UnitStatusEvent};
+use unitlibrary::UnitLibrary;
 //use unitwatcher::UnitWatcher;
-//use unitloader::UnitLoader;
-use unitmanager::UnitManager;
+use unitloader::UnitLoader;
+use unitmanager::{ManagerControlMessage, ManagerControlMessageContents, UnitManager};
+use wear::{Thresholds, WearCounters, WearError};
 use units::interface::{Interface, InterfaceDescription};
-use units::jig::{Jig, JigDescription};
+use units::jig::{self, Jig, JigChoice, JigDescription};
 use units::logger::{Logger, LoggerDescription};
 use units::scenario::{Scenario, ScenarioDescription};
 use units::test::{Test, TestDescription};
@@ -65,7 +92,7 @@ fn setup_exclave(timeout: Option<Duration>) -> Exclave {
     }
 }
 
-fn add_unit(exclave: &Exclave, name: UnitName, unit_text: &str) -> Result<(), ()> {
+fn add_unit(exclave: &Exclave, name: UnitName, unit_text: &str) -> Result<(), UnitDescriptionError> {
     match *name.kind() {
         UnitKind::Test => {
             let desc = TestDescription::from_string(unit_text, name)?;
@@ -80,6 +107,31 @@ fn add_unit(exclave: &Exclave, name: UnitName, unit_text: &str) -> Result<(), ()
     Ok(())
 }
 
+/// A directory for one test to work in, which is removed when it goes out
+/// of scope, even if the test fails.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let dir = env::temp_dir().join(format!("exclave-{}-{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
+
 #[test]
 fn load_dependency() {
     let exclave = setup_exclave(None);
@@ -87,3 +139,982 @@ fn load_dependency() {
     add_unit(&exclave, UnitName::from_str("linux", "jig").unwrap(), LINUX_JIG).ok();
     assert!(exclave.manager.jig_is_loaded(&UnitName::from_str("generic", "jig").unwrap()));
 }
+
+#[test]
+fn scenario_needs_required_tests() {
+    let dir = TempDir::new("requires");
+    let scenario_path = dir.join("board.scenario");
+    fs::File::create(&scenario_path).unwrap().write_all(b"[Scenario]\nTests=program\n").unwrap();
+    let scenario = ScenarioDescription::from_path(&scenario_path).unwrap();
+
+    let exclave = setup_exclave(None);
+    add_unit(&exclave, UnitName::from_str("program", "test").unwrap(), "[Test]\nExecStart=true\nRequires=power\nSuggests=erase\n").unwrap();
+    add_unit(&exclave, UnitName::from_str("erase", "test").unwrap(), "[Test]\nExecStart=true\n").unwrap();
+    assert!(exclave.manager.load_scenario(&scenario).is_err());
+
+    add_unit(&exclave, UnitName::from_str("power", "test").unwrap(), "[Test]\nExecStart=true\n").unwrap();
+    let name = exclave.manager.load_scenario(&scenario).unwrap();
+    let order = exclave.manager.get_scenario_named(&name).unwrap().borrow().test_sequence();
+    assert_eq!(order.last(), Some(&UnitName::from_str("program", "test").unwrap()));
+    assert_eq!(order.len(), 3);
+}
+
+#[test]
+fn lint_unit_directory() {
+    let dir = TempDir::new("lint");
+    let units = [
+        ("a.test", "[Test]\nExecStart=true\nRequires=b\nTimout=5\n"),
+        ("b.test", "[Test]\nExecStart=true\nRequires=a\n"),
+        ("c.test", "[Test]\nExecStart=true\n\nRequires=missing\n"),
+        ("d.test", "[Test]\nExecStart=true\nTimeout=soon\n"),
+        ("orphan.test", "[Test]\nExecStart=true\n"),
+        ("board.scenario", "[Scenario]\nTests=a, c\n"),
+    ];
+    for &(name, text) in units.iter() {
+        fs::File::create(dir.join(name)).unwrap().write_all(text.as_bytes()).unwrap();
+    }
+
+    let mut layers = ConfigLayers::new(Precedence::LastWins);
+    layers.add_dir(&dir);
+    let diagnostics: Vec<String> = lint::check(&layers, &Defines::new())
+        .iter()
+        .map(|d| format!("{}", d).replace(&format!("{}/", dir.display()), ""))
+        .collect();
+    assert_eq!(diagnostics, vec![
+        "a.test:3: error: dependency cycle: a.test -> b.test -> a.test",
+        "a.test:4: error: unknown directive 'Timout' in [Test] (did you mean 'Timeout'?)",
+        "board.scenario:2: error: board.scenario \u{2192} a.test requires b.test requires a.test (circular dependency between a.test, b.test)",
+        "c.test:4: error: Requires refers to missing.test, which does not exist and is not provided by any test",
+        "d.test:3: error: invalid value for Timeout: expected number at 0",
+        "orphan.test: warning: orphan.test is not run by any scenario",
+    ]);
+}
+
+#[test]
+fn plan_shows_why_tests_run() {
+    let tests = vec![
+        TestDescription::from_string("[Test]\nExecStart=true\nRequires=swd\n", UnitName::from_str("program", "test").unwrap()).unwrap(),
+        TestDescription::from_string("[Test]\nExecStart=true\nProvides=swd\nSuggests=power\n", UnitName::from_str("openocd", "test").unwrap()).unwrap(),
+        TestDescription::from_string("[Test]\nExecStart=true\n", UnitName::from_str("power", "test").unwrap()).unwrap(),
+    ];
+    let scenario = ScenarioDescription::from_string("[Scenario]\nTests=program\nAssume=power\n",
+                                                    UnitName::from_str("board", "scenario").unwrap()).unwrap();
+
+    let plan = Plan::build(&scenario, &tests, None).unwrap();
+    assert!(plan.steps[0].assumed);
+    assert_eq!(format!("{}", plan), "board.scenario:\n\
+                                     \x20  -  power.test  (assumed; suggested by openocd.test)\n\
+                                     \x20  1. openocd.test  (required by program.test; provides swd.test)\n\
+                                     \x20  2. program.test  (requested)\n");
+    assert!(plan.to_dot().contains("\"program.test\" -> \"openocd.test\" [label=\"Requires swd.test\", style=solid];"));
+}
+
+#[test]
+fn jig_recheck_follows_fixture() {
+    let dir = TempDir::new("recheck");
+    let head = dir.join("bench-head");
+    fs::File::create(&head).unwrap();
+    fs::File::create(dir.join("bench.jig")).unwrap()
+        .write_all(format!("[Jig]\nTestFile={}\nPriority=10\nRecheckInterval=1h\n", head.display()).as_bytes()).unwrap();
+    fs::File::create(dir.join("spare.jig")).unwrap().write_all(b"[Jig]\nName=Spare Jig\n").unwrap();
+
+    let config = Arc::new(Mutex::new(Config::new()));
+    let mut layers = ConfigLayers::new(Precedence::LastWins);
+    layers.add_dir(&dir);
+    config.lock().unwrap().set_layers(layers);
+    let broadcaster = UnitBroadcaster::new();
+    let receiver = broadcaster.subscribe();
+    let mut library = UnitLibrary::new(&broadcaster, &config);
+    let loader = UnitLoader::new(&broadcaster);
+    let mut quiesce = Quiesce::new(Duration::from_millis(50), &broadcaster);
+
+    // Run the main loop until it goes quiet, returning the jigs that were
+    // selected.  Jig files are only reloaded when they really change.
+    let mut run = |rechecking: bool| {
+        let mut selected = vec![];
+        while let Ok(msg) = receiver.recv_timeout(Duration::from_millis(500)) {
+            match msg {
+                UnitEvent::Log(ref entry) if entry.id() == &UnitName::internal("jig") && entry.message().ends_with(": selected") => {
+                    selected.push(entry.message().split(' ').next().unwrap().to_owned());
+                }
+                UnitEvent::Status(UnitStatusEvent { status: UnitStatus::UpdateStarted(_), .. }) => assert!(!rechecking),
+                _ => (),
+            }
+            loader.process_message(&msg);
+            library.process_message(&msg);
+            quiesce.process_message(&msg);
+        }
+        selected
+    };
+
+    for name in &["bench.jig", "spare.jig"] {
+        broadcaster.broadcast(&UnitEvent::Status(UnitStatusEvent::new_added(&dir.join(name)).unwrap()));
+    }
+    assert_eq!(run(false), vec!["bench.jig"]);
+
+    // Nothing has changed, so a recheck leaves the jig alone.
+    let recheck = || UnitEvent::ManagerRequest(ManagerControlMessage::new(&UnitName::internal("jig"),
+                                                                          ManagerControlMessageContents::RecheckJigs));
+    broadcaster.broadcast(&recheck());
+    assert!(run(true).is_empty());
+
+    // Taking the fixture head off switches to the spare jig, and putting it back switches back.
+    fs::remove_file(&head).unwrap();
+    broadcaster.broadcast(&recheck());
+    assert_eq!(run(true), vec!["spare.jig"]);
+    fs::File::create(&head).unwrap();
+    broadcaster.broadcast(&recheck());
+    assert_eq!(run(true), vec!["bench.jig"]);
+}
+
+#[test]
+fn load_scenario_without_selected_jig() {
+    // Picking a provider consults the jig candidates, which must not try to
+    // lock the configuration again while the scenario is being loaded.
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let exclave = setup_exclave(None);
+        add_unit(&exclave, UnitName::from_str("linux", "jig").unwrap(), "[Jig]\nPrefer=openocd-rpi\n").unwrap();
+        add_unit(&exclave, UnitName::from_str("program", "test").unwrap(), "[Test]\nExecStart=true\nRequires=swd\n").unwrap();
+        add_unit(&exclave, UnitName::from_str("openocd-ftdi", "test").unwrap(), "[Test]\nExecStart=true\nProvides=swd\n").unwrap();
+        add_unit(&exclave, UnitName::from_str("openocd-rpi", "test").unwrap(), "[Test]\nExecStart=true\nProvides=swd\n").unwrap();
+        let scenario = ScenarioDescription::from_string("[Scenario]\nTests=program\n",
+                                                        UnitName::from_str("board", "scenario").unwrap()).unwrap();
+        tx.send(exclave.manager.load_scenario(&scenario).is_ok()).unwrap();
+    });
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(true));
+}
+
+#[test]
+fn dependency_errors_show_chain() {
+    let tests = vec![
+        TestDescription::from_string("[Test]\nExecStart=true\nRequires=swd\n", UnitName::from_str("program", "test").unwrap()).unwrap(),
+        TestDescription::from_string("[Test]\nExecStart=true\nProvides=swd\nRequires=power\n", UnitName::from_str("openocd", "test").unwrap()).unwrap(),
+    ];
+    let scenario = ScenarioDescription::from_string("[Scenario]\nTests=program\n",
+                                                    UnitName::from_str("board", "scenario").unwrap()).unwrap();
+
+    let err = scenario.resolve_tests(&tests, None).err().unwrap();
+    assert_eq!(format!("{}", err),
+               "board.scenario \u{2192} program.test requires swd.test (provided by openocd.test) requires power.test (missing)");
+}
+
+#[test]
+fn ordering_and_conflicts() {
+    let test = |name: &str, contents: &str| {
+        TestDescription::from_string(&format!("[Test]\nExecStart=true\n{}", contents), UnitName::from_str(name, "test").unwrap()).unwrap()
+    };
+    let scenario = |tests: &str| {
+        ScenarioDescription::from_string(&format!("[Scenario]\nTests={}\n", tests), UnitName::from_str("board", "scenario").unwrap()).unwrap()
+    };
+    let names = |list: &[&str]| -> Vec<UnitName> { list.iter().map(|n| UnitName::from_str(n, "test").unwrap()).collect() };
+
+    let tests = vec![
+        test("flash", "After=erase\n"),
+        test("erase", ""),
+        test("led", "Before=flash\nAfter=missing\n"),
+        test("factory-reset", "Conflicts=flash\n"),
+    ];
+    let (order, _) = scenario("flash erase led").resolve_tests(&tests, None).unwrap();
+    assert_eq!(order, names(&["erase", "led", "flash"]));
+
+    // After= doesn't pull in a test that isn't already in the scenario.
+    let (order, _) = scenario("flash").resolve_tests(&tests, None).unwrap();
+    assert_eq!(order, names(&["flash"]));
+
+    let err = scenario("flash factory-reset").resolve_tests(&tests, None).err().unwrap();
+    assert_eq!(format!("{}", err), "factory-reset.test conflicts with flash.test, and both would be run");
+
+    let tests = vec![test("a", "After=b\n"), test("b", "After=a\n")];
+    let err = scenario("a b").resolve_tests(&tests, None).err().unwrap();
+    assert_eq!(format!("{}", err), "ordering loop: a.test runs after b.test, which runs after a.test");
+}
+
+#[test]
+fn provider_selection() {
+    let test = |name: &str, contents: &str| {
+        TestDescription::from_string(&format!("[Test]\nExecStart=true\n{}", contents), UnitName::from_str(name, "test").unwrap()).unwrap()
+    };
+    let tests = vec![
+        test("program", "Requires=swd\n"),
+        test("openocd-ftdi", "Provides=swd\n"),
+        test("openocd-olimex", "Provides=swd\nPriority=10\n"),
+        test("openocd-rpi", "Provides=swd\n"),
+    ];
+    let scenario = ScenarioDescription::from_string("[Scenario]\nTests=program\n", UnitName::from_str("board", "scenario").unwrap()).unwrap();
+    let (order, _) = scenario.resolve_tests(&tests, None).unwrap();
+    assert_eq!(order[0], UnitName::from_str("openocd-olimex", "test").unwrap());
+
+    let jig = JigDescription::from_string("[Jig]\nPrefer=openocd-rpi\n", UnitName::from_str("linux", "jig").unwrap()).unwrap();
+    let plan = Plan::build(&scenario, &tests, Some(&jig)).unwrap();
+    assert_eq!(plan.steps[0].test, UnitName::from_str("openocd-rpi", "test").unwrap());
+    assert_eq!(format!("{}", plan.providers[0]),
+               "swd.test is provided by openocd-rpi.test (preferred by linux.jig; also provided by openocd-ftdi.test, openocd-olimex.test)");
+    let (order, _) = scenario.resolve_tests(&tests, Some(&jig)).unwrap();
+    let choices = scenario.provider_choices(&tests, Some(&jig), &order);
+    assert_eq!(choices.len(), 1);
+    assert_eq!(choices[0].provider, UnitName::from_str("openocd-rpi", "test").unwrap());
+    assert!(scenario.provider_choices(&tests, Some(&jig), &order[1..]).is_empty());
+
+    let scenario = ScenarioDescription::from_string("[Scenario]\nTests=program\nPrefer=openocd-ftdi\n",
+                                                    UnitName::from_str("board", "scenario").unwrap()).unwrap();
+    let (order, _) = scenario.resolve_tests(&tests, Some(&jig)).unwrap();
+    assert_eq!(order[0], UnitName::from_str("openocd-ftdi", "test").unwrap());
+}
+
+#[test]
+fn jig_detection_is_deterministic() {
+    let jig = |name: &str| UnitName::from_str(name, "jig").unwrap();
+    let mut candidates = BTreeMap::new();
+    candidates.insert(jig("bench"), (0, None));
+    candidates.insert(jig("alpha"), (0, None));
+    candidates.insert(jig("production"), (10, None));
+    candidates.insert(jig("fixture"), (20, Some("Test file /dev/fixture not present".to_owned())));
+
+    let ranked = jig::rank_jigs(&candidates, None);
+    let names: Vec<&str> = ranked.iter().map(|c| c.name.id().as_str()).collect();
+    assert_eq!(names, vec!["fixture", "production", "alpha", "bench"]);
+    assert_eq!(ranked[1].choice, JigChoice::Selected);
+    assert_eq!(ranked[2].choice, JigChoice::Outranked(jig("production")));
+    assert_eq!(format!("{}", ranked[0]), "fixture.jig (priority 20): rejected: Test file /dev/fixture not present");
+
+    // A pinned jig is used even though it has a lower priority.
+    let ranked = jig::rank_jigs(&candidates, Some(&jig("bench")));
+    assert_eq!(ranked[1].choice, JigChoice::NotPinned(jig("bench")));
+    assert_eq!(ranked[3].choice, JigChoice::Selected);
+
+    let description = JigDescription::from_string("[Jig]\nPriority=-5\n", jig("bench")).unwrap();
+    assert_eq!(description.priority(), &-5);
+    assert_eq!(description.recheck_interval(), &None);
+
+    let description = JigDescription::from_string("[Jig]\nRecheckInterval=30s\n", jig("bench")).unwrap();
+    assert_eq!(description.recheck_interval(), &Some(Duration::from_secs(30)));
+}
+
+#[test]
+fn jig_matchers() {
+    let sysfs = TempDir::new("sysfs");
+    let usb = sysfs.join("bus/usb/devices/1-1");
+    fs::create_dir_all(&usb).unwrap();
+    fs::create_dir_all(sysfs.join("class/dmi/id")).unwrap();
+    fs::File::create(usb.join("idVendor")).unwrap().write_all(b"1d50\n").unwrap();
+    fs::File::create(usb.join("idProduct")).unwrap().write_all(b"6018\n").unwrap();
+    fs::File::create(sysfs.join("class/dmi/id/product_name")).unwrap().write_all(b"Raspberry Pi 3 Model B\n").unwrap();
+
+    let exclave = setup_exclave(None);
+    let mut settings = Settings::new();
+    settings.sysfs_root = Some(sysfs.to_path_buf());
+    exclave.config.lock().unwrap().apply_settings(&settings);
+    let compatible = |contents: &str| {
+        let jig = JigDescription::from_string(&format!("[Jig]\n{}", contents), UnitName::from_str("fixture", "jig").unwrap()).unwrap();
+        let config = exclave.config.lock().unwrap();
+        jig.is_compatible(&exclave.manager, &config).map_err(|e| format!("{}", e))
+    };
+
+    assert_eq!(compatible("MatchUsb=0403:6010 1d50:6018\nMatchDmiProduct=Raspberry*\n"), Ok(()));
+    assert_eq!(compatible("MatchUsb=0403:6010\nMatchDmiProduct=Raspberry*\n"),
+               Err(format!("no USB device 0403:6010 under {}", sysfs.display())));
+    assert_eq!(compatible("MatchMode=any\nMatchUsb=0403:6010\nMatchDmiProduct=Raspberry*\n"), Ok(()));
+    assert_eq!(compatible("MatchEnvironment=PATH=*/*\n"), Ok(()));
+    assert_eq!(compatible("MatchMode=any\nMatchEnvironment=EXCLAVE_NOT_DEFINED\nMatchDmiProduct=*PC*\n"),
+               Err("environment does not have EXCLAVE_NOT_DEFINED=*, and DMI product Raspberry Pi 3 Model B does not match *PC*".to_owned()));
+    assert!(JigDescription::from_string("[Jig]\nMatchUsb=1d50\n", UnitName::from_str("fixture", "jig").unwrap()).is_err());
+    assert_eq!(compatible("TestFile=${EXCLAVE_NOT_DEFINED}/head\n"),
+               Err("unable to expand TestFile: ${EXCLAVE_NOT_DEFINED} is not set in the environment or the defines file".to_owned()));
+
+    // Directives starting with "|" form a group, of which one must match,
+    // and the rest must all match as well.
+    assert_eq!(compatible("MatchDmiProduct=Raspberry*\nMatchUsb=|0403:6010\nMatchEnvironment=|PATH\n"), Ok(()));
+    assert_eq!(compatible("MatchDmiProduct=*PC*\nMatchUsb=|0403:6010\nMatchEnvironment=|PATH\n"),
+               Err("DMI product Raspberry Pi 3 Model B does not match *PC*".to_owned()));
+    assert_eq!(compatible("MatchUsb=|0403:6010\nMatchUsb=|1209:0001\n"),
+               Err(format!("no USB device 0403:6010 under {0}, and no USB device 1209:0001 under {0}", sysfs.display())));
+    assert_eq!(compatible("MatchUsb=1d50:6018\nMatchUsb=0403:6010\n"),
+               Err(format!("no USB device 0403:6010 under {}", sysfs.display())));
+
+    assert!(detect::glob_matches("bench-[0-9]?", "bench-12"));
+    assert!(!detect::glob_matches("bench-[!0-9]*", "bench-12"));
+}
+
+#[test]
+fn jig_lifecycle_hooks() {
+    let exclave = setup_exclave(None);
+    let config = exclave.config.lock().unwrap();
+    let jig = |contents: &str| {
+        Jig::new(&JigDescription::from_string(&format!("[Jig]\nWorkingDirectory=/\n{}", contents), UnitName::from_str("fixture", "jig").unwrap()).unwrap())
+    };
+    let activate = |contents: &str| jig(contents).activate(&exclave.manager, &config).map_err(|e| format!("{}", e));
+
+    assert!(activate("ExecStart=true\n").is_ok());
+    assert_eq!(activate("ExecStart=false\n"), Err("Nonzero return: 1".to_owned()));
+    assert_eq!(activate("ExecStart=sleep 10\nExecStartTimeout=200ms\n"), Err("Timed out after 200ms".to_owned()));
+    assert_eq!(activate("Type=daemon\nExecStart=true\nDaemonReadyText=ready\n"), Err("Daemon exited with 0 before it was ready".to_owned()));
+
+    let mut daemon = jig("Type=daemon\nExecStart=sh -c \"echo ready; sleep 10\"\nDaemonReadyText=^ready\nExecStop=false\n");
+    assert!(daemon.activate(&exclave.manager, &config).is_ok());
+    assert_eq!(daemon.deactivate(&exclave.manager, &config).map_err(|e| format!("{}", e)), Err("Nonzero return: 1".to_owned()));
+
+    // Only changes that leave ExecStart alone can be applied by ExecReload.
+    let reloadable = jig("ExecStart=true\nExecReload=true\n");
+    let changed = |contents: &str| JigDescription::from_string(&format!("[Jig]\nWorkingDirectory=/\n{}", contents), UnitName::from_str("fixture", "jig").unwrap()).unwrap();
+    assert!(reloadable.can_reload(&changed("ExecStart=true\nExecReload=true\nPriority=5\n")));
+    assert!(!reloadable.can_reload(&changed("ExecStart=false\nExecReload=true\n")));
+    assert!(!reloadable.can_reload(&changed("ExecStart=true\n")));
+}
+
+#[test]
+fn jig_capabilities() {
+    let exclave = setup_exclave(None);
+    add_unit(&exclave, UnitName::from_str("fixture", "jig").unwrap(), "[Jig]\nCapabilities=swd, usb-host\n").unwrap();
+    let select = |contents: &str| {
+        let test = TestDescription::from_string(&format!("[Test]\nExecStart=true\n{}", contents), UnitName::from_str("flash", "test").unwrap()).unwrap();
+        Test::new(&test).select(&exclave.manager).map_err(|e| format!("{}", e))
+    };
+
+    assert_eq!(select("RequiresCapability=swd\n"), Ok(()));
+    assert_eq!(select("RequiresCapability=swd,camera,usb-host,gpio\n"),
+               Err("jig fixture.jig is missing capabilities: camera, gpio".to_owned()));
+    assert_eq!(select("Jigs=other\nRequiresCapability=swd\n"), Err("couldn't find any compatible jig".to_owned()));
+
+    // Until a jig is selected, any jig with the capabilities will do.
+    // Afterwards, only the selected jig counts.
+    let bench = UnitName::from_str("bench", "jig").unwrap();
+    let bench_description = JigDescription::from_string("[Jig]\nCapabilities=camera\n", bench.clone()).unwrap();
+    exclave.manager.load_jig(&bench_description).unwrap();
+    assert_eq!(select("RequiresCapability=camera\n"), Ok(()));
+    exclave.manager.select(&UnitName::from_str("fixture", "jig").unwrap());
+    assert_eq!(select("RequiresCapability=camera\n"),
+               Err("jig fixture.jig is missing capabilities: camera".to_owned()));
+
+    let camera = TestDescription::from_string("[Test]\nExecStart=true\nRequiresCapability=camera\n", UnitName::from_str("photo", "test").unwrap()).unwrap();
+    assert!(camera.supports_jig(&bench, &[bench_description]));
+    assert!(!camera.supports_jig(&UnitName::from_str("fixture", "jig").unwrap(), &[]));
+}
+
+#[test]
+fn dropin_fragments_merge() {
+    let dir = TempDir::new("dropin");
+    fs::create_dir_all(dir.join("program.test.d")).unwrap();
+    let files = [
+        ("program.test", "[Test]\nExecStart=true\nRequires=power\nSuggests=led\nTimeout=5\n"),
+        ("program.test.d/10-station.conf", "[Test]\nRequires=swd\nTimeout=30\n"),
+        ("program.test.d/20-no-led.conf", "[Test]\nSuggests=\n"),
+        ("program.test.d/README", "[Test]\nTimeout=1\n"),
+    ];
+    for &(name, text) in files.iter() {
+        fs::File::create(dir.join(name)).unwrap().write_all(text.as_bytes()).unwrap();
+    }
+
+    let test = TestDescription::from_path(&dir.join("program.test")).unwrap();
+    let names = |list: &[&str]| -> Vec<UnitName> { list.iter().map(|n| UnitName::from_str(n, "test").unwrap()).collect() };
+    assert_eq!(test.requires(), &names(&["power", "swd"]));
+    assert!(test.suggests().is_empty());
+    assert_eq!(Test::new(&test).timeout(), &Some(Duration::from_secs(30)));
+
+    assert_eq!(dropin::parent_unit(&dir.join("program.test.d").join("10-station.conf")), Some(dir.join("program.test")));
+    assert_eq!(dropin::parent_unit(&dir.join("program.test")), None);
+}
+
+#[test]
+fn inherit_from_base_unit() {
+    let dir = TempDir::new("inherit");
+    let files = [
+        ("base.test", "[Test]\nTimeout=30\nRequires=power\nWorkingDirectory=/tmp\n"),
+        ("station.test", "[Test]\nInherit=base\nTimeout=60\n"),
+        ("program.test", "[Test]\nInherit=station\nExecStart=true\nRequires=swd\n"),
+        ("orphan.test", "[Test]\nInherit=missing\nExecStart=true\n"),
+        ("loop-a.test", "[Test]\nInherit=loop-b\nExecStart=true\n"),
+        ("loop-b.test", "[Test]\nInherit=loop-a\n"),
+    ];
+    for &(name, text) in files.iter() {
+        fs::File::create(dir.join(name)).unwrap().write_all(text.as_bytes()).unwrap();
+    }
+
+    let test = TestDescription::from_path(&dir.join("program.test")).unwrap();
+    assert_eq!(test.requires(), &vec![UnitName::from_str("swd", "test").unwrap()]);
+    assert_eq!(Test::new(&test).timeout(), &Some(Duration::from_secs(60)));
+    assert_eq!(inherit::bases(&dir.join("program.test")),
+               vec![UnitName::from_str("station", "test").unwrap(), UnitName::from_str("base", "test").unwrap()]);
+
+    assert_eq!(format!("{}", TestDescription::from_path(&dir.join("orphan.test")).err().unwrap()),
+               "inherits from missing.test, which does not exist");
+    assert_eq!(inherit::bases(&dir.join("orphan.test")), vec![UnitName::from_str("missing", "test").unwrap()]);
+    assert_eq!(format!("{}", TestDescription::from_path(&dir.join("loop-a.test")).err().unwrap()),
+               "inheritance loop: loop-a.test inherits from loop-b.test, which inherits from loop-a.test");
+}
+
+#[test]
+fn expand_variables_and_specifiers() {
+    let defines = Defines::from_string("# Station settings\nFIRMWARE_DIR = /opt/firmware\n").unwrap();
+    let test = UnitName::from_str("program", "test").unwrap();
+    let expansion = Expansion::new(&test, Some(PathBuf::from("/etc/exclave")), &defines)
+        .with_jig(Some(&UnitName::from_str("linux", "jig").unwrap()));
+
+    assert_eq!(expansion.expand("${FIRMWARE_DIR}/%j/%n.bin").unwrap(), "/opt/firmware/linux/program.bin");
+    assert_eq!(expansion.expand("%d/flash.sh date +%Y 100%% $${X} $1").unwrap(), "/etc/exclave/flash.sh date +%Y 100% ${X} $1");
+    assert_eq!(expansion.expand("run %s"), Err(ExpandError::MissingSpecifier('s')));
+    assert_eq!(expansion.expand("${EXCLAVE_NOT_DEFINED}"), Err(ExpandError::UndefinedVariable("EXCLAVE_NOT_DEFINED".to_owned())));
+    assert_eq!(expansion.expand("${FIRMWARE_DIR"), Err(ExpandError::UnterminatedVariable("FIRMWARE_DIR".to_owned())));
+    assert!(Defines::from_string("FIRMWARE_DIR\n").is_err());
+}
+
+#[test]
+fn config_layers_shadow() {
+    let base = TempDir::new("layers");
+    let vendor = base.join("vendor");
+    let station = base.join("station");
+    fs::create_dir_all(&vendor).unwrap();
+    fs::create_dir_all(&station).unwrap();
+    for dir in [&vendor, &station].iter() {
+        fs::File::create(dir.join("program.test")).unwrap().write_all(b"[Test]\nExecStart=true\n").unwrap();
+    }
+    fs::File::create(vendor.join("power.test")).unwrap().write_all(b"[Test]\nExecStart=true\n").unwrap();
+    let program = UnitName::from_str("program", "test").unwrap();
+
+    let mut layers = ConfigLayers::new(Precedence::LastWins);
+    layers.add_dir(&vendor);
+    layers.add_dir(&station);
+    assert_eq!(layers.effective(&program), Some(station.join("program.test")));
+    let units = layers.units();
+    assert_eq!(units.len(), 2);
+    assert_eq!(units[0].path, vendor.join("power.test"));
+    assert_eq!(units[1].path, station.join("program.test"));
+    assert_eq!(units[1].shadowed, vec![vendor.join("program.test")]);
+
+    layers.set_precedence(Precedence::FirstWins);
+    assert_eq!(layers.effective(&program), Some(vendor.join("program.test")));
+
+    // Removing the shadowing file brings back the one underneath.
+    fs::remove_file(vendor.join("program.test")).unwrap();
+    assert_eq!(layers.effective(&program), Some(station.join("program.test")));
+}
+
+#[test]
+fn settings_file_and_overrides() {
+    let file = Settings::from_string("[Exclave]\nTestTimeout=2min\nPath=/opt/tools/bin::/usr/bin\nLogHistory=50\nListen=0.0.0.0:8000, [::1]:8001\nArtifactDirectory=runs\nKeepRuns=10\n").unwrap();
+    assert_eq!(file.test_timeout, Some(Duration::from_secs(120)));
+    assert_eq!(file.paths, Some(vec![PathBuf::from("/opt/tools/bin"), PathBuf::from("/usr/bin")]));
+    assert_eq!(file.listen, Some(vec!["0.0.0.0:8000".to_owned(), "[::1]:8001".to_owned()]));
+    assert_eq!(file.terminate_timeout, None);
+
+    let mut overrides = Settings::new();
+    overrides.test_timeout = Some(Duration::from_secs(30));
+    overrides.station_id = Some("line-2".to_owned());
+    overrides.keep_runs = Some(3);
+    let settings = file.merged(&overrides);
+    assert_eq!(settings.test_timeout, Some(Duration::from_secs(30)));
+    assert_eq!(settings.log_history, Some(50));
+    assert_eq!(settings.station_id, Some("line-2".to_owned()));
+
+    let mut config = Config::new();
+    config.apply_settings(&settings);
+    assert_eq!(config.test_timeout(), &Some(Duration::from_secs(30)));
+    assert_eq!(config.terminate_timeout(), &Duration::from_secs(5));
+    let environment = config.environment();
+    assert!(environment.contains(&("PATH".to_owned(), "/opt/tools/bin:/usr/bin".to_owned())));
+    assert!(environment.contains(&("EXCLAVE_STATION_ID".to_owned(), "line-2".to_owned())));
+    assert!(environment.contains(&("EXCLAVE_LISTEN".to_owned(), "0.0.0.0:8000 [::1]:8001".to_owned())));
+    assert!(config.artifact_directory().unwrap().ends_with("runs"));
+    assert_eq!(config.artifact_retention(), &Some(3));
+
+    assert!(Settings::from_string("[Exclave]\nListen=8000\n").is_err());
+    assert!(Settings::from_string("[Exclave]\nLogHistory=lots\n").is_err());
+    assert!(Settings::from_string("[Exclave]\nKeepRuns=lots\n").is_err());
+}
+
+#[cfg(unix)]
+const PRINT_QUOTING: &str = "printenv EXCLAVE_TEST_QUOTING";
+#[cfg(windows)]
+const PRINT_QUOTING: &str = "cmd /C echo %EXCLAVE_TEST_QUOTING%";
+
+#[test]
+fn commands_get_their_own_environment() {
+    let vars = vec![("EXCLAVE_TEST_QUOTING".to_owned(), "it's a \\ \"test\" $HOME".to_owned())];
+    let mut running = config::command_with_environment(PRINT_QUOTING, &vars).start().unwrap();
+    let mut output = String::new();
+    running.read_to_string(&mut output).unwrap();
+    assert_eq!(output.trim_end(), "it's a \\ \"test\" $HOME");
+    assert!(env::var_os("EXCLAVE_TEST_QUOTING").is_none());
+}
+
+#[test]
+fn log_levels_and_sources() {
+    let exclave = setup_exclave(None);
+    let unit = UnitName::from_str("flash", "test").unwrap();
+    let sent = vec![
+        (LogType::Debug, LogSource::Protocol, "probing"),
+        (LogType::Info, LogSource::Stderr, "50% done"),
+        (LogType::Warn, LogSource::Stdout, "voltage\tlow"),
+        (LogType::Error, LogSource::Internal, "gave up"),
+    ];
+    for &(ref kind, ref source, message) in &sent {
+        exclave.broadcaster.broadcast(&UnitEvent::Log(LogEntry::new(unit.clone(), kind.clone(), source.clone(), message.to_owned())));
+    }
+
+    let received: Vec<LogEntry> = exclave.receiver.try_iter().filter_map(|evt| match evt {
+        UnitEvent::Log(entry) => Some(entry),
+        _ => None,
+    }).collect();
+    assert_eq!(received.len(), sent.len());
+    for (entry, &(ref kind, ref source, message)) in received.iter().zip(sent.iter()) {
+        assert_eq!((entry.kind(), entry.source(), entry.message().as_str()), (kind, source, message));
+
+        // The original six fields come first, and the rest follow the message.
+        let tsv = entry.tsv();
+        let fields: Vec<&str> = tsv.split('\t').collect();
+        assert_eq!(fields.len(), 12);
+        assert_eq!(&fields[..3], &[kind.as_str(), "flash", "test"]);
+        assert_eq!(fields[5], message.replace("\t", "\\t"));
+        assert_eq!(fields[6], source.as_str());
+    }
+    assert_eq!(format!("{}", received[1]), "INFO flash.test (stderr): 50% done");
+    assert_eq!(format!("{}", received[3]), "ERROR flash.test: gave up");
+
+    // The LOG verb takes an optional LEVEL=, which defaults to info.
+    let mut words = vec!["LEVEL=WARN".to_owned(), "fan".to_owned(), "stalled".to_owned()];
+    assert_eq!(LogType::from_log_words(&mut words), Ok(LogType::Warn));
+    assert_eq!(words, vec!["fan".to_owned(), "stalled".to_owned()]);
+    let mut words = vec!["error".to_owned(), "in".to_owned(), "flash".to_owned(), "step".to_owned()];
+    assert_eq!(LogType::from_log_words(&mut words), Ok(LogType::Info));
+    assert_eq!(words.len(), 4);
+    let mut words = vec!["LEVEL=loud".to_owned(), "fan".to_owned()];
+    assert!(LogType::from_log_words(&mut words).is_err());
+}
+
+#[test]
+fn log_entries_stamped_with_run() {
+    let exclave = setup_exclave(None);
+    let unit = UnitName::internal("test");
+
+    exclave.broadcaster.broadcast(&UnitEvent::Log(LogEntry::new_info(unit.clone(), "before".to_owned())));
+    let run_id = exclave.broadcaster.begin_run();
+    exclave.broadcaster.broadcast(&UnitEvent::Log(LogEntry::new_info(unit.clone(), "during".to_owned())));
+    exclave.broadcaster.end_run();
+    exclave.broadcaster.broadcast(&UnitEvent::Log(LogEntry::new_info(unit.clone(), "after".to_owned())));
+
+    let runs: Vec<_> = exclave.receiver.try_iter().filter_map(|evt| match evt {
+        UnitEvent::Log(entry) => Some(entry.run().clone().map(|r| r.id)),
+        _ => None,
+    }).collect();
+    assert_eq!(runs, vec![None, Some(run_id), None]);
+
+    // Output that arrives during a run keeps it, even if the run has ended
+    // by the time the manager gets to it.
+    let run_id = exclave.broadcaster.begin_run();
+    let control = exclave.manager.get_control_channel();
+    control.send(ManagerControlMessage::new(&UnitName::from_str("flash", "test").unwrap(),
+                                            ManagerControlMessageContents::Log(LogType::Info, LogSource::Stdout, "late".to_owned()))).unwrap();
+    let request = loop {
+        if let UnitEvent::ManagerRequest(req) = exclave.receiver.recv_timeout(Duration::from_secs(5)).unwrap() {
+            break req;
+        }
+    };
+    exclave.broadcaster.end_run();
+    exclave.manager.process_message(&UnitEvent::ManagerRequest(request));
+    let runs: Vec<_> = exclave.receiver.try_iter().filter_map(|evt| match evt {
+        UnitEvent::Log(entry) => Some(entry.run().clone().map(|r| r.id)),
+        _ => None,
+    }).collect();
+    assert_eq!(runs, vec![Some(run_id)]);
+}
+
+#[test]
+fn serial_stamped_and_history_kept() {
+    let exclave = setup_exclave(None);
+    let unit = UnitName::internal("test");
+    let dir = TempDir::new("history");
+
+    // A serial declared between runs belongs to the next one.
+    assert_eq!(exclave.broadcaster.set_serial("SN0001".to_owned()), None);
+    exclave.broadcaster.begin_run();
+    exclave.broadcaster.broadcast(&UnitEvent::Log(LogEntry::new_info(unit.clone(), "during".to_owned())));
+    exclave.broadcaster.end_run();
+    exclave.broadcaster.begin_run();
+    exclave.broadcaster.broadcast(&UnitEvent::Log(LogEntry::new_info(unit.clone(), "next".to_owned())));
+    exclave.broadcaster.end_run();
+
+    let serials: Vec<_> = exclave.receiver.try_iter().filter_map(|evt| match evt {
+        UnitEvent::Log(entry) => Some(entry.run().clone().and_then(|r| r.serial)),
+        _ => None,
+    }).collect();
+    assert_eq!(serials, vec![Some("SN0001".to_owned()), None]);
+
+    for &(id, serial, result) in &[("1", "SN0001", 200), ("2", "SN0002", 200), ("3", "SN0001", 501)] {
+        history::append(&dir, &RunRecord {
+            run_id: id.to_owned(),
+            serial: Some(serial.to_owned()),
+            operator: None,
+            scenario: "board".to_owned(),
+            jig: None,
+            finished: 0,
+            result: result,
+            message: if result == 200 { "all tests passed" } else { "at least one test failed" }.to_owned(),
+        }).unwrap();
+    }
+    let runs = history::for_serial(&dir, "SN0001").unwrap();
+    assert_eq!(runs.iter().map(|r| r.run_id.as_str()).collect::<Vec<_>>(), vec!["1", "3"]);
+    assert_eq!(history::summary(&runs), "failed 1 of 2 runs, most recently run 3 (at least one test failed)");
+    assert_eq!(history::summary(&history::for_serial(&dir, "SN0002").unwrap()), "passed its only run");
+    assert!(history::for_serial(&dir, "SN0003").unwrap().is_empty());
+}
+
+#[test]
+fn operator_login_settings_and_stamps() {
+    let settings = Settings::from_string("[Exclave]\nRequireLogin=yes\nOperatorRoster=operators\n").unwrap();
+    assert_eq!(settings.require_login, Some(true));
+    assert!(Settings::from_string("[Exclave]\nRequireLogin=sometimes\n").is_err());
+    let mut config = Config::new();
+    config.apply_settings(&settings);
+    assert!(config.require_login());
+    assert_eq!(config.operator_roster().unwrap().file_name().unwrap(), "operators");
+
+    let roster = Roster::from_string("# id name\nalice  Alice Smith\n\nbob\n");
+    assert_eq!(roster.find("alice"), Ok(Some("Alice Smith".to_owned())));
+    assert_eq!(roster.find("bob"), Ok(None));
+    assert_eq!(roster.find("mallory"), Err(LoginError::NotOnRoster("mallory".to_owned())));
+
+    let exclave = setup_exclave(None);
+    let unit = UnitName::internal("test");
+    exclave.broadcaster.set_operator(Some("alice".to_owned()));
+    exclave.broadcaster.broadcast(&UnitEvent::Log(LogEntry::new_info(unit.clone(), "logged in".to_owned())));
+    exclave.broadcaster.set_operator(None);
+    exclave.broadcaster.broadcast(&UnitEvent::Log(LogEntry::new_info(unit.clone(), "logged out".to_owned())));
+    let operators: Vec<_> = exclave.receiver.try_iter().filter_map(|evt| match evt {
+        UnitEvent::Log(entry) => Some(entry.operator().clone()),
+        _ => None,
+    }).collect();
+    assert_eq!(operators, vec![Some("alice".to_owned()), None]);
+}
+
+#[test]
+fn run_artifacts_manifest_and_retention() {
+    let root = TempDir::new("artifacts");
+    let scenario = UnitName::from_str("board", "scenario").unwrap();
+    let test = UnitName::from_str("led", "test").unwrap();
+
+    for run_id in &["1000-000000000", "2000-000000000"] {
+        let mut run = RunArtifacts::create(&root, run_id, &scenario, "Board", None,
+                                           vec![(test.clone(), "LED".to_owned())]).unwrap();
+        run.test_started(&test);
+        run.append_output(&test, &LogSource::Stdout, "blink").unwrap();
+        run.test_finished(&test, 0, "blink");
+        run.finish(200, "all passed").unwrap();
+    }
+
+    let manifest: serde_json::Value = serde_json::from_reader(
+        fs::File::open(root.join("2000-000000000").join(artifacts::MANIFEST_NAME)).unwrap()).unwrap();
+    assert_eq!(manifest["tests"][0]["state"], "Pass");
+    assert_eq!(manifest["tests"][0]["files"][0], "stdout.log");
+
+    let reports = report::regenerate(&root).unwrap();
+    assert_eq!(reports.len(), 2);
+    let mut html = String::new();
+    fs::File::open(&reports[1]).unwrap().read_to_string(&mut html).unwrap();
+    assert!(html.contains("<td class=\"pass\">pass</td>"));
+    assert!(html.contains("<pre>blink\n</pre>"));
+
+    let removed = artifacts::prune_runs(&root, 1).unwrap();
+    assert_eq!(removed, vec![root.join("1000-000000000")]);
+    assert!(root.join("2000-000000000").is_dir());
+}
+
+#[test]
+fn report_renders_manifest() {
+    let run_dir = TempDir::new("report");
+    fs::create_dir_all(run_dir.join("01-adc")).unwrap();
+    fs::File::create(run_dir.join("01-adc").join(report::MEASUREMENTS_NAME)).unwrap()
+        .write_all(b"vdd\t3.31\tV\nnoise\n").unwrap();
+    fs::File::create(run_dir.join("01-adc").join("stdout.log")).unwrap().write_all(b"<reading>\n").unwrap();
+    let test = |id: &str, state: TestState, message: &str| TestRecord {
+        id: id.to_owned(),
+        name: id.to_uppercase(),
+        state: state,
+        message: message.to_owned(),
+        duration_ms: Some(1500),
+        directory: format!("01-{}", id),
+        files: vec![],
+    };
+    let manifest = RunManifest {
+        run_id: "1500000000-000000000".to_owned(),
+        scenario: "board".to_owned(),
+        scenario_name: "Board & Case".to_owned(),
+        jig: None,
+        serial: Some("BRD-1".to_owned()),
+        operator: None,
+        started: 1_500_000_000,
+        finished: Some(1_500_000_003),
+        duration_ms: Some(3250),
+        result: Some(500),
+        message: "at least one test failed".to_owned(),
+        tests: vec![test("adc", TestState::Fail, "vdd < 3.3"), test("led", TestState::Skip, "adc failed")],
+    };
+    serde_json::to_writer(fs::File::create(run_dir.join(artifacts::MANIFEST_NAME)).unwrap(), &manifest).unwrap();
+
+    // A run directory on its own regenerates just its own report.
+    assert_eq!(report::regenerate(&run_dir).unwrap(), vec![run_dir.join(report::REPORT_NAME)]);
+    let mut html = String::new();
+    fs::File::open(run_dir.join(report::REPORT_NAME)).unwrap().read_to_string(&mut html).unwrap();
+    assert!(html.contains("<h1>Board &amp; Case</h1>"));
+    assert!(html.contains("<tr><th>Started</th><td>2017-07-14 02:40:00 UTC</td></tr>"));
+    assert!(html.contains("<tr><th>Duration</th><td>3.250 s</td></tr>"));
+    assert!(html.contains("<tr><th>Jig</th><td>(none)</td></tr>"));
+    assert!(html.contains("<tr><th>Serial</th><td>BRD-1</td></tr>"));
+    assert!(html.contains("<tr><th>Operator</th><td>(not logged in)</td></tr>"));
+    assert!(html.contains("<span class=\"fail\">fail</span> at least one test failed"));
+    assert!(html.contains("<td class=\"fail\">fail</td><td>1.500 s</td><td>vdd &lt; 3.3</td><td>vdd: 3.31 V<br></td>"));
+    assert!(html.contains("<td class=\"skip\">skip</td>"));
+    assert!(html.contains("<pre>&lt;reading&gt;\n</pre>"));
+
+    assert_eq!(report::utc_time(0), "1970-01-01 00:00:00 UTC");
+    assert_eq!(report::utc_time(951_782_400), "2000-02-29 00:00:00 UTC");
+    assert_eq!(report::utc_time(4_102_444_799), "2099-12-31 23:59:59 UTC");
+}
+
+#[test]
+fn self_test_gate_persists() {
+    let dir = TempDir::new("selftest");
+    let jig = UnitName::from_str("fixture", "jig").unwrap();
+    let scenario = UnitName::from_str("golden", "scenario").unwrap();
+    let hour = Duration::from_secs(3600);
+    let passed_at = UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+    let mut gate = SelfTestGate::open(&dir).unwrap();
+    assert_eq!(gate.check(&jig, &scenario, hour, passed_at), Err(GateError::NeverPassed(jig.clone(), scenario.clone())));
+    gate.record_pass(&jig, passed_at).unwrap();
+
+    // A fresh gate sees the pass recorded by the old one.
+    let mut gate = SelfTestGate::open(&dir).unwrap();
+    assert_eq!(gate.check(&jig, &scenario, hour, passed_at + hour / 2), Ok(()));
+    let expired = gate.check(&jig, &scenario, hour, passed_at + hour * 2).unwrap_err();
+    assert_eq!(format!("{}", expired),
+               "jig fixture.jig last passed its self-test 2h 0m ago, but it is only good for 1h 0m; run golden.scenario again");
+
+    gate.record_fail(&jig).unwrap();
+    assert!(SelfTestGate::open(&dir).unwrap().last_passed(&jig).is_none());
+}
+
+#[test]
+fn wear_counters_and_limits() {
+    let dir = TempDir::new("wear");
+    let jig_name = UnitName::from_str("fixture", "jig").unwrap();
+    let usb = UnitName::from_str("usb", "test").unwrap();
+    let jig = JigDescription::from_string("[Jig]\nWearWarning=2 usb=1\nWearLimit=3\n", jig_name.clone()).unwrap();
+    let thresholds = jig.wear_thresholds();
+    assert_eq!(thresholds[&jig_name], Thresholds { warning: Some(2), limit: Some(3) });
+    assert_eq!(thresholds[&usb], Thresholds { warning: Some(1), limit: None });
+    assert!(JigDescription::from_string("[Jig]\nWearLimit=usb=lots\n", jig_name.clone()).is_err());
+
+    let limited = [(&jig_name, &thresholds[&jig_name])];
+    let mut counters = WearCounters::open(&dir).unwrap();
+    for expected in 1..4 {
+        assert_eq!(counters.check(&jig_name, &limited), Ok(()));
+        assert_eq!(counters.increment(&jig_name, &jig_name).unwrap(), expected);
+    }
+    counters.increment(&jig_name, &usb).unwrap();
+
+    // The counts survive a restart, and stay at the limit until reset.
+    let mut counters = WearCounters::open(&dir).unwrap();
+    assert_eq!(counters.check(&jig_name, &limited), Err(WearError::LimitReached(jig_name.clone(), 3, 3)));
+    assert_eq!(counters.reset(&jig_name, Some(&jig_name)).unwrap(), vec!["fixture.jig".to_owned()]);
+    assert_eq!(counters.check(&jig_name, &limited), Ok(()));
+    assert_eq!(WearCounters::open(&dir).unwrap().counters(&jig_name), vec![("usb.test".to_owned(), 1)]);
+}
+
+#[test]
+fn pool_journal_survives_crashes() {
+    let dir = TempDir::new("pool");
+    let serial = PoolDescription::from_string("serial", "[Pool]\nPrefix=SN\nRange=0008-0010\nValues=spare\n").unwrap();
+    let mac = PoolDescription::from_string("mac", "[Pool]\nVariable=MAC\nRange=02:00:00:00:00:ff-02:00:00:00:01:00\n").unwrap();
+    assert_eq!(serial.variable(), "EXCLAVE_POOL_SERIAL");
+    assert!(PoolDescription::from_string("bad", "[Pool]\nRange=10-ff\n").is_err());
+    assert!(PoolDescription::from_string("empty", "[Pool]\nPrefix=SN\n").is_err());
+    let repeated = PoolDescription::from_string("repeated", "[Pool]\nValues=a b\nRange=1-2\nValues=c\nRange=5-5\n").unwrap();
+
+    let mut journal = PoolJournal::open(&dir, "serial").unwrap();
+    let first = journal.check_out(&serial, "run1").unwrap();
+    assert_eq!(first.value, "SN0008");
+    journal.give_back(&first).unwrap();
+    let second = journal.check_out(&serial, "run2").unwrap();
+    assert_eq!(second.value, "SN0008");
+    journal.commit(&second).unwrap();
+    assert_eq!(journal.check_out(&serial, "run3").unwrap().value, "SN0009");
+
+    // run3 never finished, so its value is voided rather than reissued.
+    let mut journal = PoolJournal::open(&dir, "serial").unwrap();
+    assert_eq!(journal.pending(), vec![("SN0009".to_owned(), "run3".to_owned())]);
+    journal.void("SN0009", "run3").unwrap();
+    assert_eq!(journal.check_out(&serial, "run4").unwrap().value, "SN0010");
+    assert_eq!(journal.check_out(&serial, "run5").unwrap().value, "spare");
+    assert_eq!(format!("{}", journal.check_out(&serial, "run6").unwrap_err()), "pool serial has no values left");
+    assert_eq!(PoolJournal::open(&dir, "serial").unwrap().pending().len(), 2);
+
+    let mut journal = PoolJournal::open(&dir, "mac").unwrap();
+    let values: Vec<String> = (0..2).map(|_| journal.check_out(&mac, "run7").unwrap().value).collect();
+    assert_eq!(values, vec!["02:00:00:00:00:ff", "02:00:00:00:01:00"]);
+
+    let mut journal = PoolJournal::open(&dir, "repeated").unwrap();
+    let values: Vec<String> = (0..6).map(|_| journal.check_out(&repeated, "run8").unwrap().value).collect();
+    assert_eq!(values, vec!["1", "2", "5", "a", "b", "c"]);
+}
+
+#[test]
+fn start_unselectable_scenario_is_refused() {
+    let exclave = setup_exclave(None);
+    add_unit(&exclave, UnitName::from_str("bench", "jig").unwrap(), "[Jig]\nName=Bench\n").unwrap();
+    exclave.manager.refresh_defaults();
+    exclave.receiver.try_iter().count();
+
+    let missing = UnitName::from_str("missing", "scenario").unwrap();
+    exclave.manager.process_message(&UnitEvent::ManagerRequest(ManagerControlMessage::new(
+        &UnitName::internal("test"), ManagerControlMessageContents::StartScenario(Some(missing)))));
+    assert!(exclave.broadcaster.run_stamp().is_none());
+    let events: Vec<UnitEvent> = exclave.receiver.try_iter().collect();
+    assert!(!events.iter().any(|evt| matches!(*evt, UnitEvent::Lifecycle(_))));
+    assert!(events.iter().any(|evt| match *evt {
+        UnitEvent::Log(ref entry) => entry.message() == "unable to start missing.scenario: scenario could not be selected",
+        _ => false,
+    }));
+}
+
+#[test]
+fn jig_stop_output_comes_before_exit() {
+    let exclave = setup_exclave(None);
+    add_unit(&exclave, UnitName::from_str("bench", "jig").unwrap(), "[Jig]\nName=Bench\nExecStop=echo stopped\n").unwrap();
+    exclave.manager.refresh_defaults();
+    exclave.broadcaster.broadcast(&UnitEvent::Shutdown);
+
+    let mut messages = vec![];
+    loop {
+        let msg = exclave.receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        match msg {
+            UnitEvent::Exit => break,
+            UnitEvent::Log(ref entry) => messages.push(entry.message().clone()),
+            _ => (),
+        }
+        exclave.manager.process_message(&msg);
+    }
+    assert!(messages.contains(&"stopped".to_owned()));
+    assert_eq!(exclave.receiver.recv_timeout(Duration::from_secs(5)).err(), Some(mpsc::RecvTimeoutError::Disconnected));
+}
+
+#[test]
+fn audit_log_detects_tampering() {
+    let dir = TempDir::new("audit");
+    let log_path = dir.join("audit.log");
+    let key_path = dir.join("audit.key");
+    let anchor_path = audit::default_anchor_path(&log_path);
+    assert_eq!(anchor_path, dir.join("audit.log.anchor"));
+    fs::File::create(&key_path).unwrap().write_all(b"secret").unwrap();
+
+    {
+        let mut audit = AuditLog::open(&log_path, &key_path, &anchor_path, 2).unwrap();
+        for msg in &["one", "two", "three"] {
+            let entry = LogEntry::new_info(UnitName::internal("test"), msg.to_string());
+            audit.process_message(&UnitEvent::Log(entry)).unwrap();
+        }
+        audit.process_message(&UnitEvent::Exit).unwrap();
+    }
+    // Reopening continues the existing chain.
+    {
+        let mut audit = AuditLog::open(&log_path, &key_path, &anchor_path, 2).unwrap();
+        let entry = LogEntry::new_info(UnitName::internal("test"), "four".to_owned());
+        audit.process_message(&UnitEvent::Log(entry)).unwrap();
+        audit.process_message(&UnitEvent::Exit).unwrap();
+    }
+    let summary = audit::verify(&log_path, &key_path, &anchor_path).unwrap();
+    assert_eq!(summary.records, 7);
+    assert_eq!(summary.checkpoints, 3);
+
+    let mut original = String::new();
+    fs::File::open(&log_path).unwrap().read_to_string(&mut original).unwrap();
+
+    // Edit a message.
+    fs::File::create(&log_path).unwrap().write_all(original.replace("two", "TWO").as_bytes()).unwrap();
+    match audit::verify(&log_path, &key_path, &anchor_path) {
+        Err(AuditError::BadHash(2, 1)) => (),
+        other => panic!("unexpected result: {:?}", other.map(|s| s.records)),
+    }
+
+    // Drop the final checkpoint, which the anchor still records.
+    let lines: Vec<&str> = original.lines().collect();
+    fs::File::create(&log_path).unwrap().write_all(lines[..lines.len() - 1].join("\n").as_bytes()).unwrap();
+    match audit::verify(&log_path, &key_path, &anchor_path) {
+        Err(AuditError::Truncated(6, 7)) => (),
+        other => panic!("unexpected result: {:?}", other.map(|s| s.records)),
+    }
+
+    // Cut the log back to an earlier checkpoint, which leaves a valid chain
+    // that no longer reaches the anchor.
+    for &(keep, records) in &[(5, 5), (3, 3)] {
+        let mut truncated = lines[..keep].join("\n");
+        truncated.push('\n');
+        fs::File::create(&log_path).unwrap().write_all(truncated.as_bytes()).unwrap();
+        match audit::verify(&log_path, &key_path, &anchor_path) {
+            Err(AuditError::Truncated(found, 7)) if found == records => (),
+            other => panic!("unexpected result: {:?}", other.map(|s| s.records)),
+        }
+        // Nor can the truncated log be continued.
+        match AuditLog::open(&log_path, &key_path, &anchor_path, 2) {
+            Err(AuditError::Truncated(found, 7)) if found == records => (),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("truncated log was reopened"),
+        }
+    }
+
+    // Removing the anchor doesn't help, and neither does editing it.
+    fs::File::create(&log_path).unwrap().write_all(original.as_bytes()).unwrap();
+    let mut anchor = String::new();
+    fs::File::open(&anchor_path).unwrap().read_to_string(&mut anchor).unwrap();
+    fs::remove_file(&anchor_path).unwrap();
+    match audit::verify(&log_path, &key_path, &anchor_path) {
+        Err(AuditError::MissingAnchor(_)) => (),
+        other => panic!("unexpected result: {:?}", other.map(|s| s.records)),
+    }
+    fs::File::create(&anchor_path).unwrap().write_all(anchor.replace("\"records\": 7", "\"records\": 5").as_bytes()).unwrap();
+    match audit::verify(&log_path, &key_path, &anchor_path) {
+        Err(AuditError::BadAnchor(_, _)) => (),
+        other => panic!("unexpected result: {:?}", other.map(|s| s.records)),
+    }
+    fs::File::create(&anchor_path).unwrap().write_all(anchor.as_bytes()).unwrap();
+    assert_eq!(audit::verify(&log_path, &key_path, &anchor_path).unwrap().records, 7);
+
+    // A copy of the anchor taken earlier still verifies the records it
+    // covers after the log has grown.
+    let copied_anchor_path = dir.join("copied.anchor");
+    fs::copy(&anchor_path, &copied_anchor_path).unwrap();
+    {
+        let mut audit = AuditLog::open(&log_path, &key_path, &anchor_path, 2).unwrap();
+        audit.process_message(&UnitEvent::Exit).unwrap();
+    }
+    assert_eq!(audit::verify(&log_path, &key_path, &anchor_path).unwrap().records, 8);
+    assert_eq!(audit::verify(&log_path, &key_path, &copied_anchor_path).unwrap().records, 8);
+
+    // A record written just before a crash is left unsigned.  Editing it
+    // stops the log from being continued, and otherwise it is marked and
+    // signed when the log is next opened.
+    {
+        let mut audit = AuditLog::open(&log_path, &key_path, &anchor_path, 100).unwrap();
+        let entry = LogEntry::new_info(UnitName::internal("test"), "five".to_owned());
+        audit.process_message(&UnitEvent::Log(entry)).unwrap();
+    }
+    match audit::verify(&log_path, &key_path, &anchor_path) {
+        Err(AuditError::Unsigned(1)) => (),
+        other => panic!("unexpected result: {:?}", other.map(|s| s.records)),
+    }
+    let mut crashed = String::new();
+    fs::File::open(&log_path).unwrap().read_to_string(&mut crashed).unwrap();
+    fs::File::create(&log_path).unwrap().write_all(crashed.replace("five", "FIVE").as_bytes()).unwrap();
+    match AuditLog::open(&log_path, &key_path, &anchor_path, 100) {
+        Err(AuditError::BadHash(9, 8)) => (),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("edited log was reopened"),
+    }
+    fs::File::create(&log_path).unwrap().write_all(crashed.as_bytes()).unwrap();
+    AuditLog::open(&log_path, &key_path, &anchor_path, 100).unwrap();
+    let summary = audit::verify(&log_path, &key_path, &anchor_path).unwrap();
+    assert_eq!((summary.records, summary.checkpoints, summary.resumed), (11, 5, 1));
+
+    // Verifying with the wrong key fails.
+    fs::File::create(&key_path).unwrap().write_all(b"wrong").unwrap();
+    assert!(audit::verify(&log_path, &key_path, &anchor_path).is_err());
+}
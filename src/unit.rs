@@ -7,6 +7,7 @@ extern crate systemd_parser;
 use std::fmt;
 use std::path::Path;
 use std::io;
+use std::time::Duration;
 
 use self::dependy::DepError;
 use self::humantime::DurationError;
@@ -14,6 +15,8 @@ use self::runny::RunnyError;
 use self::runny::running::RunningError;
 use self::systemd_parser::errors::ParserError;
 
+use expand::ExpandError;
+
 #[derive(PartialEq, Eq, Hash, Debug, Clone, PartialOrd, Ord, Serialize)]
 pub enum UnitKind {
     Interface,
@@ -143,6 +146,45 @@ impl fmt::Display for UnitName {
     }
 }
 
+/// How one unit came to depend on another.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DependencyKind {
+    /// Listed in a scenario's Tests
+    Tests,
+    Requires,
+    Suggests,
+}
+
+/// One step in a chain of dependencies, such as "requires swd (provided by openocd)".
+#[derive(Debug, Clone)]
+pub struct DependencyLink {
+    pub kind: DependencyKind,
+
+    /// The name as it was written in the unit file
+    pub name: UnitName,
+
+    /// The test that was chosen to satisfy `name`, if it is a virtual name
+    pub provider: Option<UnitName>,
+}
+
+impl DependencyLink {
+    /// Format a chain of links, starting from `root`.
+    pub fn format_chain(root: &UnitName, chain: &[DependencyLink]) -> String {
+        let mut out = format!("{}", root);
+        for link in chain {
+            match link.kind {
+                DependencyKind::Tests => out.push_str(&format!(" \u{2192} {}", link.name)),
+                DependencyKind::Requires => out.push_str(&format!(" requires {}", link.name)),
+                DependencyKind::Suggests => out.push_str(&format!(" suggests {}", link.name)),
+            }
+            if let Some(ref provider) = link.provider {
+                out.push_str(&format!(" (provided by {})", provider));
+            }
+        }
+        out
+    }
+}
+
 #[derive(Debug)]
 pub enum UnitIncompatibleReason {
     TestProgramReturnedNonzero(i32, String),
@@ -150,6 +192,27 @@ pub enum UnitIncompatibleReason {
     TestFileNotPresent(String),
     IncompatibleJig,
     DependencyError(DepError<UnitName>),
+
+    /// The last link in the chain names a test that doesn't exist.
+    MissingDependency(UnitName /* scenario */, Vec<DependencyLink>),
+
+    /// The chain loops back on itself, starting with the test at the given index.
+    CircularDependency(UnitName /* scenario */, Vec<DependencyLink>, usize),
+
+    /// Two tests that conflict with each other would both be run.
+    ConflictingTests(UnitName, UnitName),
+
+    /// After= and Before= (along with any dependencies) form a loop among these tests.
+    OrderingCycle(Vec<UnitName>),
+
+    /// One of a jig's Match directives didn't match this machine.
+    MatchFailed(String /* reason */),
+
+    /// A variable or specifier in the named directive couldn't be expanded.
+    ExpansionFailed(String /* directive */, ExpandError),
+
+    /// The current jig, or else the jig missing the fewest, lacks these required capabilities.
+    MissingCapabilities(UnitName /* jig */, Vec<String>),
 }
 
 impl fmt::Display for UnitIncompatibleReason {
@@ -165,6 +228,32 @@ impl fmt::Display for UnitIncompatibleReason {
                 write!(f, "Test file {} not present", file_name)
             }
             &UnitIncompatibleReason::IncompatibleJig => write!(f, "Jig not compatible"),
+            &UnitIncompatibleReason::MatchFailed(ref reason) => write!(f, "{}", reason),
+            UnitIncompatibleReason::ExpansionFailed(directive, e) => {
+                write!(f, "unable to expand {}: {}", directive, e)
+            }
+            &UnitIncompatibleReason::MissingCapabilities(ref jig, ref missing) => {
+                write!(f, "jig {} is missing capabilities: {}", jig, missing.join(", "))
+            }
+            &UnitIncompatibleReason::ConflictingTests(ref test, ref other) => {
+                write!(f, "{} conflicts with {}, and both would be run", test, other)
+            }
+            &UnitIncompatibleReason::OrderingCycle(ref tests) => {
+                write!(f, "ordering loop: {}", tests[0])?;
+                for (idx, test) in tests.iter().skip(1).chain(tests.iter().take(1)).enumerate() {
+                    write!(f, "{} runs after {}", if idx == 0 { "" } else { ", which" }, test)?;
+                }
+                Ok(())
+            }
+            &UnitIncompatibleReason::MissingDependency(ref scenario, ref chain) => {
+                write!(f, "{} (missing)", DependencyLink::format_chain(scenario, chain))
+            }
+            &UnitIncompatibleReason::CircularDependency(ref scenario, ref chain, start) => {
+                let members: Vec<String> = chain[start..chain.len() - 1].iter()
+                    .map(|l| format!("{}", l.provider.as_ref().unwrap_or(&l.name)))
+                    .collect();
+                write!(f, "{} (circular dependency between {})", DependencyLink::format_chain(scenario, chain), members.join(", "))
+            }
             &UnitIncompatibleReason::DependencyError(ref dep_error) => match dep_error {
                 &DepError::RequirementsNotFound(ref req) => {
                     write!(f, "Requirement '{}' not found", req)
@@ -218,6 +307,9 @@ pub enum UnitSelectError {
 
     /// This unit is for a jig that is not loaded.
     NoCompatibleJig,
+
+    /// The current jig, or else the jig missing the fewest, lacks these required capabilities.
+    MissingCapabilities(UnitName /* jig */, Vec<String>),
 }
 
 impl fmt::Display for UnitSelectError {
@@ -225,6 +317,9 @@ impl fmt::Display for UnitSelectError {
         match self {
             &UnitSelectError::UnitNotFound => write!(f, "couldn't find unit by ID"),
             &UnitSelectError::NoCompatibleJig => write!(f, "couldn't find any compatible jig"),
+            &UnitSelectError::MissingCapabilities(ref jig, ref missing) => {
+                write!(f, "jig {} is missing capabilities: {}", jig, missing.join(", "))
+            }
         }
     }
 }
@@ -249,6 +344,18 @@ pub enum UnitActivateError {
 
     /// We tried to Exec, but an error occurred.
     ExecFailed(RunnyError),
+
+    /// A variable or specifier in the command couldn't be expanded.
+    ExpansionFailed(ExpandError),
+
+    /// The command returned something other than 0.
+    NonZeroReturn(i32),
+
+    /// The command didn't finish in time, and was killed.
+    TimedOut(Duration),
+
+    /// A daemon exited before it printed the text that says it's ready.
+    DaemonNotReady(i32 /* result */),
 }
 
 impl From<RunnyError> for UnitActivateError {
@@ -257,6 +364,12 @@ impl From<RunnyError> for UnitActivateError {
     }
 }
 
+impl From<ExpandError> for UnitActivateError {
+    fn from(error: ExpandError) -> Self {
+        UnitActivateError::ExpansionFailed(error)
+    }
+}
+
 impl fmt::Display for UnitActivateError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::runny::RunnyError;
@@ -274,6 +387,10 @@ impl fmt::Display for UnitActivateError {
                     write!(f, "Unable to activate unit: Nix library error: {:?}", e)
                 }
             },
+            &UnitActivateError::ExpansionFailed(ref e) => write!(f, "Unable to activate unit: {}", e),
+            &UnitActivateError::NonZeroReturn(i) => write!(f, "Nonzero return: {}", i),
+            &UnitActivateError::TimedOut(ref d) => write!(f, "Timed out after {:?}", d),
+            &UnitActivateError::DaemonNotReady(i) => write!(f, "Daemon exited with {} before it was ready", i),
             &UnitActivateError::UnitNotFound => write!(f, "Couldn't find unit by id"),
             &UnitActivateError::UnitNotSelected => write!(f, "Tried to activate a deselected unit"),
         }
@@ -289,6 +406,15 @@ pub enum UnitDeactivateError {
 
     /// Runny reported an error of some sort.
     RunningError(RunningError),
+
+    /// We tried to Exec a stop command, but an error occurred.
+    ExecFailed(RunnyError),
+
+    /// A variable or specifier in the stop command couldn't be expanded.
+    ExpansionFailed(ExpandError),
+
+    /// The stop command didn't finish in time, and was killed.
+    TimedOut(Duration),
 }
 
 impl From<RunningError> for UnitDeactivateError {
@@ -302,6 +428,9 @@ impl fmt::Display for UnitDeactivateError {
         match self {
             &UnitDeactivateError::NonZeroReturn(i) => write!(f, "Nonzero return: {}", i),
             &UnitDeactivateError::RunningError(ref e) => write!(f, "Running error: {:?}", e),
+            &UnitDeactivateError::ExecFailed(ref e) => write!(f, "Unable to run stop command: {:?}", e),
+            &UnitDeactivateError::ExpansionFailed(ref e) => write!(f, "Unable to run stop command: {}", e),
+            &UnitDeactivateError::TimedOut(ref d) => write!(f, "Timed out after {:?}", d),
             &UnitDeactivateError::UnitNotFound => write!(f, "Couldn't find unit by id"),
         }
     }
@@ -322,6 +451,10 @@ pub enum UnitDescriptionError {
         String,      // Specified value
         Vec<String>, /* Allowed values */
     ),
+    InvalidNumber(String /* section name */, String /* key name */, String /* specified value */),
+    MissingBase(UnitName /* unit named in Inherit= */),
+    InvalidBase(UnitName /* unit named in Inherit= */, UnitKind /* kind of the inheriting unit */),
+    InheritanceCycle(Vec<UnitName>),
 }
 
 impl From<UnitNameError> for UnitDescriptionError {
@@ -385,6 +518,26 @@ impl fmt::Display for UnitDescriptionError {
                 val,
                 allowed.join(",")
             ),
+            &UnitDescriptionError::InvalidNumber(ref sec, ref key, ref val) => {
+                write!(f, "key {} in section {} must be a whole number, not: {}", key, sec, val)
+            }
+            &UnitDescriptionError::MissingBase(ref base) => {
+                write!(f, "inherits from {}, which does not exist", base)
+            }
+            &UnitDescriptionError::InvalidBase(ref base, ref kind) => {
+                write!(f, "can only inherit from another .{} unit, not {}", kind, base)
+            }
+            &UnitDescriptionError::InheritanceCycle(ref units) => {
+                write!(f, "inheritance loop: {}", units[0])?;
+                for (idx, unit) in units.iter().skip(1).chain(units.iter().take(1)).enumerate() {
+                    if idx == 0 {
+                        write!(f, " inherits from {}", unit)?;
+                    } else {
+                        write!(f, ", which inherits from {}", unit)?;
+                    }
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -1,9 +1,11 @@
 use std::fmt;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::time;
 
+use settings::Settings;
 use unitmanager::ManagerControlMessage;
 use unit::{UnitKind, UnitName};
 
@@ -245,18 +247,124 @@ impl UnitCategoryEvent {
     }
 }
 
+/// Escape returns, tabs, and backslashes, so that `msg` fits in one field
+/// of a TSV record.
+pub fn cfti_escape(msg: &str) -> String {
+    msg.replace("\\", "\\\\")
+        .replace("\t", "\\t")
+        .replace("\n", "\\n")
+        .replace("\r", "\\r")
+}
+
 #[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize)]
 pub enum LogType {
-    Error,
+    Debug,
     Info,
+    Warn,
+    Error,
 }
 
 impl LogType {
     pub fn as_str(&self) -> &str {
         match self {
-            &LogType::Error => "error",
+            &LogType::Debug => "debug",
             &LogType::Info => "info",
+            &LogType::Warn => "warn",
+            &LogType::Error => "error",
+        }
+    }
+
+    /// Take the level from a LEVEL= word at the start of a LOG verb's words,
+    /// if there is one, or else default to info.  Any other first word is
+    /// part of the message, even if it happens to name a level.
+    pub fn from_log_words(words: &mut Vec<String>) -> Result<LogType, String> {
+        let kind = match words.first() {
+            Some(w) if w.starts_with("LEVEL=") => w["LEVEL=".len()..].parse()?,
+            _ => return Ok(LogType::Info),
+        };
+        words.remove(0);
+        Ok(kind)
+    }
+}
+
+impl FromStr for LogType {
+    type Err = String;
+    fn from_str(s: &str) -> Result<LogType, String> {
+        match s.to_lowercase().as_str() {
+            "debug" => Ok(LogType::Debug),
+            "info" => Ok(LogType::Info),
+            "warn" => Ok(LogType::Warn),
+            "error" => Ok(LogType::Error),
+            _ => Err(format!("unknown log level \"{}\", expected debug, info, warn or error", s)),
+        }
+    }
+}
+
+/// Where a log message came from.  This is independent of the
+/// severity, so e.g. progress printed to stderr is not an error.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize)]
+pub enum LogSource {
+    /// Text printed to stdout by a child process.
+    Stdout,
+
+    /// Text printed to stderr by a child process.
+    Stderr,
+
+    /// Messages generated by exclave itself.
+    Internal,
+
+    /// Messages sent by a unit over its control protocol (e.g. LOG).
+    Protocol,
+}
+
+impl LogSource {
+    pub fn as_str(&self) -> &str {
+        match self {
+            &LogSource::Stdout => "stdout",
+            &LogSource::Stderr => "stderr",
+            &LogSource::Internal => "internal",
+            &LogSource::Protocol => "protocol",
+        }
+    }
+}
+
+/// Identifies the scenario run that a record belongs to, along with
+/// the monotonic time since that run started.  Unlike the wall-clock
+/// time, this is unaffected by the system clock being adjusted.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize)]
+pub struct RunStamp {
+    /// Unique identifier of the run
+    pub id: String,
+
+    /// Number of seconds since the run started
+    pub secs: u64,
+
+    /// Number of nanoseconds (in addition to secs) since the run started
+    pub nsecs: u32,
+
+    /// Serial number of the device under test, once it has been declared
+    pub serial: Option<String>,
+}
+
+impl RunStamp {
+    /// Format the run id, run-relative time, and serial fields of a TSV
+    /// record.  These are left empty for records that were not part of a run.
+    pub fn tsv(run: &Option<RunStamp>) -> String {
+        match *run {
+            None => "\t\t\t".to_owned(),
+            Some(ref r) => format!("{}\t{}\t{}\t{}", cfti_escape(&r.id), r.secs, r.nsecs,
+                                   cfti_escape(r.serial.as_deref().unwrap_or(""))),
+        }
+    }
+}
+
+impl fmt::Display for RunStamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "run {}", self.id)?;
+        if let Some(ref serial) = self.serial {
+            write!(f, " serial {}", serial)?;
         }
+        write!(f, " +{}.{:03}s", self.secs, self.nsecs / 1_000_000)
     }
 }
 
@@ -264,35 +372,56 @@ impl LogType {
 pub struct LogEntry {
     unit: UnitName,
     log_type: LogType,
+    log_source: LogSource,
     log_message: String,
     /// Number of seconds since the epoch
     pub unix_time: u64,
 
     /// Number of nanoseconds since the epoch
     pub unix_time_nsecs: u32,
+
+    /// The run this entry was logged during, if a scenario was running
+    run: Option<RunStamp>,
+
+    /// Whether `run` has been filled in, even if only to say that no
+    /// scenario was running
+    #[serde(skip)]
+    run_stamped: bool,
+
+    /// The operator who was logged in when this entry was logged
+    operator: Option<String>,
 }
 
 impl LogEntry {
-    pub fn new_error(id: UnitName, message: String) -> Self {
+    pub fn new(id: UnitName, log_type: LogType, log_source: LogSource, message: String) -> Self {
         let elapsed = Self::elapsed();
         LogEntry {
             unit: id,
-            log_type: LogType::Error,
+            log_type: log_type,
+            log_source: log_source,
             log_message: message,
             unix_time: elapsed.as_secs(),
             unix_time_nsecs: elapsed.subsec_nanos(),
+            run: None,
+            run_stamped: false,
+            operator: None,
         }
     }
 
+    /// Stamp this entry with the run it was produced during, rather than
+    /// the run in progress when it is broadcast.
+    pub fn with_run(mut self, run: Option<RunStamp>) -> Self {
+        self.run = run;
+        self.run_stamped = true;
+        self
+    }
+
+    pub fn new_error(id: UnitName, message: String) -> Self {
+        Self::new(id, LogType::Error, LogSource::Internal, message)
+    }
+
     pub fn new_info(id: UnitName, message: String) -> Self {
-        let elapsed = Self::elapsed();
-        LogEntry {
-            unit: id,
-            log_type: LogType::Info,
-            log_message: message,
-            unix_time: elapsed.as_secs(),
-            unix_time_nsecs: elapsed.subsec_nanos(),
-        }
+        Self::new(id, LogType::Info, LogSource::Internal, message)
     }
 
     pub fn secs(&self) -> u64 {
@@ -311,10 +440,38 @@ impl LogEntry {
         &self.log_type
     }
 
+    pub fn source(&self) -> &LogSource {
+        &self.log_source
+    }
+
     pub fn id(&self) -> &UnitName {
         &self.unit
     }
 
+    pub fn run(&self) -> &Option<RunStamp> {
+        &self.run
+    }
+
+    pub fn operator(&self) -> &Option<String> {
+        &self.operator
+    }
+
+    /// This entry as a TSV record, as described in doc/IPC.md.
+    pub fn tsv(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.kind().as_str(),
+            cfti_escape(self.id().id()),
+            cfti_escape(&format!("{}", self.id().kind())),
+            self.secs(),
+            self.nsecs(),
+            cfti_escape(self.message()),
+            self.source().as_str(),
+            RunStamp::tsv(self.run()),
+            cfti_escape(self.operator().as_deref().unwrap_or(""))
+        )
+    }
+
     fn elapsed() -> time::Duration {
         let now = time::SystemTime::now();
         match now.duration_since(time::UNIX_EPOCH) {
@@ -326,10 +483,143 @@ impl LogEntry {
 
 impl fmt::Display for LogEntry {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.log_type {
-            LogType::Error => write!(f, "ERROR {}: {}", self.unit, self.log_message),
-            LogType::Info => write!(f, "INFO {}: {}", self.unit, self.log_message),
+        let level = self.log_type.as_str().to_uppercase();
+        match self.log_source {
+            LogSource::Stdout | LogSource::Stderr => write!(
+                f,
+                "{} {} ({}): {}",
+                level,
+                self.unit,
+                self.log_source.as_str(),
+                self.log_message
+            ),
+            _ => write!(f, "{} {}: {}", level, self.unit, self.log_message),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize)]
+pub enum LifecycleKind {
+    /// A scenario has started running
+    ScenarioStarted,
+
+    /// A test has started running
+    TestStarted,
+
+    /// A test finished successfully
+    TestPassed,
+
+    /// A test finished unsuccessfully
+    TestFailed,
+
+    /// A test was skipped
+    TestSkipped,
+
+    /// A scenario has finished running
+    ScenarioFinished,
+}
+
+impl LifecycleKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            &LifecycleKind::ScenarioStarted => "start",
+            &LifecycleKind::TestStarted => "running",
+            &LifecycleKind::TestPassed => "pass",
+            &LifecycleKind::TestFailed => "fail",
+            &LifecycleKind::TestSkipped => "skip",
+            &LifecycleKind::ScenarioFinished => "finish",
+        }
+    }
+}
+
+/// A scenario or test changed state during a run.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize)]
+pub struct LifecycleEntry {
+    unit: UnitName,
+    event: LifecycleKind,
+    message: String,
+
+    /// Number of seconds since the epoch
+    pub unix_time: u64,
+
+    /// Number of nanoseconds since the epoch
+    pub unix_time_nsecs: u32,
+
+    /// The run this event happened during
+    run: Option<RunStamp>,
+
+    /// The operator who was logged in when this event happened
+    operator: Option<String>,
+}
+
+impl LifecycleEntry {
+    pub fn new(id: UnitName, event: LifecycleKind, message: String) -> Self {
+        let elapsed = LogEntry::elapsed();
+        LifecycleEntry {
+            unit: id,
+            event: event,
+            message: message,
+            unix_time: elapsed.as_secs(),
+            unix_time_nsecs: elapsed.subsec_nanos(),
+            run: None,
+            operator: None,
+        }
+    }
+
+    pub fn id(&self) -> &UnitName {
+        &self.unit
+    }
+
+    pub fn kind(&self) -> &LifecycleKind {
+        &self.event
+    }
+
+    pub fn message(&self) -> &String {
+        &self.message
+    }
+
+    pub fn secs(&self) -> u64 {
+        self.unix_time
+    }
+
+    pub fn nsecs(&self) -> u32 {
+        self.unix_time_nsecs
+    }
+
+    pub fn run(&self) -> &Option<RunStamp> {
+        &self.run
+    }
+
+    pub fn operator(&self) -> &Option<String> {
+        &self.operator
+    }
+
+    /// This event as a TSV record, with a source of "lifecycle".
+    pub fn tsv(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\tlifecycle\t{}\t{}",
+            self.kind().as_str(),
+            cfti_escape(self.id().id()),
+            cfti_escape(&format!("{}", self.id().kind())),
+            self.secs(),
+            self.nsecs(),
+            cfti_escape(self.message()),
+            RunStamp::tsv(self.run()),
+            cfti_escape(self.operator().as_deref().unwrap_or(""))
+        )
+    }
+}
+
+impl fmt::Display for LifecycleEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.event.as_str().to_uppercase(), self.unit)?;
+        if let Some(ref run) = self.run {
+            write!(f, " ({})", run)?;
+        }
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
         }
+        Ok(())
     }
 }
 
@@ -344,6 +634,9 @@ pub enum UnitEvent {
     /// A generic log message.
     Log(LogEntry),
 
+    /// A scenario or test changed state.
+    Lifecycle(LifecycleEntry),
+
     /// The system has requested a rescan take place.
     RescanRequest,
 
@@ -356,18 +649,108 @@ pub enum UnitEvent {
     /// A unit made a request to a Manager, which will be passed to the main thread.
     ManagerRequest(ManagerControlMessage),
 
+    /// The global settings were loaded or changed.
+    SettingsChanged(Settings),
+
     /// The system is shutting down.
     Shutdown,
+
+    /// Everything has stopped after a Shutdown, and nothing more will be sent.
+    Exit,
+}
+
+/// The scenario run that is currently in progress.
+#[derive(Debug, Clone)]
+struct CurrentRun {
+    id: String,
+    started: time::Instant,
+    serial: Option<String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct UnitBroadcaster {
     senders: Arc<Mutex<Vec<Sender<UnitEvent>>>>,
+    current_run: Arc<Mutex<Option<CurrentRun>>>,
+
+    /// A serial declared while no run was in progress, for the next run
+    next_serial: Arc<Mutex<Option<String>>>,
+
+    /// The operator who is logged in, if any
+    operator: Arc<Mutex<Option<String>>>,
 }
 
 impl UnitBroadcaster {
     pub fn new() -> Self {
-        UnitBroadcaster { senders: Arc::new(Mutex::new(vec![])) }
+        UnitBroadcaster {
+            senders: Arc::new(Mutex::new(vec![])),
+            current_run: Arc::new(Mutex::new(None)),
+            next_serial: Arc::new(Mutex::new(None)),
+            operator: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Begin a new run.  Every log and lifecycle event broadcast until
+    /// `end_run()` is called will be stamped with the returned run id and
+    /// with the time elapsed since this call.  Output from units is stamped
+    /// when it arrives, so output still waiting to be handled when the run
+    /// ends keeps the run it came from.
+    pub fn begin_run(&self) -> String {
+        let now = LogEntry::elapsed();
+        let id = format!("{}-{:09}", now.as_secs(), now.subsec_nanos());
+        *self.current_run.lock().unwrap() = Some(CurrentRun {
+            id: id.clone(),
+            started: time::Instant::now(),
+            serial: self.next_serial.lock().unwrap().take(),
+        });
+        id
+    }
+
+    /// Record the serial number of the device under test.  If a run is in
+    /// progress it belongs to that run, and its id is returned.  Otherwise
+    /// it is kept for the next run to begin.
+    pub fn set_serial(&self, serial: String) -> Option<String> {
+        match *self.current_run.lock().unwrap() {
+            Some(ref mut run) => {
+                run.serial = Some(serial);
+                Some(run.id.clone())
+            }
+            None => {
+                *self.next_serial.lock().unwrap() = Some(serial);
+                None
+            }
+        }
+    }
+
+    /// Finish the current run, if any.
+    pub fn end_run(&self) {
+        *self.current_run.lock().unwrap() = None;
+    }
+
+    /// Log an operator in, or out if `operator` is None.  Every log and
+    /// lifecycle event broadcast while they are logged in is stamped with
+    /// their id.
+    pub fn set_operator(&self, operator: Option<String>) {
+        *self.operator.lock().unwrap() = operator;
+    }
+
+    pub fn operator(&self) -> Option<String> {
+        self.operator.lock().unwrap().clone()
+    }
+
+    /// The current run, if any, as it would be stamped on a record now.
+    pub fn run_stamp(&self) -> Option<RunStamp> {
+        match *self.current_run.lock().unwrap() {
+            None => None,
+            Some(ref run) => {
+                let elapsed = run.started.elapsed();
+                Some(RunStamp {
+                    id: run.id.clone(),
+                    secs: elapsed.as_secs(),
+                    nsecs: elapsed.subsec_nanos(),
+                    serial: run.serial.clone(),
+                })
+            }
+        }
     }
 
     fn broadcast_core(senders: &Arc<Mutex<Vec<Sender<UnitEvent>>>>, event: &UnitEvent) {
@@ -389,13 +772,37 @@ impl UnitBroadcaster {
         }
 
         match *event {
-            UnitEvent::Shutdown => notify_senders_ref.clear(),
+            UnitEvent::Exit => notify_senders_ref.clear(),
             _ => (),
         }
     }
 
     pub fn broadcast(&self, event: &UnitEvent) {
-        Self::broadcast_core(&self.senders, event)
+        // Stamp log and lifecycle records with the current run and operator.
+        match *event {
+            UnitEvent::Log(ref entry) => {
+                let mut entry = entry.clone();
+                if !entry.run_stamped {
+                    entry.run = self.run_stamp();
+                    entry.run_stamped = true;
+                }
+                if entry.operator.is_none() {
+                    entry.operator = self.operator();
+                }
+                Self::broadcast_core(&self.senders, &UnitEvent::Log(entry))
+            }
+            UnitEvent::Lifecycle(ref entry) => {
+                let mut entry = entry.clone();
+                if entry.run.is_none() {
+                    entry.run = self.run_stamp();
+                }
+                if entry.operator.is_none() {
+                    entry.operator = self.operator();
+                }
+                Self::broadcast_core(&self.senders, &UnitEvent::Lifecycle(entry))
+            }
+            _ => Self::broadcast_core(&self.senders, event),
+        }
     }
 
     pub fn subscribe(&self) -> Receiver<UnitEvent> {
@@ -3,9 +3,11 @@
 
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
 use config::Config;
+use inherit;
 use unit::{UnitKind, UnitName};
 use unitbroadcaster::{UnitBroadcaster, UnitCategoryEvent, UnitEvent, UnitStatus, UnitStatusEvent};
 use unitmanager::UnitManager;
@@ -148,6 +150,10 @@ pub struct UnitLibrary {
     dirty_tests: RefCell<HashMap<UnitName, ()>>,
     dirty_triggers: RefCell<HashMap<UnitName, ()>>,
 
+    /// The file each unit was read from, along with every unit it inherits
+    /// from, so that it can be reread when one of those changes.
+    inheritance: RefCell<HashMap<UnitName, (PathBuf, Vec<UnitName>)>>,
+
     /// The object in charge of keeping track of units in-memory.
     unit_manager: RefCell<UnitManager>,
 }
@@ -172,6 +178,8 @@ impl UnitLibrary {
             dirty_tests: RefCell::new(HashMap::new()),
             dirty_triggers: RefCell::new(HashMap::new()),
 
+            inheritance: RefCell::new(HashMap::new()),
+
             unit_manager: RefCell::new(UnitManager::new(broadcaster, config)),
         }
     }
@@ -189,10 +197,46 @@ impl UnitLibrary {
         };
     }
 
+    /// Read a unit's description from disk, and mark it as dirty.
+    fn read_description(&self, name: &UnitName, status: &UnitStatus, path: &Path) {
+        if name.kind() != &UnitKind::Internal {
+            self.inheritance
+                .borrow_mut()
+                .insert(name.clone(), (path.to_owned(), inherit::bases(path)));
+        }
+        process_if!(self, name, status, UnitKind::Interface, path, InterfaceDescription, interface_descriptions);
+        process_if!(self, name, status, UnitKind::Jig, path, JigDescription, jig_descriptions);
+        process_if!(self, name, status, UnitKind::Logger, path, LoggerDescription, logger_descriptions);
+        process_if!(self, name, status, UnitKind::Scenario, path, ScenarioDescription, scenario_descriptions);
+        process_if!(self, name, status, UnitKind::Test, path, TestDescription, test_descriptions);
+        process_if!(self, name, status, UnitKind::Trigger, path, TriggerDescription, trigger_descriptions);
+    }
+
+    /// Reread every unit that inherits from a dirty unit, since its defaults
+    /// may have changed.  This marks those units as dirty in turn.
+    fn reread_inheritors(&self) {
+        let mut dirty = vec![];
+        for dirty_units in &[&self.dirty_interfaces, &self.dirty_jigs, &self.dirty_loggers,
+                             &self.dirty_scenarios, &self.dirty_tests, &self.dirty_triggers] {
+            dirty.extend(dirty_units.borrow().keys().cloned());
+        }
+
+        let inheritors: Vec<(UnitName, PathBuf)> = self.inheritance
+            .borrow()
+            .iter()
+            .filter(|&(name, &(_, ref bases))| !dirty.contains(name) && bases.iter().any(|b| dirty.contains(b)))
+            .map(|(name, &(ref path, _))| (name.clone(), path.clone()))
+            .collect();
+        for (name, path) in inheritors {
+            self.read_description(&name, &UnitStatus::UpdateStarted(path.clone()), &path);
+        }
+    }
+
     /// Examine all of the loaded units and ensure they can be loaded.
     ///
     /// Each unit type must be handled differently.
     ///
+    /// 0. Reread every unit that inherits from a dirty unit.
     /// 1. Mark every Interface, Scenario or Test that depends on a dirty jig as dirty.
     ///    That way, they will be rescanned.
     /// 2. Mark every Scenario that uses a dirty Test as dirty.
@@ -206,12 +250,21 @@ impl UnitLibrary {
     /// 9. Activate all Interfaces.
     pub fn rescan(&mut self) {
         self.broadcaster.broadcast(&UnitEvent::RescanStart);
+
+        // 0. Units inheriting from a changed unit must be reread, which marks them dirty.
+        self.reread_inheritors();
+
         let mut statuses = self.unit_status.borrow_mut();
 
         // 1. Go through jigs and mark dependent scenarios and tests as dirty.
         for (jig_name, _) in self.dirty_jigs.borrow().iter() {
+            // Units that need a capability depend on the jig if it had the
+            // capability before the change, or has it now.
+            let versions: Vec<JigDescription> = self.jig_descriptions.borrow().get(jig_name).cloned().into_iter()
+                .chain(self.unit_manager.borrow().jig_description_named(jig_name))
+                .collect();
             for (test_name, test_description) in self.test_descriptions.borrow().iter() {
-                if test_description.supports_jig(jig_name) {
+                if test_description.supports_jig(jig_name, &versions) {
                     self.dirty_tests.borrow_mut().insert(test_name.clone(), ());
                 }
             }
@@ -219,7 +272,7 @@ impl UnitLibrary {
             for (scenario_name, scenario_description) in self.scenario_descriptions
                 .borrow()
                 .iter() {
-                if scenario_description.supports_jig(jig_name) {
+                if scenario_description.supports_jig(jig_name, &versions) {
                     self.dirty_scenarios
                         .borrow_mut()
                         .insert(scenario_name.clone(), ());
@@ -345,7 +398,23 @@ impl UnitLibrary {
             }
         }
 
-        // 4. Load all Jigs that are valid.
+        // 4. Load all Jigs that are valid.  The jig that's in use is reloaded in
+        //    place instead, if its ExecReload can take care of what changed.
+        {
+            let mut reloaded = vec![];
+            for (id, _) in self.dirty_jigs.borrow().iter() {
+                if let Some(&UnitStatus::UpdateStarted(_)) = statuses.get(id) {
+                    if let Some(description) = self.jig_descriptions.borrow().get(id) {
+                        if self.unit_manager.borrow().reload_jig(description) {
+                            reloaded.push(id.clone());
+                        }
+                    }
+                }
+            }
+            for id in reloaded {
+                self.dirty_jigs.borrow_mut().remove(&id);
+            }
+        }
         load_units_for_activation!(self, statuses, dirty_jigs, jig_descriptions, load_jig);
 
         // 5. Load all Interfaces that are compatible with this Jig.
@@ -363,8 +432,9 @@ impl UnitLibrary {
         // 9. Load all Scenarios that are compatible with this Jig.
         load_units!(self, statuses, dirty_scenarios, scenario_descriptions, load_scenario);
 
-        // 10. Activate all jigs that were just loaded.
-        select_and_activate_units!(self, dirty_jigs);
+        // 10. Jigs aren't activated here, since only one of them may be used.
+        //     The best one is chosen in step 13.
+        self.dirty_jigs.borrow_mut().clear();
 
         // 11. Activate all interfaces that were just loaded.
         select_and_activate_units!(self, dirty_interfaces);
@@ -387,22 +457,10 @@ impl UnitLibrary {
                 let &UnitStatusEvent {ref name, ref status} = msg;
 
                 match status {
-                    &UnitStatus::LoadStarted(ref path) => {
-                        process_if!(self, name, status, UnitKind::Interface, path, InterfaceDescription, interface_descriptions);
-                        process_if!(self, name, status, UnitKind::Logger, path, LoggerDescription, logger_descriptions);
-                        process_if!(self, name, status, UnitKind::Jig, path, JigDescription, jig_descriptions);
-                        process_if!(self, name, status, UnitKind::Scenario, path, ScenarioDescription, scenario_descriptions);
-                        process_if!(self, name, status, UnitKind::Test, path, TestDescription, test_descriptions);
-                        process_if!(self, name, status, UnitKind::Trigger, path, TriggerDescription, trigger_descriptions);
-                    }
-                    &UnitStatus::UpdateStarted(ref path) => {
-                        process_if!(self, name, status, UnitKind::Interface, path, InterfaceDescription, interface_descriptions);
-                        process_if!(self, name, status, UnitKind::Jig, path, JigDescription, jig_descriptions);
-                        process_if!(self, name, status, UnitKind::Logger, path, LoggerDescription, logger_descriptions);
-                        process_if!(self, name, status, UnitKind::Scenario, path, ScenarioDescription, scenario_descriptions);
-                        process_if!(self, name, status, UnitKind::Trigger, path, TriggerDescription, trigger_descriptions);
-                    }
+                    &UnitStatus::LoadStarted(ref path) => self.read_description(name, status, path),
+                    &UnitStatus::UpdateStarted(ref path) => self.read_description(name, status, path),
                     &UnitStatus::UnloadStarted(ref path) => {
+                        self.inheritance.borrow_mut().remove(name);
                         self.unit_status
                             .borrow_mut()
                             .insert(name.clone(), UnitStatus::UnloadStarted(path.clone()));
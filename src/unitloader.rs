@@ -18,13 +18,16 @@ impl UnitLoader {
     pub fn process_message(&self, msg: &UnitEvent) {
         match msg {
             &UnitEvent::Shutdown => return,
+            &UnitEvent::Exit => (),
             &UnitEvent::Status(ref evt) => self.handle_status(evt),
             &UnitEvent::RescanRequest => (),
             &UnitEvent::RescanStart => (),
             &UnitEvent::RescanFinish => (),
             &UnitEvent::Category(_) => (),
             &UnitEvent::Log(_) => (),
+            &UnitEvent::Lifecycle(_) => (),
             &UnitEvent::ManagerRequest(_) => (),
+            &UnitEvent::SettingsChanged(_) => (),
         }
     }
 
@@ -1,18 +1,29 @@
 // The UnitManager contains all units that are Selected.  This includes
 // units that are Active.
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::rc::Rc;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use artifacts::{self, RunArtifacts};
 use config::Config;
+use history::{self, RunRecord};
+use operator::{self, LoginError, Roster};
+use plan::Plan;
+use pool::{Allocation, PoolDescription, PoolError, PoolJournal};
+use report;
+use selftest::{self, GateError, SelfTestGate};
+use wear::{Thresholds, WearCounters, WearError};
 use unit::{UnitName, UnitKind, UnitActivateError, UnitDeactivateError, UnitSelectError, UnitDeselectError, UnitIncompatibleReason};
-use unitbroadcaster::{UnitBroadcaster, UnitEvent, UnitStatusEvent, UnitStatus, LogEntry};
+use unitbroadcaster::{UnitBroadcaster, UnitEvent, UnitStatusEvent, UnitStatus, LogEntry, LogSource, LogType,
+                      LifecycleEntry, LifecycleKind, RunStamp};
 use units::interface::{Interface, InterfaceDescription};
-use units::jig::{Jig, JigDescription};
+use units::jig::{self, Jig, JigCandidate, JigChoice, JigDescription};
 use units::logger::{Logger, LoggerDescription};
 use units::scenario::{Scenario, ScenarioDescription};
 use units::test::{Test, TestDescription};
@@ -72,6 +83,9 @@ pub enum ManagerStatusMessage {
     /// Return the first name of the jig we're running on.
     Jig(Option<UnitName> /* Name of the jig (if one is selected) */),
 
+    /// A jig that was considered, and whether it was chosen.  Follows Jig.
+    JigCandidate(JigCandidate),
+
     /// Return a list of known scenarios.
     Scenarios(Vec<UnitName>),
 
@@ -108,6 +122,30 @@ pub enum ManagerStatusMessage {
     /// Sent when a scenario has finished running.
     Finished(UnitName /* Scenario name */, u32 /* Result code */, String /* Reason for finishing */),
 
+    /// A scenario or test changed state.  Sent to loggers.
+    Lifecycle(LifecycleEntry),
+
+    /// How a scenario's tests were resolved.
+    Plan(Plan),
+
+    /// A scenario's resolved plan, as a Graphviz graph.
+    Dot(UnitName /* Scenario name */, String /* Graph */),
+
+    /// A scenario was not allowed to start.
+    Refused(UnitName /* Scenario name */, String /* Reason */),
+
+    /// How many times one of the jig's wear counters has been used.
+    Wear(UnitName /* Jig name */, String /* Counter */, u64 /* Count */, Thresholds),
+
+    /// Every previous run of a device, oldest first.
+    History(String /* Serial number */, Vec<RunRecord>),
+
+    /// The operator who is logged in, if any.
+    Operator(Option<String> /* Operator id */),
+
+    /// An operator was not allowed to log in or out.
+    LoginRefused(String /* Operator id */, String /* Reason */),
+
 }
 
 /// Messages for Unit -> Library communication
@@ -116,6 +154,24 @@ pub enum ManagerControlMessageContents {
     /// Get the current Jig
     Jig,
 
+    /// Check whether the jig still matches, and switch jigs if it doesn't.
+    RecheckJigs,
+
+    /// Get the current jig's wear counters.
+    Wear,
+
+    /// Declare the serial number of the device under test.
+    Serial(String),
+
+    /// Get every run of the device with a given serial number.
+    History(String /* Serial number */),
+
+    /// Log an operator in at the station.
+    Login(String /* Operator id */),
+
+    /// Log the current operator out.
+    Logout,
+
     /// Get a list of compatible, Selected scenarios.
     Scenarios,
 
@@ -125,6 +181,10 @@ pub enum ManagerControlMessageContents {
     /// Get a list of tests, either from the current scenario (None) or a specific scenario (Some)
     Tests(Option<UnitName>),
 
+    /// Get the resolved plan of the current scenario (None) or a specific scenario (Some),
+    /// optionally as a Graphviz graph.
+    Plan(Option<UnitName>, bool /* As a graph */),
+
     /// An error message from a particular interface.
     Error(String /* Error message contents */),
 
@@ -140,11 +200,8 @@ pub enum ManagerControlMessageContents {
     /// Client sent an unimplemented message.
     Unimplemented(String /* verb */, String /* rest of line */),
 
-    /// Send an INFO message to the logging system
-    Log(String /* log message */),
-
-    /// Send an ERROR message to the logging system
-    LogError(String /* log message */),
+    /// Send a message to the logging system, with a severity and a source
+    Log(LogType, LogSource, String /* log message */),
 
     /// Start running a scenario, or the default scenario if None
     StartScenario(Option<UnitName>),
@@ -169,12 +226,20 @@ pub enum ManagerControlMessageContents {
 
     /// Shutdown the entire system
     Shutdown(Option<String>),
+
+    /// Everything has stopped after a shutdown
+    Exit,
 }
 
 #[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub struct ManagerControlMessage {
     sender: UnitName,
     contents: ManagerControlMessageContents,
+
+    /// The run in progress when the message arrived.  Messages can wait
+    /// behind others, so the run may have finished by the time the message
+    /// is handled.
+    run: Option<RunStamp>,
 }
 
 impl ManagerControlMessage {
@@ -182,6 +247,7 @@ impl ManagerControlMessage {
         ManagerControlMessage {
             sender: id.clone(),
             contents: contents,
+            run: None,
         }
     }
 }
@@ -217,11 +283,27 @@ pub struct UnitManager {
     /// The currently-selected Jig, if any
     current_jig: Rc<RefCell<Option<Rc<RefCell<Jig>>>>>,
 
+    /// Every jig that was loaded or tried to load, with the reason it's
+    /// incompatible, if it is.
+    jig_candidates: RefCell<BTreeMap<UnitName, (JigDescription, Option<String>)>>,
+
+    /// How often jigs are rechecked, and a flag to stop the thread that does it.
+    jig_recheck: RefCell<Option<(Duration, Arc<AtomicBool>)>>,
+
+    /// How the jig was last chosen, so that it's only logged when it changes.
+    jig_report: RefCell<Vec<JigCandidate>>,
+
     /// A list of selected units.
     selected: Rc<RefCell<HashMap<UnitName, ()>>>,
 
     /// A list of active units.  These units must also be selected.
     active: Rc<RefCell<HashMap<UnitName, ()>>>,
+
+    /// Artifacts for the scenario that is currently running, if enabled.
+    artifacts: RefCell<Option<RunArtifacts>>,
+
+    /// Values checked out of pools for the scenario that is currently running.
+    allocations: RefCell<Vec<Allocation>>,
 }
 
 impl UnitManager {
@@ -247,14 +329,21 @@ impl UnitManager {
 
             current_scenario: Rc::new(RefCell::new(None)),
             current_jig: Rc::new(RefCell::new(None)),
+            jig_candidates: RefCell::new(BTreeMap::new()),
+            jig_recheck: RefCell::new(None),
+            jig_report: RefCell::new(vec![]),
 
             control_sender: sender,
+
+            artifacts: RefCell::new(None),
+            allocations: RefCell::new(vec![]),
         }
     }
 
     /// Runs in a separate thread and consolidates control messages
     fn control_message_monitor(receiver: Receiver<ManagerControlMessage>, broadcaster: UnitBroadcaster) {
-        while let Ok(msg) = receiver.recv() {
+        while let Ok(mut msg) = receiver.recv() {
+            msg.run = broadcaster.run_stamp();
             broadcaster.broadcast(&UnitEvent::ManagerRequest(msg));
         }
     }
@@ -276,7 +365,12 @@ impl UnitManager {
     }
 
     pub fn load_jig(&self, desceription: &JigDescription) -> Result<UnitName, UnitIncompatibleReason> {
-        load!(self, jigs, desceription)
+        let result = load!(self, jigs, desceription);
+        let rejected = result.as_ref().err().map(|e| format!("{}", e));
+        self.jig_candidates
+            .borrow_mut()
+            .insert(desceription.id().clone(), (desceription.clone(), rejected));
+        result
     }
 
     pub fn load_scenario(&self, desceription: &ScenarioDescription) -> Result<UnitName, UnitIncompatibleReason> {
@@ -557,11 +651,21 @@ impl UnitManager {
     /// For example, if there is no current Jig, activate the first Jig we find.
     /// Likewise, if there is no selected Scenario, select the first scenario we find.
     pub fn refresh_defaults(&self) {
-        // Activate a "random" available jig.
-        if self.current_jig.borrow().is_none() && !self.jigs.borrow().is_empty() {
-            let new_jig_id = self.jigs.borrow().keys().next().unwrap().clone();
-            self.activate(&new_jig_id);
+        // Switch to the best compatible jig, if it isn't already in use.
+        let candidates = self.jig_candidates();
+        if *self.jig_report.borrow() != candidates {
+            self.report_jig_candidates(&candidates);
+            *self.jig_report.borrow_mut() = candidates.clone();
+        }
+        let best = candidates.iter().find(|c| c.choice == JigChoice::Selected).map(|c| c.name.clone());
+        let current = self.current_jig.borrow().as_ref().map(|j| j.borrow().id().clone());
+        if let Some(best) = best {
+            if current.as_ref() != Some(&best) {
+                self.select(&best);
+                self.activate(&best);
+            }
         }
+        self.schedule_jig_recheck();
 
         // If there is no current scenario, select a random one.
         if self.current_scenario.borrow().is_none() && !self.scenarios.borrow().is_empty() {
@@ -570,6 +674,117 @@ impl UnitManager {
         }
     }
 
+    /// Every jig that was considered, best first, along with whether it was chosen.
+    pub fn jig_candidates(&self) -> Vec<JigCandidate> {
+        let pinned = self.cfg.lock().unwrap().pinned_jig().clone();
+        self.rank_jig_candidates(pinned.as_ref())
+    }
+
+    /// Like `jig_candidates()`, for callers that already hold the config lock.
+    fn rank_jig_candidates(&self, pinned: Option<&UnitName>) -> Vec<JigCandidate> {
+        let candidates = self.jig_candidates
+            .borrow()
+            .iter()
+            .map(|(name, &(ref desc, ref rejected))| (name.clone(), (*desc.priority(), rejected.clone())))
+            .collect();
+        jig::rank_jigs(&candidates, pinned)
+    }
+
+    /// Periodically ask for the jigs to be rechecked, as often as the jig
+    /// with the shortest RecheckInterval wants.  Any previous schedule is
+    /// replaced.
+    fn schedule_jig_recheck(&self) {
+        let interval = self.jig_candidates
+            .borrow()
+            .values()
+            .filter_map(|&(ref desc, _)| *desc.recheck_interval())
+            .min();
+        if self.jig_recheck.borrow().as_ref().map(|&(i, _)| i) == interval {
+            return;
+        }
+        if let Some((_, ref stop)) = *self.jig_recheck.borrow() {
+            stop.store(true, Ordering::SeqCst);
+        }
+        *self.jig_recheck.borrow_mut() = match interval {
+            None => None,
+            Some(interval) => {
+                let stop = Arc::new(AtomicBool::new(false));
+                let thr_stop = stop.clone();
+                let control = self.get_control_channel();
+                thread::spawn(move || loop {
+                    thread::sleep(interval);
+                    if thr_stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let msg = ManagerControlMessage::new(&UnitName::internal("jig"), ManagerControlMessageContents::RecheckJigs);
+                    if control.send(msg).is_err() {
+                        break;
+                    }
+                });
+                Some((interval, stop))
+            }
+        };
+    }
+
+    /// Check whether each jig is still compatible, unless a scenario is
+    /// running.  A jig that has become compatible is loaded, and one that
+    /// no longer is gets unloaded but stays a candidate.  If anything
+    /// changed, the best jig is selected and interfaces are sent the JIG.
+    fn recheck_jigs(&self) {
+        if let Some(ref scenario) = *self.current_scenario.borrow() {
+            if self.active.borrow().contains_key(scenario.borrow().id()) {
+                return;
+            }
+        }
+
+        let mut changed = vec![];
+        {
+            let config = self.cfg.lock().unwrap();
+            for (name, &(ref desc, ref rejected)) in self.jig_candidates.borrow().iter() {
+                let now_rejected = desc.is_compatible(self, &config).err().map(|e| format!("{}", e));
+                if &now_rejected != rejected {
+                    match now_rejected {
+                        None => self.bc.log("jig", format!("{} is now compatible", name)),
+                        Some(ref reason) => self.bc.log("jig", format!("{} is no longer compatible: {}", name, reason)),
+                    }
+                    changed.push((desc.clone(), now_rejected));
+                }
+            }
+        }
+        if changed.is_empty() {
+            return;
+        }
+
+        for (desc, now_rejected) in changed {
+            match now_rejected {
+                None => {
+                    self.load_jig(&desc).ok();
+                }
+                Some(reason) => {
+                    self.deactivate(desc.id(), "jig is no longer compatible");
+                    self.deselect(desc.id(), "jig is no longer compatible");
+                    self.jigs.borrow_mut().remove(desc.id());
+                    self.jig_candidates.borrow_mut().insert(desc.id().clone(), (desc, Some(reason)));
+                }
+            }
+        }
+        self.refresh_defaults();
+        for (interface_id, _) in self.interfaces.borrow().iter() {
+            self.send_jig_to(interface_id);
+        }
+    }
+
+    fn report_jig_candidates(&self, candidates: &[JigCandidate]) {
+        if let Some(ref pinned) = *self.cfg.lock().unwrap().pinned_jig() {
+            if !candidates.iter().any(|c| &c.name == pinned) {
+                self.bc.log("jig", format!("pinned jig {} was not found", pinned));
+            }
+        }
+        for candidate in candidates {
+            self.bc.log("jig", format!("{}", candidate));
+        }
+    }
+
     fn activate_interface(&self, id: &UnitName) -> Result<(), UnitActivateError> {
         // Activate the interface, which actually starts it up.
         match self.interfaces.borrow().get(id) {
@@ -717,17 +932,47 @@ impl UnitManager {
         match *current_opt {
             None => Ok(()),
             Some(ref s) => {
-                let current = s.borrow_mut();
+                let mut current = s.borrow_mut();
                 if current.id() != id {
                     Ok(())
                 }
                 else {
-                    current.deactivate()
+                    current.deactivate(self, &*self.cfg.lock().unwrap())
                 }
             }
         }
     }
 
+    /// If the given jig is the one in use, and its ExecReload can take care of
+    /// what changed, reload it in place rather than stopping and starting it.
+    /// Returns true if it was reloaded.
+    pub fn reload_jig(&self, desceription: &JigDescription) -> bool {
+        let id = desceription.id();
+        if ! self.active.borrow().contains_key(id) {
+            return false;
+        }
+        let jig = match self.jigs.borrow().get(id) {
+            Some(j) => j.clone(),
+            None => return false,
+        };
+        if ! jig.borrow().can_reload(desceription) {
+            return false;
+        }
+
+        let config = self.cfg.lock().unwrap();
+        if desceription.is_compatible(self, &config).is_err() {
+            return false;
+        }
+        self.jig_candidates
+            .borrow_mut()
+            .insert(id.clone(), (desceription.clone(), None));
+        match jig.borrow_mut().reload(desceription, self, &config) {
+            Ok(()) => self.bc.broadcast(&UnitEvent::Status(UnitStatusEvent::new_loaded(id))),
+            Err(e) => self.bc.broadcast(&UnitEvent::Status(UnitStatusEvent::new_active_failed(id, format!("unable to reload: {}", e)))),
+        }
+        true
+    }
+
     pub fn unload(&self, id: &UnitName) {
         self.deselect(id, "unloading");
         match *id.kind() {
@@ -767,6 +1012,7 @@ impl UnitManager {
         self.deselect(id, "jig is being unloaded");
 
         self.jigs.borrow_mut().remove(id);
+        self.jig_candidates.borrow_mut().remove(id);
     }
 
     fn unload_test(&self, id: &UnitName) {
@@ -810,6 +1056,63 @@ impl UnitManager {
         self.jigs.borrow().get(id).is_some()
     }
 
+    /// Make sure that a unit limited to `jigs`, unless it's empty, and
+    /// needing the `required` capabilities can run.  One of its jigs must be
+    /// loaded, and once a jig is selected, that jig must have every
+    /// capability.  Until then, any of its jigs will do.  When none does, the
+    /// current jig is named along with what it's missing, or else the jig
+    /// that is missing the fewest.
+    pub fn check_capabilities(&self, jigs: &[UnitName], required: &[String]) -> Result<(), UnitIncompatibleReason> {
+        let mut candidates: Vec<(UnitName, Vec<String>)> = self.jigs
+            .borrow()
+            .iter()
+            .filter(|&(id, _)| jigs.is_empty() || jigs.contains(id))
+            .map(|(id, jig)| (id.clone(), jig.borrow().jig_description().missing_capabilities(required)))
+            .collect();
+        if candidates.is_empty() {
+            return Err(UnitIncompatibleReason::IncompatibleJig);
+        }
+        if required.is_empty() {
+            return Ok(());
+        }
+
+        let current = self.current_jig.borrow().as_ref().map(|j| j.borrow().id().clone());
+        if let Some(current) = current {
+            return match candidates.into_iter().find(|c| c.0 == current) {
+                None => Err(UnitIncompatibleReason::IncompatibleJig),
+                Some((_, ref missing)) if missing.is_empty() => Ok(()),
+                Some((jig, missing)) => Err(UnitIncompatibleReason::MissingCapabilities(jig, missing)),
+            };
+        }
+
+        candidates.sort_by(|a, b| a.1.len().cmp(&b.1.len()).then_with(|| a.0.cmp(&b.0)));
+        let (jig, missing) = candidates.remove(0);
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(UnitIncompatibleReason::MissingCapabilities(jig, missing))
+        }
+    }
+
+    /// The description of the loaded jig with the given name.
+    pub fn jig_description_named(&self, id: &UnitName) -> Option<JigDescription> {
+        self.jigs.borrow().get(id).map(|j| j.borrow().jig_description().clone())
+    }
+
+    /// The jig that a unit limited to `jigs` would run on.  This is the
+    /// current jig if there is one, or else the highest-ranked loaded jig
+    /// that the unit supports.
+    pub fn jig_description_for(&self, jigs: &[UnitName], config: &Config) -> Option<JigDescription> {
+        if let Some(ref jig) = *self.current_jig.borrow() {
+            return Some(jig.borrow().jig_description().clone());
+        }
+        let loaded = self.jigs.borrow();
+        self.rank_jig_candidates(config.pinned_jig().as_ref())
+            .into_iter()
+            .find(|c| loaded.contains_key(&c.name) && (jigs.is_empty() || jigs.contains(&c.name)))
+            .map(|c| loaded[&c.name].borrow().jig_description().clone())
+    }
+
     pub fn process_message(&self, msg: &UnitEvent) {
         match msg {
             &UnitEvent::ManagerRequest(ref req) => self.manager_request(req),
@@ -832,6 +1135,30 @@ impl UnitManager {
                     self.deactivate(&unit_id, &reason);
                 }
             },
+            &UnitEvent::Lifecycle(ref entry) => {
+                let mut units_to_deactivate = vec![];
+                for (logger_id, logger) in self.loggers.borrow().iter() {
+                    let lifecycle_status_msg = ManagerStatusMessage::Lifecycle(entry.clone());
+                    if let Err(e) = logger.borrow().output_message(lifecycle_status_msg) {
+                        units_to_deactivate.push((logger_id.clone(), format!("unable to send message to logger: {:?}", e)));
+                    }
+                }
+                for (unit_id, reason) in units_to_deactivate {
+                    self.deactivate(&unit_id, &reason);
+                }
+            },
+            &UnitEvent::SettingsChanged(ref settings) => self.cfg.lock().unwrap().apply_settings(settings),
+            &UnitEvent::Shutdown => {
+                // Give the jig a chance to run ExecStop, and to stop its daemon.
+                let current_jig = self.current_jig.borrow().as_ref().map(|j| j.borrow().id().clone());
+                if let Some(id) = current_jig {
+                    self.deactivate(&id, "shutting down");
+                }
+                // Exit through the control channel, so that it comes after
+                // anything the jig logged while stopping.
+                self.control_sender.send(ManagerControlMessage::new(&UnitName::internal("manager"),
+                                                                    ManagerControlMessageContents::Exit)).ok();
+            }
             _ => (),
         }
     }
@@ -855,13 +1182,29 @@ impl UnitManager {
     }
 
     fn manager_request(&self, msg: &ManagerControlMessage) {
-        let &ManagerControlMessage {sender: ref sender_name, contents: ref msg} = msg;
+        let &ManagerControlMessage {sender: ref sender_name, contents: ref msg, ref run} = msg;
 
         match *msg {
             ManagerControlMessageContents::Scenarios => self.send_scenarios_to(sender_name),
             ManagerControlMessageContents::Tests(ref scenario_name) => self.send_tests_to(sender_name, scenario_name),
-            ManagerControlMessageContents::Log(ref txt) => self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(sender_name.clone(), txt.clone()))),
-            ManagerControlMessageContents::LogError(ref txt) => self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(sender_name.clone(), txt.clone()))),
+            ManagerControlMessageContents::Log(ref kind, ref source, ref txt) => {
+                if *sender_name.kind() == UnitKind::Test {
+                    if let Some(ref mut artifacts) = *self.artifacts.borrow_mut() {
+                        artifacts.append_output(sender_name, source, txt).ok();
+                    }
+                }
+                // Output belongs to the run it was produced during, even if
+                // that run finished before the output was handled.
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new(sender_name.clone(), kind.clone(), source.clone(), txt.clone())
+                                                  .with_run(run.clone())));
+                // Tests declare the serial by printing EXCLAVE_SERIAL=VALUE.
+                if *sender_name.kind() == UnitKind::Test && *source == LogSource::Stdout {
+                    let prefix = format!("{}=", history::SERIAL_VARIABLE);
+                    if txt.starts_with(&prefix) {
+                        self.declare_serial(sender_name, txt[prefix.len()..].trim());
+                    }
+                }
+            }
             ManagerControlMessageContents::Scenario(ref new_scenario_name) => {
                 if self.get_scenario_named(new_scenario_name).is_some() {
                     self.select(new_scenario_name);
@@ -873,7 +1216,20 @@ impl UnitManager {
             ManagerControlMessageContents::Error(ref err) => {
                 self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(sender_name.clone(), err.clone())));
             },
+            ManagerControlMessageContents::Plan(ref scenario_name, dot) => self.send_plan_to(sender_name, scenario_name, dot),
             ManagerControlMessageContents::Jig => self.send_jig_to(sender_name),
+            ManagerControlMessageContents::RecheckJigs => self.recheck_jigs(),
+            ManagerControlMessageContents::Wear => {
+                let messages = self.wear_messages();
+                self.send_messages_to(sender_name, messages);
+            }
+            ManagerControlMessageContents::Serial(ref serial) => self.declare_serial(sender_name, serial),
+            ManagerControlMessageContents::History(ref serial) => {
+                let runs = self.serial_history(serial);
+                self.send_messages_to(sender_name, vec![ManagerStatusMessage::History(serial.clone(), runs)]);
+            }
+            ManagerControlMessageContents::Login(ref id) => self.log_in(sender_name, id),
+            ManagerControlMessageContents::Logout => self.log_out(sender_name),
             ManagerControlMessageContents::InitialGreeting => {
                 // Send some initial information to the client.
                 self.send_hello_to(sender_name);
@@ -883,6 +1239,7 @@ impl UnitManager {
                 if let Some(ref sc) = *self.current_scenario.borrow() {
                     self.send_scenario_to(sender_name, &sc.borrow().id().clone());
                 }
+                self.send_messages_to(sender_name, vec![ManagerStatusMessage::Operator(self.bc.operator())]);
             },
             ManagerControlMessageContents::ChildExited => {
                 self.bc.broadcast(&UnitEvent::Status(UnitStatusEvent::new_active_failed(sender_name, "Unit unexpectedly exited".to_owned())));
@@ -894,7 +1251,7 @@ impl UnitManager {
                 }
             },
             ManagerControlMessageContents::Unimplemented(ref verb, ref remainder) => {
-                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(sender_name.clone(), format!("unimplemented verb: {} (args: {})", verb, remainder))));
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new(sender_name.clone(), LogType::Warn, LogSource::Internal, format!("unimplemented verb: {} (args: {})", verb, remainder))));
             },
             ManagerControlMessageContents::StartScenario(ref scenario_name_opt) => {
                 // If a scenario exists and is running, don't start a new one.
@@ -916,30 +1273,81 @@ impl UnitManager {
                         Some(ref scenario) => scenario.borrow().id().clone()
                     }
                 };
+                // Selecting fails if the scenario doesn't exist or isn't compatible.
+                if self.current_scenario.borrow().as_ref().map(|s| s.borrow().id() != &scenario_name).unwrap_or(true) {
+                    self.refuse_start(sender_name, &scenario_name, "scenario could not be selected");
+                    return;
+                }
+
+                if let Err(e) = self.check_login_required() {
+                    self.refuse_start(sender_name, &scenario_name, e);
+                    return;
+                }
+                if let Err(e) = self.check_self_test(&scenario_name) {
+                    self.refuse_start(sender_name, &scenario_name, e);
+                    return;
+                }
+                if let Err(e) = self.check_wear(&scenario_name) {
+                    self.refuse_start(sender_name, &scenario_name, e);
+                    return;
+                }
+
+                // Everything logged from here until the scenario finishes belongs to this run.
+                let run_id = self.bc.begin_run();
+                if let Err(e) = self.check_out_pools(&scenario_name, &run_id) {
+                    self.refuse_start(sender_name, &scenario_name, e);
+                    // The device is still waiting to be tested.
+                    let serial = self.bc.run_stamp().and_then(|run| run.serial);
+                    self.bc.end_run();
+                    if let Some(serial) = serial {
+                        self.bc.set_serial(serial);
+                    }
+                    return;
+                }
+                self.create_artifacts(&run_id, &scenario_name);
+                self.broadcast_lifecycle(&scenario_name, LifecycleKind::ScenarioStarted, String::new());
 
                 self.activate(&scenario_name);
+                if !self.active.borrow().contains_key(&scenario_name) {
+                    // The run has begun, so close it rather than leave it open.
+                    self.finish_scenario(&scenario_name, 500, "unable to activate scenario");
+                    return;
+                }
                 self.broadcast_message(ManagerStatusMessage::Start(scenario_name));
+                if let Some(jig_id) = self.current_jig.borrow().as_ref().map(|j| j.borrow().id().clone()) {
+                    self.count_wear(&jig_id);
+                }
             },
             ManagerControlMessageContents::Skip(ref test_name, ref reason) => {
+                self.broadcast_lifecycle(test_name, LifecycleKind::TestSkipped, reason.clone());
+                if let Some(ref mut artifacts) = *self.artifacts.borrow_mut() {
+                    artifacts.test_skipped(test_name, reason);
+                }
                 self.broadcast_skipped(test_name, reason);
             },
             ManagerControlMessageContents::TestStarted => {
+                self.broadcast_lifecycle(sender_name, LifecycleKind::TestStarted, String::new());
+                self.count_wear(sender_name);
+                if let Some(ref mut artifacts) = *self.artifacts.borrow_mut() {
+                    artifacts.test_started(sender_name);
+                }
                 self.broadcast_message(ManagerStatusMessage::Running(sender_name.clone()));
             }
             ManagerControlMessageContents::TestFinished(result, ref message) => {
+                match result {
+                    0 => self.broadcast_lifecycle(sender_name, LifecycleKind::TestPassed, message.clone()),
+                    i => self.broadcast_lifecycle(sender_name, LifecycleKind::TestFailed, format!("{} (result {})", message, i)),
+                }
+                if let Some(ref mut artifacts) = *self.artifacts.borrow_mut() {
+                    artifacts.test_finished(sender_name, result, message);
+                }
                 self.broadcast_message(match result {
                     0 => ManagerStatusMessage::Pass(sender_name.clone(), message.clone()),
                     i => ManagerStatusMessage::Fail(sender_name.clone(), i, message.clone()),
                 });
             }
             ManagerControlMessageContents::ScenarioFinished(code, ref message) => {
-                // Deactivate the current scenario.
-                // Since a scenario is finishing, the current scenario MUST not be None.
-                {
-                    let cs = self.current_scenario.borrow();
-                    self.deactivate(cs.as_ref().unwrap().borrow().id(), &message);
-                }
-                self.broadcast_finished(sender_name, code, message);
+                self.finish_scenario(sender_name, code, message);
             }
             ManagerControlMessageContents::StartTest(ref test_name) => {
                 self.activate(test_name);
@@ -955,15 +1363,33 @@ impl UnitManager {
                 self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(sender_name.clone(), txt)));
                 self.bc.broadcast(&UnitEvent::Shutdown);
             }
+            ManagerControlMessageContents::Exit => self.bc.broadcast(&UnitEvent::Exit),
         }
     }
 
+    /// Stop the current scenario, and record and close the run it was part of.
+    fn finish_scenario(&self, scenario_name: &UnitName, code: u32, message: &str) {
+        // Deactivate the current scenario.
+        // Since a scenario is finishing, the current scenario MUST not be None.
+        {
+            let cs = self.current_scenario.borrow();
+            self.deactivate(cs.as_ref().unwrap().borrow().id(), message);
+        }
+        self.settle_pools(code == 200);
+        self.broadcast_finished(scenario_name, code, &message.to_owned());
+        self.broadcast_lifecycle(scenario_name, LifecycleKind::ScenarioFinished, format!("{} {}", code, message));
+        self.record_self_test(scenario_name, code);
+        self.record_history(scenario_name, code, message);
+        self.finish_artifacts(code, message);
+        self.bc.end_run();
+    }
+
     pub fn send_hello_to(&self, sender_name: &UnitName) {
         self.send_messages_to(sender_name, vec![ManagerStatusMessage::Hello("Jig/20 1.0".to_owned())]);
     }
 
     pub fn send_jig_to(&self, sender_name: &UnitName) {
-        let messages = match *self.current_jig.borrow() {
+        let mut messages = match *self.current_jig.borrow() {
             None => vec![ManagerStatusMessage::Jig(None)],
             Some(ref jig_rc) => {
                 let jig = jig_rc.borrow();
@@ -974,6 +1400,7 @@ impl UnitManager {
                 ]
             }
         };
+        messages.extend(self.jig_candidates().into_iter().map(ManagerStatusMessage::JigCandidate));
         self.send_messages_to(sender_name, messages);
     }
 
@@ -1029,6 +1456,47 @@ impl UnitManager {
         }
     }
 
+    /// Send the resolved plan of a scenario to the specified recipient.
+    /// If no scenario name is specified, send the current scenario.
+    pub fn send_plan_to(&self, sender_name: &UnitName, scenario_name_opt: &Option<UnitName>, dot: bool) {
+        let scenario_id = match *scenario_name_opt {
+            Some(ref n) => n.clone(),
+            None => match *self.current_scenario.borrow() {
+                Some(ref cs) => cs.borrow().id().clone(),
+                None => {
+                    self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(sender_name.clone(), "unable to show plan, no scenario specified and no scenario selected".to_owned())));
+                    return;
+                }
+            }
+        };
+        let scenario_description = match self.scenarios.borrow().get(&scenario_id) {
+            Some(sc) => sc.borrow().scenario_description().clone(),
+            None => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(sender_name.clone(), format!("unable to show plan, scenario {} not found", scenario_id))));
+                return;
+            }
+        };
+        let tests: Vec<TestDescription> = self.tests.borrow().values().map(|t| t.borrow().test_description().clone()).collect();
+        let config = self.cfg.lock().unwrap();
+        let jig = self.jig_description_for(scenario_description.jigs(), &config);
+        match Plan::build(&scenario_description, &tests, jig.as_ref()) {
+            Err(e) => self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(sender_name.clone(), format!("unable to resolve plan for {}: {}", scenario_id, e)))),
+            Ok(mut plan) => {
+                plan.expand_commands(&tests, |test| {
+                    config.expansion(test)
+                        .with_jig(jig.as_ref().map(|j| j.id()))
+                        .with_scenario(Some(&scenario_id))
+                });
+                let msg = if dot {
+                    ManagerStatusMessage::Dot(scenario_id, plan.to_dot())
+                } else {
+                    ManagerStatusMessage::Plan(plan)
+                };
+                self.send_messages_to(sender_name, vec![msg]);
+            }
+        }
+    }
+
     fn broadcast_selected_jig(&self) {
         let jig_opt = self.current_jig.borrow();
         match *jig_opt {
@@ -1129,6 +1597,389 @@ impl UnitManager {
         }
     }
 
+    /// If the current jig has a self-test scenario, refuse to start any
+    /// other scenario until the self-test has passed recently enough.
+    fn check_self_test(&self, scenario_name: &UnitName) -> Result<(), GateError> {
+        let (jig_id, self_test, validity) = match *self.current_jig.borrow() {
+            None => return Ok(()),
+            Some(ref jig) => {
+                let jig = jig.borrow();
+                match *jig.jig_description().self_test_scenario() {
+                    None => return Ok(()),
+                    Some(ref s) => (jig.id().clone(), s.clone(), jig.jig_description().self_test_validity()),
+                }
+            }
+        };
+        if self_test == *scenario_name {
+            return Ok(());
+        }
+        let gate = SelfTestGate::open_for_check(&self.cfg.lock().unwrap().state_directory())?;
+        gate.check(&jig_id, &self_test, validity, SystemTime::now())
+    }
+
+    /// If `scenario_name` is the current jig's self-test, open or close
+    /// the gate depending on how it finished.
+    fn record_self_test(&self, scenario_name: &UnitName, code: u32) {
+        let (jig_id, validity) = match *self.current_jig.borrow() {
+            None => return,
+            Some(ref jig) => {
+                let jig = jig.borrow();
+                if jig.jig_description().self_test_scenario().as_ref() != Some(scenario_name) {
+                    return;
+                }
+                (jig.id().clone(), jig.jig_description().self_test_validity())
+            }
+        };
+        let passed = code == 200;
+        let result = SelfTestGate::open(&self.cfg.lock().unwrap().state_directory()).and_then(|mut gate| {
+            if passed {
+                gate.record_pass(&jig_id, SystemTime::now())
+            } else {
+                gate.record_fail(&jig_id)
+            }
+        });
+        let entry = match (result, passed) {
+            (Err(e), _) => LogEntry::new_error(jig_id.clone(), format!("unable to record self-test result: {}", e)),
+            (Ok(()), true) => LogEntry::new_info(
+                jig_id.clone(),
+                format!("self-test passed; other scenarios may run for the next {}", selftest::brief_duration(&validity)),
+            ),
+            (Ok(()), false) => LogEntry::new(
+                jig_id.clone(),
+                LogType::Warn,
+                LogSource::Internal,
+                format!("self-test failed; other scenarios will be refused until {} passes", scenario_name),
+            ),
+        };
+        self.bc.broadcast(&UnitEvent::Log(entry));
+    }
+
+    /// Open the journal of the named pool, voiding anything left checked
+    /// out by a run that never finished.
+    fn open_pool(&self, pool: &str, config: &Config) -> Result<(PoolDescription, PoolJournal), PoolError> {
+        let description = match config.pool_path(pool) {
+            None => return Err(PoolError::NotFound(pool.to_owned())),
+            Some(path) => PoolDescription::from_path(pool, &path)?,
+        };
+        let mut journal = PoolJournal::open(&config.state_directory(), pool)?;
+        for (value, run) in journal.pending() {
+            if self.allocations.borrow().iter().any(|a| a.pool == pool && a.value == value) {
+                continue;
+            }
+            journal.void(&value, &run)?;
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new(
+                UnitName::internal("pool"),
+                LogType::Warn,
+                LogSource::Internal,
+                format!("{} {} was checked out by run {}, which never finished; it will not be issued again", pool, value, run),
+            )));
+        }
+        Ok((description, journal))
+    }
+
+    /// Check a value out of each of the scenario's pools.  If any pool
+    /// can't provide one, the values already checked out are returned.
+    fn check_out_pools(&self, scenario_name: &UnitName, run_id: &str) -> Result<(), PoolError> {
+        let pools = match self.scenarios.borrow().get(scenario_name) {
+            None => return Ok(()),
+            Some(scenario) => scenario.borrow().scenario_description().pools().clone(),
+        };
+        self.allocations.borrow_mut().clear();
+        for pool in pools {
+            let result = {
+                let config = self.cfg.lock().unwrap();
+                self.open_pool(&pool, &config)
+                    .and_then(|(description, mut journal)| journal.check_out(&description, run_id))
+            };
+            match result {
+                Ok(allocation) => {
+                    self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(
+                        UnitName::internal("pool"),
+                        format!("checked out {} {} as {}", allocation.pool, allocation.value, allocation.variable),
+                    )));
+                    self.allocations.borrow_mut().push(allocation);
+                }
+                Err(e) => {
+                    self.settle_pools(false);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Commit the values checked out for this run if it passed, or return
+    /// them if it didn't.
+    fn settle_pools(&self, passed: bool) {
+        let allocations: Vec<Allocation> = self.allocations.borrow_mut().drain(..).collect();
+        for allocation in allocations {
+            let result = {
+                let config = self.cfg.lock().unwrap();
+                PoolJournal::open(&config.state_directory(), &allocation.pool).and_then(|mut journal| {
+                    if passed {
+                        journal.commit(&allocation)
+                    } else {
+                        journal.give_back(&allocation)
+                    }
+                })
+            };
+            let entry = match result {
+                Ok(()) if passed => LogEntry::new_info(UnitName::internal("pool"), format!("committed {} {}", allocation.pool, allocation.value)),
+                Ok(()) => LogEntry::new_info(UnitName::internal("pool"), format!("returned {} {}", allocation.pool, allocation.value)),
+                Err(e) => LogEntry::new_error(UnitName::internal("pool"), format!("unable to settle {} {}: {}", allocation.pool, allocation.value, e)),
+            };
+            self.bc.broadcast(&UnitEvent::Log(entry));
+        }
+    }
+
+    /// Environment variables for everything run as part of the current
+    /// scenario, such as the values checked out of its pools.
+    pub fn run_environment(&self) -> Vec<(String, String)> {
+        let mut env: Vec<(String, String)> = self.allocations.borrow().iter().map(|a| (a.variable.clone(), a.value.clone())).collect();
+        if let Some(serial) = self.bc.run_stamp().and_then(|run| run.serial) {
+            env.push((history::SERIAL_VARIABLE.to_owned(), serial));
+        }
+        if let Some(operator) = self.bc.operator() {
+            env.push(("EXCLAVE_OPERATOR".to_owned(), operator));
+        }
+        env
+    }
+
+    /// Make `serial` the identity of the device in the current run, or in
+    /// the next run if none is in progress, and tell interfaces how that
+    /// device has fared before.
+    fn declare_serial(&self, sender_name: &UnitName, serial: &str) {
+        if serial.is_empty() || serial.contains(char::is_whitespace) {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(sender_name.clone(), format!("invalid serial number \"{}\"", serial))));
+            return;
+        }
+        if let Some(previous) = self.bc.run_stamp().and_then(|run| run.serial) {
+            if previous == serial {
+                return;
+            }
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new(sender_name.clone(), LogType::Warn, LogSource::Internal,
+                                                            format!("serial number changed from {} to {}", previous, serial))));
+        }
+        let message = match self.bc.set_serial(serial.to_owned()) {
+            Some(_) => format!("device under test is {}", serial),
+            None => format!("device under test is {}, for the next run", serial),
+        };
+        self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(sender_name.clone(), message)));
+
+        let runs = self.serial_history(serial);
+        let kind = if runs.iter().any(|r| !r.passed()) { LogType::Warn } else { LogType::Info };
+        self.bc.broadcast(&UnitEvent::Log(LogEntry::new(UnitName::internal("history"), kind, LogSource::Internal,
+                                                        format!("{} has {}", serial, history::summary(&runs)))));
+        self.broadcast_message(ManagerStatusMessage::History(serial.to_owned(), runs));
+    }
+
+    /// Log why a scenario can't be started, and tell interfaces that it was refused.
+    fn refuse_start<E: fmt::Display>(&self, sender_name: &UnitName, scenario_name: &UnitName, reason: E) {
+        self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(sender_name.clone(),
+                                                              format!("unable to start {}: {}", scenario_name, reason))));
+        self.broadcast_message(ManagerStatusMessage::Refused(scenario_name.clone(), format!("{}", reason)));
+    }
+
+    /// Refuse to start scenarios if RequireLogin is set and nobody is logged in.
+    fn check_login_required(&self) -> Result<(), LoginError> {
+        if self.cfg.lock().unwrap().require_login() && self.bc.operator().is_none() {
+            return Err(LoginError::NotLoggedIn);
+        }
+        Ok(())
+    }
+
+    /// Make sure the operator may log in or out now.  If they're logging in
+    /// and there is a roster, returns their name from it.
+    fn check_login(&self, id: Option<&str>) -> Result<Option<String>, LoginError> {
+        // Each run must be recorded against the operator who started it.
+        if self.bc.run_stamp().is_some() {
+            return Err(LoginError::ScenarioRunning);
+        }
+        let id = match id {
+            None => return Ok(None),
+            Some(id) => id,
+        };
+        operator::check_id(id)?;
+        let roster_path = match self.cfg.lock().unwrap().operator_roster() {
+            None => return Ok(None),
+            Some(path) => path,
+        };
+        match Roster::from_path(&roster_path) {
+            Ok(roster) => roster.find(id),
+            Err(e) => Err(LoginError::RosterUnreadable(roster_path, format!("{}", e))),
+        }
+    }
+
+    fn log_in(&self, sender_name: &UnitName, id: &str) {
+        let name = match self.check_login(Some(id)) {
+            Ok(name) => name,
+            Err(e) => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(sender_name.clone(), format!("unable to log in {}: {}", id, e))));
+                self.send_messages_to(sender_name, vec![ManagerStatusMessage::LoginRefused(id.to_owned(), format!("{}", e))]);
+                return;
+            }
+        };
+        if let Some(previous) = self.bc.operator() {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(sender_name.clone(), format!("operator {} logged out", previous))));
+        }
+        self.bc.set_operator(Some(id.to_owned()));
+        let message = match name {
+            None => format!("operator {} logged in", id),
+            Some(name) => format!("operator {} ({}) logged in", id, name),
+        };
+        self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(sender_name.clone(), message)));
+        self.broadcast_message(ManagerStatusMessage::Operator(Some(id.to_owned())));
+    }
+
+    fn log_out(&self, sender_name: &UnitName) {
+        let id = match self.bc.operator() {
+            Some(id) => id,
+            None => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new(sender_name.clone(), LogType::Warn, LogSource::Internal,
+                                                                "unable to log out: nobody is logged in".to_owned())));
+                return;
+            }
+        };
+        if let Err(e) = self.check_login(None) {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(sender_name.clone(), format!("unable to log out {}: {}", id, e))));
+            self.send_messages_to(sender_name, vec![ManagerStatusMessage::LoginRefused(id, format!("{}", e))]);
+            return;
+        }
+        self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(sender_name.clone(), format!("operator {} logged out", id))));
+        self.bc.set_operator(None);
+        self.broadcast_message(ManagerStatusMessage::Operator(None));
+    }
+
+    /// Every previous run of the device with serial number `serial`.
+    fn serial_history(&self, serial: &str) -> Vec<RunRecord> {
+        let state_dir = self.cfg.lock().unwrap().state_directory();
+        match history::for_serial(&state_dir, serial) {
+            Ok(runs) => runs,
+            Err(e) => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(UnitName::internal("history"),
+                                                                      format!("unable to read run history: {}", e))));
+                vec![]
+            }
+        }
+    }
+
+    /// Add the run that just finished to the history.
+    fn record_history(&self, scenario_name: &UnitName, code: u32, message: &str) {
+        let run = match self.bc.run_stamp() {
+            None => return,
+            Some(r) => r,
+        };
+        let record = RunRecord {
+            run_id: run.id,
+            serial: run.serial,
+            operator: self.bc.operator(),
+            scenario: scenario_name.id().clone(),
+            jig: self.current_jig.borrow().as_ref().map(|jig| jig.borrow().id().id().clone()),
+            finished: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            result: code,
+            message: message.to_owned(),
+        };
+        let state_dir = self.cfg.lock().unwrap().state_directory();
+        if let Err(e) = history::append(&state_dir, &record) {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(UnitName::internal("history"),
+                                                                  format!("unable to record run: {}", e))));
+        }
+    }
+
+    /// The current jig, along with its wear thresholds.
+    fn current_jig_wear(&self) -> Option<(UnitName, BTreeMap<UnitName, Thresholds>)> {
+        self.current_jig.borrow().as_ref().map(|jig| {
+            let jig = jig.borrow();
+            (jig.id().clone(), jig.jig_description().wear_thresholds().clone())
+        })
+    }
+
+    /// Refuse to start a scenario if the jig, or any test in the scenario,
+    /// has reached its wear limit.
+    fn check_wear(&self, scenario_name: &UnitName) -> Result<(), WearError> {
+        let (jig_id, thresholds) = match self.current_jig_wear() {
+            None => return Ok(()),
+            Some(w) => w,
+        };
+        let tests = match self.scenarios.borrow().get(scenario_name) {
+            None => vec![],
+            Some(scenario) => scenario.borrow().test_sequence(),
+        };
+        let limited: Vec<(&UnitName, &Thresholds)> = thresholds
+            .iter()
+            .filter(|&(counter, t)| t.limit.is_some() && (*counter == jig_id || tests.contains(counter)))
+            .collect();
+        if limited.is_empty() {
+            return Ok(());
+        }
+        let counters = WearCounters::open_for_check(&self.cfg.lock().unwrap().state_directory())?;
+        counters.check(&jig_id, &limited)
+    }
+
+    /// Count one use of `counter`, which is either the current jig or one
+    /// of the tests it has wear thresholds for, and warn if it's wearing out.
+    fn count_wear(&self, counter: &UnitName) {
+        let (jig_id, thresholds) = match self.current_jig_wear() {
+            None => return,
+            Some(w) => w,
+        };
+        if *counter != jig_id && !thresholds.contains_key(counter) {
+            return;
+        }
+        let result = WearCounters::open(&self.cfg.lock().unwrap().state_directory())
+            .and_then(|mut counters| counters.increment(&jig_id, counter));
+        let count = match result {
+            Ok(c) => c,
+            Err(e) => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(jig_id.clone(), format!("unable to update wear counter for {}: {}", counter, e))));
+                return;
+            }
+        };
+        let thresholds = thresholds.get(counter).cloned().unwrap_or_default();
+        let warning = match (thresholds.warning, thresholds.limit) {
+            (_, Some(limit)) if count >= limit => Some(format!(
+                "{} has been used {} times, reaching its limit of {}; no more runs will start until it is reset",
+                counter, count, limit
+            )),
+            (Some(warning), _) if count >= warning => Some(format!(
+                "{} has been used {} times, past its warning level of {}; maintenance is due",
+                counter, count, warning
+            )),
+            _ => None,
+        };
+        if let Some(warning) = warning {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new(jig_id.clone(), LogType::Warn, LogSource::Internal, warning)));
+        }
+        self.broadcast_message(ManagerStatusMessage::Wear(jig_id, format!("{}", counter), count, thresholds));
+    }
+
+    /// A WEAR message for each of the current jig's counters, including
+    /// ones with thresholds that haven't been used yet.
+    fn wear_messages(&self) -> Vec<ManagerStatusMessage> {
+        let (jig_id, thresholds) = match self.current_jig_wear() {
+            None => return vec![],
+            Some(w) => w,
+        };
+        let counts = match WearCounters::open(&self.cfg.lock().unwrap().state_directory()) {
+            Ok(counters) => counters.counters(&jig_id),
+            Err(e) => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(jig_id.clone(), format!("unable to read wear counters: {}", e))));
+                return vec![];
+            }
+        };
+        let mut all: BTreeMap<String, (u64, Thresholds)> = BTreeMap::new();
+        all.insert(format!("{}", jig_id), (0, Thresholds::default()));
+        for (counter, t) in thresholds {
+            all.insert(format!("{}", counter), (0, t));
+        }
+        for (counter, count) in counts {
+            all.entry(counter).or_insert((0, Thresholds::default())).0 = count;
+        }
+        all.into_iter()
+            .map(|(counter, (count, t))| ManagerStatusMessage::Wear(jig_id.clone(), counter, count, t))
+            .collect()
+    }
+
     fn broadcast_finished(&self, unit_id: &UnitName, code: u32, message: &String) {
         let msg = ManagerStatusMessage::Finished(unit_id.clone(), code, message.clone());
         for (interface_id, _) in self.interfaces.borrow().iter() {
@@ -1136,6 +1987,84 @@ impl UnitManager {
         }
     }
 
+    /// Create a directory to hold the output of this run, if an artifact
+    /// directory has been configured.
+    fn create_artifacts(&self, run_id: &str, scenario_id: &UnitName) {
+        *self.artifacts.borrow_mut() = None;
+        let root = match self.cfg.lock().unwrap().artifact_directory() {
+            None => return,
+            Some(r) => r,
+        };
+        let (scenario_name, tests) = match self.scenarios.borrow().get(scenario_id) {
+            None => return,
+            Some(scenario) => {
+                let scenario = scenario.borrow();
+                let tests = scenario.test_sequence().into_iter().map(|test_id| {
+                    let name = match self.tests.borrow().get(&test_id) {
+                        Some(test) => test.borrow().name().clone(),
+                        None => test_id.id().clone(),
+                    };
+                    (test_id, name)
+                }).collect();
+                (scenario.name().clone(), tests)
+            }
+        };
+        let jig_id = self.current_jig.borrow().as_ref().map(|jig| jig.borrow().id().clone());
+
+        match RunArtifacts::create(&root, run_id, scenario_id, &scenario_name, jig_id.as_ref(), tests) {
+            Ok(a) => *self.artifacts.borrow_mut() = Some(a),
+            Err(e) => self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(scenario_id.clone(),
+                                        format!("unable to create artifact directory: {}", e)))),
+        }
+    }
+
+    /// Write out the manifest for the current run, and remove old runs.
+    fn finish_artifacts(&self, code: u32, message: &str) {
+        let mut artifacts = match self.artifacts.borrow_mut().take() {
+            None => return,
+            Some(a) => a,
+        };
+        artifacts.set_serial(self.bc.run_stamp().and_then(|run| run.serial));
+        artifacts.set_operator(self.bc.operator());
+        let id = UnitName::internal("artifacts");
+        let run_dir = artifacts.dir().to_owned();
+        match artifacts.finish(code, message) {
+            Ok(path) => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(id.clone(), format!("wrote run manifest {}", path.display()))));
+                match report::write_report(&run_dir) {
+                    Ok(report) => self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(id.clone(), format!("wrote run report {}", report.display())))),
+                    Err(e) => self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(id.clone(), format!("unable to write run report: {}", e)))),
+                }
+            }
+            Err(e) => self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(id.clone(), format!("unable to write run manifest: {}", e)))),
+        }
+
+        let cfg = self.cfg.lock().unwrap();
+        if let (Some(root), &Some(keep)) = (cfg.artifact_directory(), cfg.artifact_retention()) {
+            match artifacts::prune_runs(&root, keep) {
+                Ok(removed) => for run in removed {
+                    self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(id.clone(), format!("removed old run {}", run.display()))));
+                },
+                Err(e) => self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(id.clone(), format!("unable to remove old runs: {}", e)))),
+            }
+        }
+    }
+
+    /// Environment variables that should be set for a test when it runs.
+    pub fn test_environment(&self, test_id: &UnitName) -> Vec<(String, String)> {
+        let mut env = self.run_environment();
+        if let Some(ref artifacts) = *self.artifacts.borrow() {
+            if let Ok(dir) = artifacts.test_dir(test_id) {
+                env.push(("EXCLAVE_ARTIFACT_DIR".to_owned(), dir.to_string_lossy().into_owned()));
+            }
+        }
+        env
+    }
+
+    fn broadcast_lifecycle(&self, unit_id: &UnitName, kind: LifecycleKind, message: String) {
+        self.bc.broadcast(&UnitEvent::Lifecycle(LifecycleEntry::new(unit_id.clone(), kind, message)));
+    }
+
     fn broadcast_message(&self, msg: ManagerStatusMessage) {
         for (interface_id, _) in self.interfaces.borrow().iter() {
             self.send_messages_to(interface_id, vec![msg.clone()]);
@@ -2,21 +2,22 @@ extern crate runny;
 extern crate systemd_parser;
 
 use std::cell::RefCell;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Read, Write, Error, ErrorKind};
+use std::io::{BufRead, BufReader, Write, Error, ErrorKind};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
 use std::thread;
 use std::time::Duration;
 
-use config::Config;
+use config::{command_with_environment, Config};
+use inherit;
 use unit::{UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitIncompatibleReason, UnitSelectError, UnitDeselectError,
            UnitName};
+use unitbroadcaster::{LogSource, LogType};
+use units::jig::JigChoice;
 use unitmanager::{ManagerControlMessage, ManagerControlMessageContents, ManagerStatusMessage,
                   UnitManager};
 
 use self::systemd_parser::items::DirectiveEntry;
-use self::runny::Runny;
 use self::runny::running::{Running, RunningOutput};
 
 #[derive(Clone, Copy)]
@@ -25,6 +26,9 @@ enum InterfaceFormat {
     JSON,
 }
 
+/// Directives recognized in the [Interface] section.
+pub const DIRECTIVES: &[&str] = &["Name", "Description", "Jigs", "WorkingDirectory", "ExecStart", "Format"];
+
 /// A struct defining an in-memory representation of a .Interface file
 pub struct InterfaceDescription {
     /// The id of the unit (including the kind)
@@ -53,9 +57,12 @@ impl InterfaceDescription {
     pub fn from_path(path: &Path) -> Result<InterfaceDescription, UnitDescriptionError> {
         let unit_name = UnitName::from_path(path)?;
 
-        // Parse the file into a systemd unit_file object
-        let mut contents = String::with_capacity(8192);
-        File::open(path)?.read_to_string(&mut contents)?;
+        // Read the file, along with any drop-in fragments and the units it inherits from
+        let contents = inherit::read_unit(path)?;
+        Self::from_string(&contents, unit_name)
+    }
+
+    pub fn from_string(contents: &str, unit_name: UnitName) -> Result<InterfaceDescription, UnitDescriptionError> {
         let unit_file = systemd_parser::parse_string(&contents)?;
 
         if !unit_file.has_category("Interface") {
@@ -128,6 +135,10 @@ impl InterfaceDescription {
         Ok(interface_description)
     }
 
+    pub fn jigs(&self) -> &Vec<UnitName> {
+        &self.jigs
+    }
+
     /// Returns true if this test is supported on the named jig.
     pub fn supports_jig(&self, name: &UnitName) -> bool {
         self.jigs.contains(name)
@@ -203,9 +214,10 @@ impl Interface {
         manager: &UnitManager,
         config: &Config,
     ) -> Result<(), UnitActivateError> {
-        let mut running = Runny::new(self.exec_start.as_str())
-                    .directory(&Some(config.working_directory(&self.working_directory)))
-                    .start()?;
+        let expansion = config.expansion(self.id());
+        let mut cmd = command_with_environment(&expansion.expand(&self.exec_start)?, &config.environment());
+        cmd.directory(&Some(expansion.expand_path(&config.working_directory(&self.working_directory))?));
+        let mut running = cmd.start()?;
 
         let stdout = running.take_output();
         let stderr = running.take_error();
@@ -285,6 +297,15 @@ impl Interface {
                 Some(jig_name) => writeln!(process, "JIG {}", Self::cfti_escape(&format!("{}", jig_name))),
                 None => writeln!(process, "JIG"),
             },
+            ManagerStatusMessage::JigCandidate(candidate) => {
+                write!(process, "JIGCANDIDATE {} {} ", Self::cfti_escape(candidate.name.id()), candidate.priority)?;
+                match candidate.choice {
+                    JigChoice::Selected => writeln!(process, "selected"),
+                    JigChoice::Outranked(other) => writeln!(process, "outranked {}", Self::cfti_escape(other.id())),
+                    JigChoice::NotPinned(other) => writeln!(process, "not-pinned {}", Self::cfti_escape(other.id())),
+                    JigChoice::Incompatible(reason) => writeln!(process, "rejected {}", Self::cfti_escape(&reason)),
+                }
+            }
             ManagerStatusMessage::Hello(id) => writeln!(process, "HELLO {}", Self::cfti_escape(&format!("{}", id))),
             ManagerStatusMessage::Tests(scenario, tests) => {
                 write!(process, "TESTS {}", Self::cfti_escape(scenario.id()))?;
@@ -307,16 +328,7 @@ impl Interface {
             ManagerStatusMessage::Describe(id, field, value) => {
                 writeln!(process, "DESCRIBE {}", Self::cfti_escape(&format!("{} {} {} {}", id.kind(), field, id.id(), value)))
             }
-            ManagerStatusMessage::Log(l) => writeln!(
-                process,
-                "LOG {}\t{}\t{}\t{}\t{}\t{}",
-                l.kind().as_str(),
-                Self::cfti_escape(l.id().id()),
-                l.id().kind(),
-                l.secs(),
-                l.nsecs(),
-                Self::cfti_escape(l.message())
-            ),
+            ManagerStatusMessage::Log(l) => writeln!(process, "LOG {}", l.tsv()),
             ManagerStatusMessage::Running(test) => writeln!(process, "RUNNING {}", Self::cfti_escape(test.id())),
             ManagerStatusMessage::Skipped(test, reason) => {
                 writeln!(process, "SKIP {} {}", Self::cfti_escape(test.id()), Self::cfti_escape(&reason))
@@ -332,7 +344,68 @@ impl Interface {
             }
             ManagerStatusMessage::Start(scenario) => {
                 writeln!(process, "START {}", Self::cfti_escape(scenario.id()))
-            }             /*
+            }
+            ManagerStatusMessage::Plan(plan) => {
+                writeln!(process, "PLAN {} {}", Self::cfti_escape(plan.scenario.id()), plan.steps.len())?;
+                let mut position = 0;
+                for step in &plan.steps {
+                    let index = if step.assumed {
+                        "-".to_owned()
+                    } else {
+                        position += 1;
+                        format!("{}", position)
+                    };
+                    write!(process, "PLANSTEP {} {} {}", Self::cfti_escape(plan.scenario.id()), index, Self::cfti_escape(step.test.id()))?;
+                    if step.assumed {
+                        write!(process, " assumed")?;
+                    }
+                    if step.requested {
+                        write!(process, " requested")?;
+                    }
+                    for name in &step.required_by {
+                        write!(process, " required-by:{}", Self::cfti_escape(name.id()))?;
+                    }
+                    for name in &step.suggested_by {
+                        write!(process, " suggested-by:{}", Self::cfti_escape(name.id()))?;
+                    }
+                    for name in &step.provides {
+                        write!(process, " provides:{}", Self::cfti_escape(name.id()))?;
+                    }
+                    writeln!(process)?;
+                }
+                Ok(())
+            }
+            ManagerStatusMessage::Dot(scenario, graph) => {
+                writeln!(process, "DOT {} {}", Self::cfti_escape(scenario.id()), Self::cfti_escape(&graph))
+            }
+            ManagerStatusMessage::Refused(scenario, reason) => {
+                writeln!(process, "REFUSED {} {}", Self::cfti_escape(scenario.id()), Self::cfti_escape(&reason))
+            }
+            ManagerStatusMessage::Wear(jig, counter, count, thresholds) => {
+                let threshold = |t: Option<u64>| t.map(|n| format!("{}", n)).unwrap_or_else(|| "-".to_owned());
+                writeln!(process, "WEAR {} {} {} {} {}", Self::cfti_escape(jig.id()), Self::cfti_escape(&counter), count,
+                         threshold(thresholds.warning), threshold(thresholds.limit))
+            }
+            ManagerStatusMessage::History(serial, runs) => {
+                let failures = runs.iter().filter(|r| !r.passed()).count();
+                writeln!(process, "HISTORY {} {} {}", Self::cfti_escape(&serial), runs.len(), failures)?;
+                for run in &runs {
+                    writeln!(process, "HISTORYRUN {} {} {} {} {} {} {}", Self::cfti_escape(&serial), Self::cfti_escape(&run.run_id),
+                             Self::cfti_escape(&run.scenario), Self::cfti_escape(run.jig.as_ref().unwrap_or(&"-".to_owned())),
+                             Self::cfti_escape(run.operator.as_ref().unwrap_or(&"-".to_owned())),
+                             run.result, Self::cfti_escape(&run.message))?;
+                }
+                Ok(())
+            }
+            ManagerStatusMessage::Operator(operator) => match operator {
+                Some(id) => writeln!(process, "OPERATOR {}", Self::cfti_escape(&id)),
+                None => writeln!(process, "OPERATOR"),
+            },
+            ManagerStatusMessage::LoginRefused(id, reason) => {
+                writeln!(process, "LOGINREFUSED {} {}", Self::cfti_escape(&id), Self::cfti_escape(&reason))
+            }
+            // Interfaces learn about state changes through the messages above.
+            ManagerStatusMessage::Lifecycle(_) => Ok(()),             /*
             //            BroadcastMessageContents::Hello(name) => writeln!(stdin,
             //                                                "HELLO {}", name),
             //            BroadcastMessageContents::Ping(val) => writeln!(stdin,
@@ -364,7 +437,7 @@ impl Interface {
         for line in BufReader::new(output).lines() {
             let line = line.expect("Unable to get next line");
             // If the send fails, that means the other end has closed the pipe.
-            if let Err(_) = control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::LogError(line))) {
+            if let Err(_) = control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::Log(LogType::Info, LogSource::Stderr, line))) {
                 break;
             }
         }
@@ -402,8 +475,48 @@ impl Interface {
                         }
                     }
                 },
+                "plan" => {
+                    let dot = words.last().map(|w| w.to_lowercase() == "dot").unwrap_or(false);
+                    if dot {
+                        words.pop();
+                    }
+                    if words.is_empty() {
+                        ManagerControlMessageContents::Plan(None, dot)
+                    } else {
+                        match UnitName::from_str(words[0].to_lowercase().as_str(), "scenario") {
+                            Ok(scenario_name) => ManagerControlMessageContents::Plan(Some(scenario_name), dot),
+                            Err(e) => ManagerControlMessageContents::Error(format!("Invalid scenario name: {}", e)),
+                        }
+                    }
+                },
                 "jig" => ManagerControlMessageContents::Jig,
-                "log" => ManagerControlMessageContents::Log(words.join(" ")),
+                "wear" => ManagerControlMessageContents::Wear,
+                "serial" => {
+                    if words.is_empty() {
+                        ManagerControlMessageContents::Error("SERIAL requires a serial number".to_owned())
+                    } else {
+                        ManagerControlMessageContents::Serial(words.join(" "))
+                    }
+                }
+                "history" => {
+                    if words.is_empty() {
+                        ManagerControlMessageContents::Error("HISTORY requires a serial number".to_owned())
+                    } else {
+                        ManagerControlMessageContents::History(words.join(" "))
+                    }
+                }
+                "login" => {
+                    if words.is_empty() {
+                        ManagerControlMessageContents::Error("LOGIN requires an operator id".to_owned())
+                    } else {
+                        ManagerControlMessageContents::Login(words.join(" "))
+                    }
+                }
+                "logout" => ManagerControlMessageContents::Logout,
+                "log" => match LogType::from_log_words(&mut words) {
+                    Ok(kind) => ManagerControlMessageContents::Log(kind, LogSource::Protocol, words.join(" ")),
+                    Err(e) => ManagerControlMessageContents::Error(e),
+                },
                 "start" => {
                     if words.is_empty() {
                         ManagerControlMessageContents::StartScenario(None)
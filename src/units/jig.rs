@@ -1,17 +1,77 @@
+extern crate regex;
 extern crate runny;
 extern crate systemd_parser;
 
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
-use std::io::Read;
-use std::fs::File;
-
-use config::Config;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use config::{command_with_environment, Config, EnvironmentCommand};
+use detect::JigMatchers;
+use expand::ExpandError;
+use inherit;
+use settings::parse_time;
 use unit::{UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitIncompatibleReason,
            UnitName, UnitSelectError, UnitDeselectError};
-use unitmanager::UnitManager;
+use unitbroadcaster::{LogSource, LogType};
+use unitmanager::{ManagerControlMessage, ManagerControlMessageContents, UnitManager};
+use wear::{parse_thresholds, Thresholds};
 
+use self::regex::Regex;
 use self::systemd_parser::items::DirectiveEntry;
-use self::runny::Runny;
+use self::runny::RunnyError;
+use self::runny::running::{RunningOutput, RunningWaiter};
+
+/// Directives recognized in the [Jig] section.
+pub const DIRECTIVES: &[&str] = &[
+    "Name",
+    "Description",
+    "WorkingDirectory",
+    "DefaultWorkingDirectory",
+    "TestFile",
+    "DefaultScenario",
+    "TestProgram",
+    "Prefer",
+    "Capabilities",
+    "Priority",
+    "RecheckInterval",
+    "Type",
+    "ExecStart",
+    "ExecStartTimeout",
+    "ExecStop",
+    "ExecStopTimeout",
+    "ExecReload",
+    "ExecReloadTimeout",
+    "DaemonReadyText",
+    "SelfTestScenario",
+    "SelfTestValidity",
+    "WearWarning",
+    "WearLimit",
+    "MatchHostname",
+    "MatchEnvironment",
+    "MatchFileContent",
+    "MatchUsb",
+    "MatchDmiProduct",
+    "MatchMode",
+];
+
+/// How long a passing self-test lasts if the jig doesn't say, in seconds.
+const DEFAULT_SELF_TEST_VALIDITY: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum JigType {
+    /// ExecStart runs to completion when the jig is activated.
+    Simple,
+
+    /// ExecStart keeps running for as long as the jig is active.
+    Daemon,
+}
 
 /// A struct defining an in-memory representation of a .jig file
 #[derive(Clone)]
@@ -36,15 +96,65 @@ pub struct JigDescription {
 
     /// A file whose existence indicates this jig is compatible
     test_file: Option<String>,
+
+    /// Tests to use on this jig when several tests provide the same name
+    prefer: Vec<UnitName>,
+
+    /// What this jig can do, such as "swd" or "camera", for tests to require
+    capabilities: Vec<String>,
+
+    /// When several jigs are compatible, the one with the highest priority is used
+    priority: i32,
+
+    /// Facts about the machine that identify this jig
+    matchers: JigMatchers,
+
+    /// How often to check whether this jig is still (or has become)
+    /// compatible while no scenario is running, if at all
+    recheck_interval: Option<Duration>,
+
+    /// Whether ExecStart finishes, or keeps running as a daemon
+    jig_type: JigType,
+
+    /// A command to run when the jig is activated, such as powering on the fixture
+    exec_start: Option<String>,
+
+    /// How long ExecStart may take, or how long a daemon has to become ready
+    exec_start_timeout: Option<Duration>,
+
+    /// A command to run when the jig is deactivated
+    exec_stop: Option<String>,
+
+    /// How long ExecStop may take
+    exec_stop_timeout: Option<Duration>,
+
+    /// A command to run when the jig's file changes while it's in use,
+    /// instead of stopping and starting it again
+    exec_reload: Option<String>,
+
+    /// How long ExecReload may take
+    exec_reload_timeout: Option<Duration>,
+
+    /// If present, a daemon isn't considered started until it prints a line matching this
+    daemon_ready: Option<Regex>,
+
+    /// A scenario run against a known-good unit, which must pass before
+    /// any other scenario is allowed to run on this jig
+    self_test_scenario: Option<UnitName>,
+
+    /// How long a passing self-test is good for
+    self_test_validity: Option<Duration>,
+
+    /// Wear thresholds for the jig itself and for tests that cycle a connector
+    wear: BTreeMap<UnitName, Thresholds>,
 }
 
 impl JigDescription {
     pub fn from_path(path: &Path) -> Result<JigDescription, UnitDescriptionError> {
         let unit_name = UnitName::from_path(path)?;
 
-        // Parse the file into a systemd unit_file object
-        let mut contents = String::with_capacity(8192);
-        File::open(path)?.read_to_string(&mut contents)?;
+        // Read the file, along with any drop-in fragments and the units it inherits from
+        let contents = inherit::read_unit(path)?;
         Self::from_string(&contents, unit_name)
     }
 
@@ -63,6 +173,22 @@ impl JigDescription {
             working_directory: None,
             test_program: None,
             test_file: None,
+            prefer: vec![],
+            capabilities: vec![],
+            priority: 0,
+            matchers: JigMatchers::new(),
+            recheck_interval: None,
+            jig_type: JigType::Simple,
+            exec_start: None,
+            exec_start_timeout: None,
+            exec_stop: None,
+            exec_stop_timeout: None,
+            exec_reload: None,
+            exec_reload_timeout: None,
+            daemon_ready: None,
+            self_test_scenario: None,
+            self_test_validity: None,
+            wear: BTreeMap::new(),
         };
 
         for entry in unit_file.lookup_by_category("Jig") {
@@ -96,9 +222,129 @@ impl JigDescription {
                             None => None,
                         }
                     }
+                    "Prefer" => {
+                        jig_description.prefer = match directive.value() {
+                            Some(s) => UnitName::from_list(s, "test")?,
+                            None => vec![],
+                        }
+                    }
+                    "Capabilities" => {
+                        jig_description.capabilities = parse_capabilities(directive.value().unwrap_or(""))
+                    }
+                    "Priority" => {
+                        jig_description.priority = match directive.value() {
+                            None => 0,
+                            Some(s) => match s.trim().parse() {
+                                Ok(p) => p,
+                                Err(_) => return Err(UnitDescriptionError::InvalidNumber(
+                                    "Jig".to_owned(),
+                                    "Priority".to_owned(),
+                                    s.to_owned(),
+                                )),
+                            },
+                        }
+                    }
+                    "RecheckInterval" => {
+                        jig_description.recheck_interval = match directive.value() {
+                            None => None,
+                            Some(s) => Some(parse_time(s)?),
+                        }
+                    }
+                    "SelfTestScenario" => {
+                        jig_description.self_test_scenario = match directive.value() {
+                            Some(s) => Some(UnitName::from_str(s, "scenario")?),
+                            None => None,
+                        }
+                    }
+                    "SelfTestValidity" => {
+                        jig_description.self_test_validity = match directive.value() {
+                            None => None,
+                            Some(s) => Some(parse_time(s)?),
+                        }
+                    }
+                    key @ "WearWarning" | key @ "WearLimit" => {
+                        let value = directive.value().unwrap_or("");
+                        let thresholds = match parse_thresholds(&jig_description.id, value) {
+                            Ok(t) => t,
+                            Err(_) => return Err(UnitDescriptionError::InvalidNumber(
+                                "Jig".to_owned(),
+                                key.to_owned(),
+                                value.to_owned(),
+                            )),
+                        };
+                        for (counter, count) in thresholds {
+                            let entry = jig_description.wear.entry(counter).or_default();
+                            if key == "WearWarning" {
+                                entry.warning = Some(count);
+                            } else {
+                                entry.limit = Some(count);
+                            }
+                        }
+                    }
+                    "Type" => {
+                        jig_description.jig_type = match directive.value() {
+                            None => JigType::Simple,
+                            Some(s) => match s.to_lowercase().as_ref() {
+                                "simple" => JigType::Simple,
+                                "daemon" => JigType::Daemon,
+                                other => return Err(UnitDescriptionError::InvalidValue(
+                                    "Jig".to_owned(),
+                                    "Type".to_owned(),
+                                    other.to_owned(),
+                                    vec!["simple".to_owned(), "daemon".to_owned()],
+                                )),
+                            },
+                        }
+                    }
+                    "ExecStart" => jig_description.exec_start = directive.value().map(|s| s.to_owned()),
+                    "ExecStop" => jig_description.exec_stop = directive.value().map(|s| s.to_owned()),
+                    "ExecReload" => jig_description.exec_reload = directive.value().map(|s| s.to_owned()),
+                    "ExecStartTimeout" => {
+                        jig_description.exec_start_timeout = match directive.value() {
+                            None => None,
+                            Some(s) => Some(parse_time(s)?),
+                        }
+                    }
+                    "ExecStopTimeout" => {
+                        jig_description.exec_stop_timeout = match directive.value() {
+                            None => None,
+                            Some(s) => Some(parse_time(s)?),
+                        }
+                    }
+                    "ExecReloadTimeout" => {
+                        jig_description.exec_reload_timeout = match directive.value() {
+                            None => None,
+                            Some(s) => Some(parse_time(s)?),
+                        }
+                    }
+                    "DaemonReadyText" => {
+                        jig_description.daemon_ready = match directive.value() {
+                            None => None,
+                            Some(s) => Some(Regex::new(s)?),
+                        }
+                    }
+                    key @ "MatchHostname"
+                    | key @ "MatchEnvironment"
+                    | key @ "MatchFileContent"
+                    | key @ "MatchUsb"
+                    | key @ "MatchDmiProduct"
+                    | key @ "MatchMode" => {
+                        jig_description.matchers.parse(key, directive.value().unwrap_or(""))?
+                    }
                     &_ => (),
                 },
-                &_ => (),
+                DirectiveEntry::Many(directives) => for directive in directives {
+                    match directive.key() {
+                        key @ "MatchHostname"
+                        | key @ "MatchEnvironment"
+                        | key @ "MatchFileContent"
+                        | key @ "MatchUsb"
+                        | key @ "MatchDmiProduct" => {
+                            jig_description.matchers.parse(key, directive.value().unwrap_or(""))?
+                        }
+                        _ => (),
+                    }
+                },
             }
         }
         Ok(jig_description)
@@ -111,24 +357,31 @@ impl JigDescription {
         _: &UnitManager,
         config: &Config,
     ) -> Result<(), UnitIncompatibleReason> {
+        let expansion = config.expansion(self.id());
+        let expand = |directive: &str, value: &str| {
+            expansion
+                .expand(value)
+                .map_err(|e| UnitIncompatibleReason::ExpansionFailed(directive.to_owned(), e))
+        };
+
         // If this Jig has a file-existence test, run it.
         if let Some(ref test_file) = self.test_file {
+            let test_file = expand("TestFile", test_file)?;
             if !Path::new(&test_file).exists() {
-                return Err(UnitIncompatibleReason::TestFileNotPresent(
-                    test_file.clone(),
-                ));
+                return Err(UnitIncompatibleReason::TestFileNotPresent(test_file));
             }
         }
 
+        // Check what's known about this machine against the Match directives.
+        self.matchers.check(config.sysfs_root())?;
+
         // If this Jig has a test-program, run that program and check the output.
         if let Some(ref cmd_str) = self.test_program {
-            use std::io::{BufRead, BufReader};
-
-            let running = Runny::new(cmd_str)
-                .directory(&Some(config.working_directory(&self.working_directory).clone()))
-                .timeout(config.timeout().clone())
-                .path(config.paths().clone())
-                .start()?;
+            let working_directory = config.working_directory(&self.working_directory);
+            let mut cmd = command_with_environment(&expand("TestProgram", cmd_str)?, &config.environment());
+            cmd.directory(&Some(PathBuf::from(expand("WorkingDirectory", &working_directory.to_string_lossy())?)))
+                .timeout(*config.timeout());
+            let running = cmd.start()?;
 
             let mut reader = BufReader::new(running);
             let mut buf = String::new();
@@ -152,6 +405,47 @@ impl JigDescription {
         &self.id
     }
 
+    pub fn default_scenario(&self) -> &Option<UnitName> {
+        &self.default_scenario
+    }
+
+    pub fn prefer(&self) -> &Vec<UnitName> {
+        &self.prefer
+    }
+
+    pub fn capabilities(&self) -> &Vec<String> {
+        &self.capabilities
+    }
+
+    /// The capabilities in `required` that this jig doesn't have.
+    pub fn missing_capabilities(&self, required: &[String]) -> Vec<String> {
+        required.iter().filter(|c| !self.capabilities.contains(c)).cloned().collect()
+    }
+
+    pub fn priority(&self) -> &i32 {
+        &self.priority
+    }
+
+    pub fn recheck_interval(&self) -> &Option<Duration> {
+        &self.recheck_interval
+    }
+
+    /// The golden-unit scenario that must pass before other scenarios may run.
+    pub fn self_test_scenario(&self) -> &Option<UnitName> {
+        &self.self_test_scenario
+    }
+
+    /// How long a passing self-test remains good for.
+    pub fn self_test_validity(&self) -> Duration {
+        self.self_test_validity.unwrap_or(Duration::from_secs(DEFAULT_SELF_TEST_VALIDITY))
+    }
+
+    /// Wear thresholds, keyed by the jig's own name or the name of a test.
+    /// Tests are only counted if they appear here.
+    pub fn wear_thresholds(&self) -> &BTreeMap<UnitName, Thresholds> {
+        &self.wear
+    }
+
     pub fn load(
         &self,
         manager: &UnitManager,
@@ -163,14 +457,150 @@ impl JigDescription {
     }
 }
 
+/// Parse a comma- or space-separated list of capabilities.
+pub fn parse_capabilities(capabilities: &str) -> Vec<String> {
+    capabilities
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|c| !c.is_empty())
+        .map(|c| c.to_owned())
+        .collect()
+}
+
+/// Whether a jig was chosen to run on, and if not, why not.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JigChoice {
+    /// This is the jig that will be used.
+    Selected,
+
+    /// Compatible, but another jig has a higher priority or, with the same
+    /// priority, sorts first by name.
+    Outranked(UnitName),
+
+    /// Compatible, but a different jig was pinned on the command line.
+    NotPinned(UnitName),
+
+    /// The jig's TestFile or TestProgram says this isn't the jig.
+    Incompatible(String /* reason */),
+}
+
+impl fmt::Display for JigChoice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &JigChoice::Selected => write!(f, "selected"),
+            &JigChoice::Outranked(ref other) => write!(f, "compatible, but outranked by {}", other),
+            &JigChoice::NotPinned(ref other) => write!(f, "compatible, but {} was pinned", other),
+            &JigChoice::Incompatible(ref reason) => write!(f, "rejected: {}", reason),
+        }
+    }
+}
+
+/// A jig that was considered when choosing which jig to run on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JigCandidate {
+    pub name: UnitName,
+    pub priority: i32,
+    pub choice: JigChoice,
+}
+
+impl fmt::Display for JigCandidate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (priority {}): {}", self.name, self.priority, self.choice)
+    }
+}
+
+/// Decide which jig to run on.  `candidates` maps each jig to its priority
+/// and, if it isn't compatible, the reason why.  The compatible jig with the
+/// highest priority is chosen, with ties going to the first by name, unless
+/// a jig is `pinned`, in which case only that jig may be chosen.  Every
+/// candidate is returned, best first.
+pub fn rank_jigs(candidates: &BTreeMap<UnitName, (i32, Option<String>)>, pinned: Option<&UnitName>) -> Vec<JigCandidate> {
+    let mut ranked: Vec<JigCandidate> = candidates
+        .iter()
+        .map(|(name, &(priority, ref rejected))| JigCandidate {
+            name: name.clone(),
+            priority: priority,
+            choice: match *rejected {
+                Some(ref reason) => JigChoice::Incompatible(reason.clone()),
+                None => JigChoice::Selected,
+            },
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.priority.cmp(&a.priority).then_with(|| a.name.cmp(&b.name)));
+
+    let mut selected: Option<UnitName> = None;
+    for candidate in ranked.iter_mut() {
+        if candidate.choice != JigChoice::Selected {
+            continue;
+        }
+        if let Some(pinned) = pinned {
+            if &candidate.name != pinned {
+                candidate.choice = JigChoice::NotPinned(pinned.clone());
+                continue;
+            }
+        }
+        match selected {
+            Some(ref best) => candidate.choice = JigChoice::Outranked(best.clone()),
+            None => selected = Some(candidate.name.clone()),
+        }
+    }
+    ranked
+}
+
+/// Why one of a jig's commands failed.
+enum JigCommandError {
+    Exec(RunnyError),
+    Expand(ExpandError),
+    NonZero(i32),
+    TimedOut(Duration),
+}
+
+impl From<RunnyError> for JigCommandError {
+    fn from(error: RunnyError) -> Self {
+        JigCommandError::Exec(error)
+    }
+}
+
+impl From<ExpandError> for JigCommandError {
+    fn from(error: ExpandError) -> Self {
+        JigCommandError::Expand(error)
+    }
+}
+
+impl From<JigCommandError> for UnitActivateError {
+    fn from(error: JigCommandError) -> Self {
+        match error {
+            JigCommandError::Exec(e) => UnitActivateError::ExecFailed(e),
+            JigCommandError::Expand(e) => UnitActivateError::ExpansionFailed(e),
+            JigCommandError::NonZero(i) => UnitActivateError::NonZeroReturn(i),
+            JigCommandError::TimedOut(d) => UnitActivateError::TimedOut(d),
+        }
+    }
+}
+
+impl From<JigCommandError> for UnitDeactivateError {
+    fn from(error: JigCommandError) -> Self {
+        match error {
+            JigCommandError::Exec(e) => UnitDeactivateError::ExecFailed(e),
+            JigCommandError::Expand(e) => UnitDeactivateError::ExpansionFailed(e),
+            JigCommandError::NonZero(i) => UnitDeactivateError::NonZeroReturn(i),
+            JigCommandError::TimedOut(d) => UnitDeactivateError::TimedOut(d),
+        }
+    }
+}
+
 pub struct Jig {
     description: JigDescription,
+
+    /// The daemon started by ExecStart, if it's running, along with a flag
+    /// that's set when it's being stopped on purpose.
+    daemon: Option<(RunningWaiter, Arc<AtomicBool>)>,
 }
 
 impl Jig {
     pub fn new(desc: &JigDescription) -> Jig {
         Jig {
             description: desc.clone(),
+            daemon: None,
         }
     }
 
@@ -190,6 +620,10 @@ impl Jig {
         &self.description.default_scenario
     }
 
+    pub fn jig_description(&self) -> &JigDescription {
+        &self.description
+    }
+
     pub fn select(&self) -> Result<(), UnitSelectError> {
         Ok(())
     }
@@ -200,14 +634,186 @@ impl Jig {
 
     pub fn activate(
         &mut self,
-        _manager: &UnitManager,
+        manager: &UnitManager,
+        config: &Config,
+    ) -> Result<(), UnitActivateError> {
+        config.set_jig_working_directory(&self.description.working_directory);
+        config.set_jig(self.id());
+
+        let cmd = match self.description.exec_start {
+            Some(ref cmd) => cmd.clone(),
+            None => return Ok(()),
+        };
+        let timeout = self.description.exec_start_timeout.unwrap_or(*config.timeout());
+        match self.description.jig_type {
+            JigType::Simple => self.run_command(&cmd, timeout, manager, config)?,
+            JigType::Daemon => self.start_daemon(&cmd, timeout, manager, config)?,
+        }
+        Ok(())
+    }
+
+    /// Run ExecStop, if there is one, and then stop the daemon, if there is one.
+    pub fn deactivate(&mut self, manager: &UnitManager, config: &Config) -> Result<(), UnitDeactivateError> {
+        let stopped = match self.description.exec_stop {
+            Some(ref cmd) => {
+                let timeout = self.description.exec_stop_timeout.unwrap_or(*config.timeout());
+                self.run_command(cmd, timeout, manager, config)
+            }
+            None => Ok(()),
+        };
+        if let Some((daemon, stopping)) = self.daemon.take() {
+            stopping.store(true, Ordering::SeqCst);
+            daemon.terminate(&Some(*config.terminate_timeout()));
+            daemon.wait();
+        }
+        Ok(stopped?)
+    }
+
+    /// Whether a change to this jig's file can be applied by running ExecReload,
+    /// rather than by stopping the jig and starting it again.
+    pub fn can_reload(&self, desc: &JigDescription) -> bool {
+        let old = &self.description;
+        desc.exec_reload.is_some()
+            && desc.exec_start == old.exec_start
+            && desc.jig_type == old.jig_type
+            && desc.working_directory == old.working_directory
+    }
+
+    /// Take on a new description, keeping any daemon running, and run ExecReload.
+    pub fn reload(
+        &mut self,
+        desc: &JigDescription,
+        manager: &UnitManager,
         config: &Config,
     ) -> Result<(), UnitActivateError> {
+        self.description = desc.clone();
         config.set_jig_working_directory(&self.description.working_directory);
+        if let Some(ref cmd) = self.description.exec_reload {
+            let timeout = self.description.exec_reload_timeout.unwrap_or(*config.timeout());
+            self.run_command(cmd, timeout, manager, config)?;
+        }
         Ok(())
     }
 
-    pub fn deactivate(&self) -> Result<(), UnitDeactivateError> {
+    fn command(&self, cmd_str: &str, config: &Config) -> Result<EnvironmentCommand, JigCommandError> {
+        let expansion = config.expansion(self.id());
+        let mut cmd = command_with_environment(&expansion.expand(cmd_str)?, &config.environment());
+        cmd.directory(&Some(expansion.expand_path(&config.working_directory(&self.description.working_directory))?));
+        Ok(cmd)
+    }
+
+    /// Run a command to completion, logging its output.
+    fn run_command(
+        &self,
+        cmd_str: &str,
+        timeout: Duration,
+        manager: &UnitManager,
+        config: &Config,
+    ) -> Result<(), JigCommandError> {
+        let mut cmd = self.command(cmd_str, config)?;
+        cmd.timeout(timeout);
+        let started = Instant::now();
+        let mut running = cmd.start()?;
+
+        let ctrl = manager.get_control_channel();
+        let stdout = Self::log_output(self.id(), &ctrl, running.take_output(), LogSource::Stdout);
+        let stderr = Self::log_output(self.id(), &ctrl, running.take_error(), LogSource::Stderr);
+        let result = running.result();
+
+        // Let the last of the output be logged before saying the command is
+        // done, unless something it started is still holding on to it.
+        let drained = Instant::now();
+        for done in &[stdout, stderr] {
+            let left = config.terminate_timeout().checked_sub(drained.elapsed()).unwrap_or_default();
+            done.recv_timeout(left).ok();
+        }
+
+        match result {
+            0 => Ok(()),
+            _ if started.elapsed() >= timeout => Err(JigCommandError::TimedOut(timeout)),
+            result => Err(JigCommandError::NonZero(result)),
+        }
+    }
+
+    /// Start ExecStart in the background, and wait for it to print its
+    /// DaemonReadyText, if it has one.  If the daemon exits on its own later
+    /// on, the jig is marked as having failed.
+    fn start_daemon(
+        &mut self,
+        cmd_str: &str,
+        timeout: Duration,
+        manager: &UnitManager,
+        config: &Config,
+    ) -> Result<(), UnitActivateError> {
+        let cmd = self.command(cmd_str, config)?;
+        let mut running = cmd.start()?;
+
+        let id = self.id().clone();
+        let ctrl = manager.get_control_channel();
+        Self::log_output(&id, &ctrl, running.take_error(), LogSource::Stderr);
+
+        let stdout = running.take_output();
+        let waiter = running.waiter();
+        let stopping = Arc::new(AtomicBool::new(false));
+        let thr_stopping = stopping.clone();
+        let ready_text = self.description.daemon_ready.clone();
+        let (ready_tx, ready_rx) = channel();
+        thread::spawn(move || {
+            let mut ready = ready_text.is_none();
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Ok(l) => l,
+                    Err(_) => break,
+                };
+                if !ready && ready_text.as_ref().map(|r| r.is_match(&line)).unwrap_or(false) {
+                    ready = true;
+                    ready_tx.send(()).ok();
+                }
+                ctrl.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::Log(LogType::Info, LogSource::Stdout, line))).ok();
+            }
+            let result = running.wait().unwrap_or(-1);
+
+            // A daemon that never became ready is reported by start_daemon() instead.
+            if ready && !thr_stopping.load(Ordering::SeqCst) {
+                ctrl.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::Log(LogType::Error, LogSource::Internal, format!("jig daemon exited unexpectedly with {}", result)))).ok();
+                ctrl.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::ChildExited)).ok();
+            }
+        });
+
+        if self.description.daemon_ready.is_some() {
+            match ready_rx.recv_timeout(timeout) {
+                Ok(()) => (),
+                Err(RecvTimeoutError::Timeout) => {
+                    stopping.store(true, Ordering::SeqCst);
+                    waiter.terminate(&Some(*config.terminate_timeout()));
+                    waiter.wait();
+                    return Err(UnitActivateError::TimedOut(timeout));
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err(UnitActivateError::DaemonNotReady(waiter.result())),
+            }
+        }
+        self.daemon = Some((waiter, stopping));
         Ok(())
     }
+
+    /// Log each line of `output` from a new thread.  The returned channel is
+    /// disconnected once all of it has been logged.
+    fn log_output(id: &UnitName, control: &Sender<ManagerControlMessage>, output: RunningOutput, source: LogSource) -> Receiver<()> {
+        let thr_control = control.clone();
+        let thr_id = id.clone();
+        let (done_tx, done_rx) = channel::<()>();
+        thread::spawn(move || {
+            let _done = done_tx;
+            for line in BufReader::new(output).lines() {
+                let line = match line {
+                    Ok(l) => l,
+                    Err(_) => break,
+                };
+                if let Err(_) = thr_control.send(ManagerControlMessage::new(&thr_id, ManagerControlMessageContents::Log(LogType::Info, source.clone(), line))) {
+                    break;
+                }
+            }
+        });
+        done_rx
+    }
 }
@@ -3,22 +3,21 @@ extern crate serde_json;
 extern crate systemd_parser;
 
 use std::cell::RefCell;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
 use std::time::Duration;
 use std::thread;
 
-use config::Config;
+use config::{command_with_environment, Config};
+use inherit;
 use unit::{UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
            UnitIncompatibleReason, UnitName, UnitSelectError};
-use unitbroadcaster::LogEntry;
+use unitbroadcaster::{LifecycleEntry, LogEntry, LogSource, LogType};
 use unitmanager::{ManagerControlMessage, ManagerControlMessageContents, ManagerStatusMessage,
                   UnitManager};
 
 use self::systemd_parser::items::DirectiveEntry;
-use self::runny::Runny;
 use self::runny::running::{Running, RunningOutput};
 
 #[derive(Clone, Copy)]
@@ -27,6 +26,9 @@ enum LoggerFormat {
     JSON,
 }
 
+/// Directives recognized in the [Logger] section.
+pub const DIRECTIVES: &[&str] = &["Name", "Description", "Jigs", "WorkingDirectory", "ExecStart", "Format"];
+
 /// A struct defining an in-memory representation of a .logger file
 #[derive(Clone)]
 pub struct LoggerDescription {
@@ -50,18 +52,18 @@ pub struct LoggerDescription {
 
     /// The working directory to start from when running the logger
     working_directory: Option<PathBuf>,
-
-    /// How long to wait for a terminate() call
-    terminate_timeout: Duration,
 }
 
 impl LoggerDescription {
     pub fn from_path(path: &Path) -> Result<LoggerDescription, UnitDescriptionError> {
         let unit_name = UnitName::from_path(path)?;
 
-        // Parse the file into a systemd unit_file object
-        let mut contents = String::with_capacity(8192);
-        File::open(path)?.read_to_string(&mut contents)?;
+        // Read the file, along with any drop-in fragments and the units it inherits from
+        let contents = inherit::read_unit(path)?;
+        Self::from_string(&contents, unit_name)
+    }
+
+    pub fn from_string(contents: &str, unit_name: UnitName) -> Result<LoggerDescription, UnitDescriptionError> {
         let unit_file = systemd_parser::parse_string(&contents)?;
 
         if !unit_file.has_category("Logger") {
@@ -76,7 +78,6 @@ impl LoggerDescription {
             format: LoggerFormat::TSV,
             exec_start: "".to_owned(),
             working_directory: None,
-            terminate_timeout: Duration::from_secs(5),
         };
 
         for entry in unit_file.lookup_by_category("Logger") {
@@ -132,6 +133,10 @@ impl LoggerDescription {
         Ok(logger_description)
     }
 
+    pub fn jigs(&self) -> &Vec<UnitName> {
+        &self.jigs
+    }
+
     /// Returns true if this test is supported on the named jig.
     pub fn supports_jig(&self, name: &UnitName) -> bool {
         self.jigs.contains(name)
@@ -172,13 +177,17 @@ impl LoggerDescription {
 pub struct Logger {
     description: LoggerDescription,
     process: RefCell<Option<Running>>,
+
+    /// How long to wait for a terminate() call
+    terminate_timeout: Duration,
 }
 
 impl Logger {
-    pub fn new(desc: &LoggerDescription, _: &UnitManager, _config: &Config) -> Logger {
+    pub fn new(desc: &LoggerDescription, _: &UnitManager, config: &Config) -> Logger {
         Logger {
             description: desc.clone(),
             process: RefCell::new(None),
+            terminate_timeout: *config.terminate_timeout(),
         }
     }
 
@@ -200,7 +209,7 @@ impl Logger {
             // If the send fails, that means the other end has closed the pipe.
             if let Err(_) = control.send(ManagerControlMessage::new(
                 &id,
-                ManagerControlMessageContents::LogError(line),
+                ManagerControlMessageContents::Log(LogType::Info, LogSource::Stderr, line),
             )) {
                 break;
             }
@@ -212,11 +221,12 @@ impl Logger {
         manager: &UnitManager,
         config: &Config,
     ) -> Result<(), UnitActivateError> {
-        let mut running = Runny::new(self.description.exec_start.as_str())
-            .directory(&Some(
-                config.working_directory(&self.description.working_directory),
-            ))
-            .start()?;
+        let expansion = config.expansion(self.id());
+        let mut cmd = command_with_environment(&expansion.expand(&self.description.exec_start)?, &config.environment());
+        cmd.directory(&Some(
+            expansion.expand_path(&config.working_directory(&self.description.working_directory))?,
+        ));
+        let mut running = cmd.start()?;
 
         // Have stdout and stderr log their output.
         let control_sender = manager.get_control_channel();
@@ -248,7 +258,7 @@ impl Logger {
 
     pub fn deactivate(&self) -> Result<(), UnitDeactivateError> {
         if let Some(process) = self.process.borrow_mut().take() {
-            match process.terminate(Some(self.description.terminate_timeout)) {
+            match process.terminate(Some(self.terminate_timeout)) {
                 Ok(retval) => match retval {
                     0 => Ok(()),
                     i => Err(UnitDeactivateError::NonZeroReturn(i)),
@@ -276,6 +286,11 @@ impl Logger {
                     LoggerFormat::TSV => self.tsv_write(l, process),
                     LoggerFormat::JSON => self.json_write(l, process),
                 },
+            ManagerStatusMessage::Lifecycle(l) =>
+                match self.description.format {
+                    LoggerFormat::TSV => self.tsv_lifecycle_write(l, process),
+                    LoggerFormat::JSON => writeln!(process, "{}", serde_json::to_string(&l)?),
+                },
             _ => Ok(()),
         }
     }
@@ -294,24 +309,12 @@ impl Logger {
         writeln!(process, "{}", serde_json::to_string(&entry)?)
     }
 
-    fn cfti_escape(msg: &String) -> String {
-        msg.replace("\\", "\\\\")
-            .replace("\t", "\\t")
-            .replace("\n", "\\n")
-            .replace("\r", "\\r")
-    }
-
     /// Write a ManagerStatusMessage to a TSV-formatted output.
     fn tsv_write(&self, l: LogEntry, process: &mut Running) -> Result<(), Error> {
-        writeln!(
-            process,
-            "{}\t{}\t{}\t{}\t{}\t{}",
-            l.kind().as_str(),
-            Self::cfti_escape(l.id().id()),
-            Self::cfti_escape(&format!("{}", l.id().kind())),
-            l.secs(),
-            l.nsecs(),
-            Self::cfti_escape(l.message())
-        )
+        writeln!(process, "{}", l.tsv())
+    }
+
+    fn tsv_lifecycle_write(&self, l: LifecycleEntry, process: &mut Running) -> Result<(), Error> {
+        writeln!(process, "{}", l.tsv())
     }
 }
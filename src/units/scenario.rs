@@ -5,8 +5,8 @@ extern crate systemd_parser;
 
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Read};
+use std::fmt;
+use std::io::{BufRead, BufReader};
 use std::sync::mpsc::Sender;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
@@ -15,16 +15,20 @@ use std::time::{Duration, Instant};
 
 use self::dependy::{Dependy, Dependency};
 use self::humantime::{parse_duration, DurationError};
-use self::runny::Runny;
 use self::runny::running::Running;
 use self::systemd_parser::items::DirectiveEntry;
 
-use config::Config;
+use config::{command_with_environment, Config};
+use expand::Expansion;
+use inherit;
+use plan;
 use unit::{UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitIncompatibleReason,
            UnitName, UnitSelectError, UnitDeselectError};
+use unitbroadcaster::{LogSource, LogType};
 use unitmanager::{ManagerControlMessage, ManagerControlMessageContents,
                   UnitManager};
-use units::test::Test;
+use units::jig::{parse_capabilities, JigDescription};
+use units::test::{Test, TestDescription};
 
 struct AssumptionDependency {
     name: UnitName,
@@ -59,6 +63,98 @@ impl Dependency<UnitName> for AssumptionDependency {
     }
 }
 
+/// A test added to the dependency graph with only the names it was
+/// chosen to provide, so that the graph can't pick a different provider.
+struct ProviderDependency<'a> {
+    test: &'a TestDescription,
+    provides: Vec<UnitName>,
+}
+
+impl<'a> Dependency<UnitName> for ProviderDependency<'a> {
+    fn name(&self) -> &UnitName {
+        self.test.id()
+    }
+    fn requirements(&self) -> &Vec<UnitName> {
+        self.test.requires()
+    }
+    fn suggestions(&self) -> &Vec<UnitName> {
+        self.test.suggests()
+    }
+    fn provides(&self) -> &Vec<UnitName> {
+        &self.provides
+    }
+}
+
+/// Why a particular test was chosen to provide a name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProviderReason {
+    /// No other test provides the name.
+    Only,
+
+    /// The test has that name itself.
+    Named,
+
+    /// The test is listed in the scenario's Prefer.
+    Scenario(UnitName),
+
+    /// The test is listed in the jig's Prefer.
+    Jig(UnitName),
+
+    /// The test has the highest Priority.
+    Priority(i32),
+
+    /// Several tests have the same Priority, and this one sorts first.
+    Alphabetical,
+}
+
+/// The test chosen to satisfy a name, along with every test that could have been.
+#[derive(Debug, Clone)]
+pub struct ProviderSelection {
+    pub name: UnitName,
+    pub provider: UnitName,
+    pub candidates: Vec<UnitName>,
+    pub reason: ProviderReason,
+}
+
+impl fmt::Display for ProviderSelection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is provided by {} (", self.name, self.provider)?;
+        match self.reason {
+            ProviderReason::Only => write!(f, "the only provider")?,
+            ProviderReason::Named => write!(f, "it is named {}", self.name)?,
+            ProviderReason::Scenario(ref s) => write!(f, "preferred by {}", s)?,
+            ProviderReason::Jig(ref j) => write!(f, "preferred by {}", j)?,
+            ProviderReason::Priority(p) => write!(f, "highest priority, {}", p)?,
+            ProviderReason::Alphabetical => write!(f, "first by name")?,
+        }
+        let others: Vec<String> = self.candidates.iter().filter(|c| *c != &self.provider).map(|c| format!("{}", c)).collect();
+        if !others.is_empty() {
+            write!(f, "; also provided by {}", others.join(", "))?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Directives recognized in the [Scenario] section.
+pub const DIRECTIVES: &[&str] = &[
+    "Name",
+    "Description",
+    "Jigs",
+    "RequiresCapability",
+    "Pools",
+    "WorkingDirectory",
+    "Tests",
+    "Assume",
+    "Prefer",
+    "ExecStart",
+    "ExecStartTimeout",
+    "Timeout",
+    "ExecStopSuccess",
+    "ExecStopSuccessTimeout",
+    "ExecStopFailure",
+    "ExecStopFailureTimeout",
+];
+
 /// A struct defining an in-memory representation of a .scenario file
 #[derive(Clone)]
 pub struct ScenarioDescription {
@@ -74,12 +170,21 @@ pub struct ScenarioDescription {
     /// A Vec<String> of jig names that this test is compatible with.
     jigs: Vec<UnitName>,
 
+    /// Capabilities that the jig must have for this scenario to run.
+    requires_capability: Vec<String>,
+
+    /// Pools to check a value out of each time this scenario starts.
+    pools: Vec<String>,
+
     /// A Vec<String> of test names that are explicitly specified.
     tests: Vec<UnitName>,
 
     /// A Vec<String> of tests that are considered to have passed without running them.
     assumptions: Vec<UnitName>,
 
+    /// Tests to use when several tests provide the same name.  Overrides the jig's Prefer.
+    prefer: Vec<UnitName>,
+
     /// The maximum duration, if any, for this scenario
     timeout: Option<Duration>,
 
@@ -109,9 +214,12 @@ impl ScenarioDescription {
     pub fn from_path(path: &Path) -> Result<ScenarioDescription, UnitDescriptionError> {
         let unit_name = UnitName::from_path(path)?;
 
-        // Parse the file into a systemd unit_file object
-        let mut contents = String::with_capacity(8192);
-        File::open(path)?.read_to_string(&mut contents)?;
+        // Read the file, along with any drop-in fragments and the units it inherits from
+        let contents = inherit::read_unit(path)?;
+        Self::from_string(&contents, unit_name)
+    }
+
+    pub fn from_string(contents: &str, unit_name: UnitName) -> Result<ScenarioDescription, UnitDescriptionError> {
         let unit_file = systemd_parser::parse_string(&contents)?;
 
         if !unit_file.has_category("Scenario") {
@@ -124,8 +232,11 @@ impl ScenarioDescription {
             description: "".to_owned(),
 
             jigs: vec![],
+            requires_capability: vec![],
+            pools: vec![],
             tests: vec![],
             assumptions: vec![],
+            prefer: vec![],
 
             timeout: None,
 
@@ -156,6 +267,12 @@ impl ScenarioDescription {
                                 None => vec![],
                             }
                         }
+                        "RequiresCapability" => {
+                            scenario_description.requires_capability = parse_capabilities(directive.value().unwrap_or(""))
+                        }
+                        "Pools" => {
+                            scenario_description.pools = parse_capabilities(directive.value().unwrap_or(""))
+                        }
                         "WorkingDirectory" => {
                             scenario_description.working_directory = match directive.value() {
                                 None => None,
@@ -174,6 +291,12 @@ impl ScenarioDescription {
                                 None => vec![],
                             }
                         }
+                        "Prefer" => {
+                            scenario_description.prefer = match directive.value() {
+                                Some(s) => UnitName::from_list(s, "test")?,
+                                None => vec![],
+                            }
+                        }
                         "ExecStart" => {
                             scenario_description.exec_start = match directive.value() {
                                 None => None,
@@ -237,33 +360,191 @@ impl ScenarioDescription {
         &self.id
     }
 
-    /// Returns true if this scenario is supported on the named jig.
-    pub fn supports_jig(&self, name: &UnitName) -> bool {
+    pub fn jigs(&self) -> &Vec<UnitName> {
+        &self.jigs
+    }
+
+    pub fn tests(&self) -> &Vec<UnitName> {
+        &self.tests
+    }
+
+    pub fn assumptions(&self) -> &Vec<UnitName> {
+        &self.assumptions
+    }
+
+    pub fn prefer(&self) -> &Vec<UnitName> {
+        &self.prefer
+    }
+
+    /// Decide which test satisfies each name that tests provide.  A test
+    /// with the name itself always wins, followed by the scenario's Prefer,
+    /// the jig's Prefer, the highest Priority, and finally the name of the
+    /// test, so that the same test is chosen every time.
+    pub fn select_providers(&self,
+                            tests: &[TestDescription],
+                            jig: Option<&JigDescription>)
+                            -> HashMap<UnitName, ProviderSelection> {
+        let mut candidates: HashMap<&UnitName, Vec<&TestDescription>> = HashMap::new();
+        for test in tests {
+            // Assumed tests aren't run, so they can't provide anything.
+            if self.assumptions.contains(test.id()) {
+                continue;
+            }
+            for provided in test.provides() {
+                candidates.entry(provided).or_default().push(test);
+            }
+        }
+
+        let mut selections = HashMap::new();
+        for (name, mut providers) in candidates {
+            providers.sort_by(|a, b| b.priority().cmp(&a.priority()).then(a.id().cmp(b.id())));
+            let preferred = |prefer: &[UnitName]| {
+                prefer.iter().filter_map(|p| providers.iter().find(|t| t.id() == p)).next().cloned()
+            };
+            let (provider, reason) = if let Some(t) = tests.iter().find(|t| t.id() == name) {
+                (t, ProviderReason::Named)
+            } else if providers.len() == 1 {
+                (providers[0], ProviderReason::Only)
+            } else if let Some(t) = preferred(&self.prefer) {
+                (t, ProviderReason::Scenario(self.id.clone()))
+            } else if let Some((t, j)) = jig.and_then(|j| preferred(j.prefer()).map(|t| (t, j))) {
+                (t, ProviderReason::Jig(j.id().clone()))
+            } else if providers[0].priority() > providers[1].priority() {
+                (providers[0], ProviderReason::Priority(providers[0].priority()))
+            } else {
+                (providers[0], ProviderReason::Alphabetical)
+            };
+            let mut candidate_names: Vec<UnitName> = providers.iter().map(|t| t.id().clone()).collect();
+            if reason == ProviderReason::Named {
+                candidate_names.push(name.clone());
+            }
+            candidate_names.sort();
+            selections.insert(name.clone(), ProviderSelection {
+                name: name.clone(),
+                provider: provider.id().clone(),
+                candidates: candidate_names,
+                reason: reason,
+            });
+        }
+        selections
+    }
+
+    /// Reorder a resolved test sequence so that After= and Before= are
+    /// honoured, keeping the order otherwise unchanged, and make sure that
+    /// no two conflicting tests would be run.
+    fn order_tests(&self,
+                   sequence: Vec<UnitName>,
+                   tests: &[TestDescription],
+                   selections: &HashMap<UnitName, ProviderSelection>)
+                   -> Result<Vec<UnitName>, UnitIncompatibleReason> {
+        let by_name: HashMap<&UnitName, &TestDescription> = tests.iter().map(|t| (t.id(), t)).collect();
+
+        // Find a test in the sequence, either by its name or by the test chosen to provide it.
+        let find = |name: &UnitName| -> Option<usize> {
+            if let Some(idx) = sequence.iter().position(|t| t == name) {
+                return Some(idx);
+            }
+            let provider = &selections.get(name)?.provider;
+            sequence.iter().position(|t| t == provider)
+        };
+
+        // For each test, the tests that must come before it.
+        let mut preceding: Vec<Vec<usize>> = vec![vec![]; sequence.len()];
+        for (idx, name) in sequence.iter().enumerate() {
+            // Assumed tests aren't run, so they can't conflict and have no dependencies.
+            if self.assumptions.contains(name) {
+                continue;
+            }
+            let description = match by_name.get(name) {
+                Some(d) => d,
+                None => continue,
+            };
+            for other in description.conflicts() {
+                if let Some(other_idx) = find(other) {
+                    if other_idx != idx && !self.assumptions.contains(&sequence[other_idx]) {
+                        return Err(UnitIncompatibleReason::ConflictingTests(name.clone(), sequence[other_idx].clone()));
+                    }
+                }
+            }
+            let earlier = description.requires().iter()
+                .chain(description.suggests().iter())
+                .chain(description.after().iter());
+            for other in earlier {
+                if let Some(other_idx) = find(other) {
+                    preceding[idx].push(other_idx);
+                }
+            }
+            for other in description.before() {
+                if let Some(other_idx) = find(other) {
+                    preceding[other_idx].push(idx);
+                }
+            }
+        }
+
+        // Repeatedly take the earliest test whose predecessors have all been
+        // placed.  With no After= or Before=, this is the original order.
+        let mut placed = vec![false; sequence.len()];
+        let mut order = vec![];
+        while order.len() < sequence.len() {
+            let next = (0..sequence.len())
+                .find(|&i| !placed[i] && preceding[i].iter().all(|&j| j == i || placed[j]));
+            match next {
+                Some(i) => {
+                    placed[i] = true;
+                    order.push(sequence[i].clone());
+                }
+                None => {
+                    // Every remaining test is waiting on another, so follow
+                    // the chain of unplaced predecessors until it loops.
+                    let mut path = vec![(0..sequence.len()).find(|&i| !placed[i]).unwrap()];
+                    loop {
+                        let current = *path.last().unwrap();
+                        let pred = *preceding[current].iter().find(|&&j| j != current && !placed[j]).unwrap();
+                        if let Some(start) = path.iter().position(|&p| p == pred) {
+                            let cycle = path[start..].iter().map(|&i| sequence[i].clone()).collect();
+                            return Err(UnitIncompatibleReason::OrderingCycle(cycle));
+                        }
+                        path.push(pred);
+                    }
+                }
+            }
+        }
+        Ok(order)
+    }
+
+    /// Returns true if this scenario is supported on the named jig.  A scenario that
+    /// only requires capabilities is supported if one of the `versions` of
+    /// the jig, such as before and after it changed, has all of them.
+    pub fn supports_jig(&self, name: &UnitName, versions: &[JigDescription]) -> bool {
         self.jigs.contains(name)
+            || (self.jigs.is_empty()
+                && !self.requires_capability.is_empty()
+                && versions.iter().any(|j| j.missing_capabilities(&self.requires_capability).is_empty()))
+    }
+
+    pub fn requires_capability(&self) -> &Vec<String> {
+        &self.requires_capability
+    }
+
+    pub fn pools(&self) -> &Vec<String> {
+        &self.pools
     }
 
     /// Determine if a unit is compatible with this system.
     pub fn is_compatible(&self,
                          manager: &UnitManager,
-                         _: &Config)
+                         config: &Config)
                          -> Result<(Vec<UnitName>, Dependy<UnitName>), UnitIncompatibleReason> {
-        // If there is at least one jig present, ensure that it is loaded.
-        if self.jigs.len() > 0 {
-            let mut loaded = false;
-            for jig_name in &self.jigs {
-                if manager.jig_is_loaded(&jig_name) {
-                    loaded = true;
-                }
-            }
-            if !loaded {
-                return Err(UnitIncompatibleReason::IncompatibleJig);
-            }
+        // If there is at least one jig present, or a required capability,
+        // ensure that a suitable jig is loaded.
+        if !self.jigs.is_empty() || !self.requires_capability.is_empty() {
+            manager.check_capabilities(&self.jigs, &self.requires_capability)?;
         }
 
         // Build the dependency graph, but don't use the result.
         // This is because right now, we're just concerned with
         // whether the dependencies are satisfied.
-        self.get_test_order(manager)
+        self.get_test_order(manager, config)
     }
 
     pub fn load(&self,
@@ -275,32 +556,18 @@ impl ScenarioDescription {
     }
 
     pub fn get_test_order(&self,
-                          manager: &UnitManager)
+                          manager: &UnitManager,
+                          config: &Config)
                           -> Result<(Vec<UnitName>, Dependy<UnitName>), UnitIncompatibleReason> {
 
-        // Create a new dependency graph
-        let mut graph = Dependy::new();
-
-        // Add each possible test into the dependency graph
-        {
-            let tests_rc = manager.get_tests();
-            let tests = tests_rc.borrow();
-            for (test_name, test) in tests.iter() {
-                if self.assumptions.contains(test_name) {
-                    let assumption_dep = AssumptionDependency::new(test_name.clone());
-                    graph.add_dependency(&assumption_dep);
-                } else {
-                    graph.add_dependency(&*test.borrow());
-                }
-            }
+        let mut tests = vec![];
+        for test in manager.get_tests().borrow().values() {
+            tests.push(test.borrow().test_description().clone());
         }
 
-        let mut test_names = vec![];
-        for test_name in &self.tests {
-            test_names.push(test_name.clone());
-        }
+        let jig = manager.jig_description_for(&self.jigs, config);
+        let (test_sequence, graph) = self.resolve_tests(&tests, jig.as_ref())?;
 
-        let test_sequence = graph.resolve_named_dependencies(&test_names)?;
         let mut test_order = vec![];
         for test_name in test_sequence {
             // Only add the test to the test order if it's not an assumption.
@@ -312,6 +579,57 @@ impl ScenarioDescription {
         // let test_order = trimmed_order;
         Ok((test_order, graph))
     }
+
+    /// The providers that were picked from more than one candidate for the
+    /// tests in `test_order`, sorted by the name they provide.
+    pub fn provider_choices(&self,
+                            tests: &[TestDescription],
+                            jig: Option<&JigDescription>,
+                            test_order: &[UnitName])
+                            -> Vec<ProviderSelection> {
+        let mut selections: Vec<ProviderSelection> = self.select_providers(tests, jig)
+            .into_values()
+            .filter(|s| s.candidates.len() > 1 && test_order.contains(&s.provider))
+            .collect();
+        selections.sort_by(|a, b| a.name.cmp(&b.name));
+        selections
+    }
+
+    /// Resolve this scenario's tests against the given set of tests.  The
+    /// returned order includes any tests that are assumed to have passed.
+    pub fn resolve_tests(&self,
+                         tests: &[TestDescription],
+                         jig: Option<&JigDescription>)
+                         -> Result<(Vec<UnitName>, Dependy<UnitName>), UnitIncompatibleReason> {
+        let selections = self.select_providers(tests, jig);
+
+        // Create a new dependency graph
+        let mut graph = Dependy::new();
+
+        // Add each possible test into the dependency graph
+        for test in tests {
+            if self.assumptions.contains(test.id()) {
+                let assumption_dep = AssumptionDependency::new(test.id().clone());
+                graph.add_dependency(&assumption_dep);
+            } else {
+                let provider_dep = ProviderDependency {
+                    test: test,
+                    provides: test.provides().iter()
+                        .filter(|p| selections.get(*p).map(|s| &s.provider == test.id()).unwrap_or(false))
+                        .cloned()
+                        .collect(),
+                };
+                graph.add_dependency(&provider_dep);
+            }
+        }
+
+        match graph.resolve_named_dependencies(&self.tests) {
+            Ok(test_sequence) => Ok((self.order_tests(test_sequence, tests, &selections)?, graph)),
+            // Work out how the problem was reached, which is much more
+            // useful than the bare names the dependency graph reports.
+            Err(e) => Err(plan::diagnose(self, tests, jig).unwrap_or(UnitIncompatibleReason::from(e))),
+        }
+    }
 }
 
 #[derive(Clone, PartialEq, Debug)]
@@ -387,6 +705,17 @@ pub struct Scenario {
 
     /// The currently-executing program (if any)
     program: Rc<RefCell<Option<Running>>>,
+
+    /// Expands variables and specifiers in support commands, based on the
+    /// jig that was active when the scenario started.
+    expansion: Rc<RefCell<Expansion>>,
+
+    /// Variables to pass to support commands, from the global settings.
+    environment: Rc<RefCell<Vec<(String, String)>>>,
+
+    /// Providers that were chosen from several candidates, logged each time
+    /// the scenario starts.
+    providers: Vec<ProviderSelection>,
 }
 
 impl Scenario {
@@ -401,6 +730,15 @@ impl Scenario {
         let mut test_sequence = vec![];
         let mut test_state = HashMap::new();
 
+        // Note which test was picked whenever there was a choice, since a
+        // different provider can change how the scenario behaves.
+        let mut descriptions = vec![];
+        for test in manager.get_tests().borrow().values() {
+            descriptions.push(test.borrow().test_description().clone());
+        }
+        let jig = manager.jig_description_for(&desc.jigs, config);
+        let providers = desc.provider_choices(&descriptions, jig.as_ref(), &test_order);
+
         for test_name in test_order {
             let test = manager.get_test_named(&test_name).expect("Unable to check out requested test from library");
             test_sequence.push(test.clone());
@@ -420,6 +758,9 @@ impl Scenario {
             graph: graph,
             start_time: Instant::now(),
             program: Rc::new(RefCell::new(None)),
+            expansion: Rc::new(RefCell::new(config.expansion(&desc.id))),
+            environment: Rc::new(RefCell::new(config.environment())),
+            providers: providers,
         }
     }
 
@@ -468,6 +809,15 @@ impl Scenario {
         // Re-assign our working directory.
         config.set_scenario_working_directory(&self.description.working_directory);
         *self.working_directory.borrow_mut() = config.working_directory(&None);
+        config.set_scenario(self.id());
+        *self.expansion.borrow_mut() = config.expansion(self.id());
+        *self.environment.borrow_mut() = config.environment();
+        self.environment.borrow_mut().extend(manager.run_environment());
+
+        for selection in &self.providers {
+            ctrl.send(ManagerControlMessage::new(self.id(),
+                ManagerControlMessageContents::Log(LogType::Info, LogSource::Internal, format!("{}", selection)))).ok();
+        }
 
         // Cause the scenario to move to the next (i.e. first) phase.
         ctrl.send(ManagerControlMessage::new(self.id(), ManagerControlMessageContents::AdvanceScenario(0))).ok();
@@ -487,6 +837,10 @@ impl Scenario {
         &self.description.name
     }
 
+    pub fn scenario_description(&self) -> &ScenarioDescription {
+        &self.description
+    }
+
     pub fn description(&self) -> &String {
         &self.description.description
     }
@@ -569,14 +923,34 @@ impl Scenario {
     /// Run a support command (i.e. ExecStart, ExecStopSuccess, or ExecStopFailure).
     /// Will emit an AdvanceScenario message upon completion.
     fn run_support_cmd(&self, cmd: &String, ctrl: &Sender<ManagerControlMessage>, timeout: &Option<Duration>, _testname: &str) {
-        let mut cmd = Runny::new(cmd);
+        let expanded = {
+            let expansion = self.expansion.borrow();
+            expansion.expand(cmd).and_then(|cmd| {
+                expansion.expand_path(&self.working_directory.borrow()).map(|dir| (cmd, dir))
+            })
+        };
+        let (cmd, directory) = match expanded {
+            Ok(e) => e,
+            Err(e) => {
+                ctrl.send(ManagerControlMessage::new(self.id(), ManagerControlMessageContents::Log(LogType::Error, LogSource::Internal, format!("unable to run {}: {}", cmd, e)))).ok();
+                ctrl.send(ManagerControlMessage::new(self.id(), ManagerControlMessageContents::AdvanceScenario(-3))).ok();
+                return;
+            }
+        };
+
+        let cmd_str = cmd;
+        let mut cmd = command_with_environment(&cmd_str, &self.environment.borrow());
         if let Some(timeout) = *timeout {
             cmd.timeout(timeout);
         }
-        cmd.directory(&Some(self.working_directory.borrow().clone()));
+        cmd.directory(&Some(directory));
         let mut running = match cmd.start() {
             Ok(o) => o,
-            Err(e) => unimplemented!(),
+            Err(e) => {
+                ctrl.send(ManagerControlMessage::new(self.id(), ManagerControlMessageContents::Log(LogType::Error, LogSource::Internal, format!("unable to run {}: {:?}", cmd_str, e)))).ok();
+                ctrl.send(ManagerControlMessage::new(self.id(), ManagerControlMessageContents::AdvanceScenario(-3))).ok();
+                return;
+            }
         };
 
         self.log_output(ctrl, &mut running);
@@ -601,7 +975,7 @@ impl Scenario {
         thread::spawn(move || {
             for line in BufReader::new(stdout).lines() {
                 let line = line.expect("Unable to get next line");
-                if let Err(_) = thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::Log(line))) {
+                if let Err(_) = thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::Log(LogType::Info, LogSource::Stdout, line))) {
                     break;
                 }
             }
@@ -613,7 +987,7 @@ impl Scenario {
         thread::spawn(move || {
             for line in BufReader::new(stderr).lines() {
                 let line = line.expect("Unable to get next line");
-                if let Err(_) = thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::LogError(line))) {
+                if let Err(_) = thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::Log(LogType::Info, LogSource::Stderr, line))) {
                     break;
                 }
             }
@@ -6,8 +6,7 @@ extern crate systemd_parser;
 
 use std::cell::RefCell;
 use std::error::Error;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Read};
+use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
@@ -18,15 +17,41 @@ use std::time::Duration;
 use self::dependy::Dependency;
 use self::humantime::{parse_duration, DurationError};
 use self::regex::Regex;
-use self::runny::Runny;
 use self::runny::running::{RunningOutput, RunningWaiter};
 use self::systemd_parser::items::DirectiveEntry;
 
-use config::Config;
+use config::{command_with_environment, Config};
+use inherit;
 use unit::{UnitName, UnitActivateError, UnitDeactivateError, UnitSelectError, UnitDeselectError,
            UnitIncompatibleReason, UnitDescriptionError};
+use unitbroadcaster::{LogSource, LogType};
 use unitmanager::{ManagerControlMessage, ManagerControlMessageContents,
                   UnitManager};
+use units::jig::{parse_capabilities, JigDescription};
+
+/// Directives recognized in the [Test] section.
+pub const DIRECTIVES: &[&str] = &[
+    "Name",
+    "Description",
+    "Jigs",
+    "RequiresCapability",
+    "Requires",
+    "Suggests",
+    "Provides",
+    "Priority",
+    "After",
+    "Before",
+    "Conflicts",
+    "DaemonReadyText",
+    "Type",
+    "WorkingDirectory",
+    "ExecStart",
+    "Timeout",
+    "ExecStopSuccess",
+    "ExecStopSuccessTimeout",
+    "ExecStopFailure",
+    "ExecStopFailureTimeout",
+];
 
 #[derive(Debug, PartialEq, Clone)]
 enum TestType {
@@ -49,6 +74,9 @@ pub struct TestDescription {
     /// A Vec<String> of jig names that this test is compatible with.
     jigs: Vec<UnitName>,
 
+    /// Capabilities that the jig must have for this test to run.
+    requires_capability: Vec<String>,
+
     /// A Vec<String> of test names that must successfully complete for this test to run.
     requires: Vec<UnitName>,
 
@@ -61,6 +89,18 @@ pub struct TestDescription {
     /// want to skip when fixing things in the factory.
     provides: Vec<UnitName>,
 
+    /// When several tests provide the same name, the one with the highest priority is used.
+    priority: i32,
+
+    /// Tests that, if they are part of the same scenario, must run before this one.
+    after: Vec<UnitName>,
+
+    /// Tests that, if they are part of the same scenario, must run after this one.
+    before: Vec<UnitName>,
+
+    /// Tests that must never run in the same scenario as this one.
+    conflicts: Vec<UnitName>,
+
     /// The maximum duration this test can be run for.
     timeout: Option<Duration>,
 
@@ -95,9 +135,8 @@ impl TestDescription {
     pub fn from_path(path: &Path) -> Result<TestDescription, UnitDescriptionError> {
         let unit_name = UnitName::from_path(path)?;
 
-        // Parse the file into a systemd unit_file object
-        let mut contents = String::with_capacity(8192);
-        File::open(path)?.read_to_string(&mut contents)?;
+        // Read the file, along with any drop-in fragments and the units it inherits from
+        let contents = inherit::read_unit(path)?;
         Self::from_string(&contents, unit_name)
     }
 
@@ -114,10 +153,15 @@ impl TestDescription {
             description: "".to_owned(),
 
             jigs: vec![],
+            requires_capability: vec![],
 
             requires: vec![],
             suggests: vec![],
             provides: vec![],
+            priority: 0,
+            after: vec![],
+            before: vec![],
+            conflicts: vec![],
 
             timeout: None,
             exec_stop_success_timeout: None,
@@ -150,6 +194,52 @@ impl TestDescription {
                                 None => vec![],
                             }
                         }
+                        "RequiresCapability" => {
+                            test_description.requires_capability = parse_capabilities(directive.value().unwrap_or(""))
+                        }
+                        "Requires" => {
+                            test_description.requires = match directive.value() {
+                                Some(s) => UnitName::from_list(s, "test")?,
+                                None => vec![],
+                            }
+                        }
+                        "Suggests" => {
+                            test_description.suggests = match directive.value() {
+                                Some(s) => UnitName::from_list(s, "test")?,
+                                None => vec![],
+                            }
+                        }
+                        "Priority" => {
+                            test_description.priority = match directive.value() {
+                                None => 0,
+                                Some(s) => match s.trim().parse() {
+                                    Ok(p) => p,
+                                    Err(_) => return Err(UnitDescriptionError::InvalidNumber(
+                                        "Test".to_owned(),
+                                        "Priority".to_owned(),
+                                        s.to_owned(),
+                                    )),
+                                },
+                            }
+                        }
+                        "After" => {
+                            test_description.after = match directive.value() {
+                                Some(s) => UnitName::from_list(s, "test")?,
+                                None => vec![],
+                            }
+                        }
+                        "Before" => {
+                            test_description.before = match directive.value() {
+                                Some(s) => UnitName::from_list(s, "test")?,
+                                None => vec![],
+                            }
+                        }
+                        "Conflicts" => {
+                            test_description.conflicts = match directive.value() {
+                                Some(s) => UnitName::from_list(s, "test")?,
+                                None => vec![],
+                            }
+                        }
                         "Provides" => {
                             test_description.provides = match directive.value() {
                                 Some(s) => UnitName::from_list(s, "test")?,
@@ -220,7 +310,8 @@ impl TestDescription {
                                 None => None,
                                 Some(s) => Some(Self::parse_time(s)?),
                             }
-                        }                        &_ => (),
+                        }
+                        &_ => (),
                     }
                 }
                 &_ => (),
@@ -244,9 +335,55 @@ impl TestDescription {
         &self.id
     }
 
-    /// Returns true if this test is supported on the named jig.
-    pub fn supports_jig(&self, name: &UnitName) -> bool {
+    pub fn jigs(&self) -> &Vec<UnitName> {
+        &self.jigs
+    }
+
+    pub fn requires_capability(&self) -> &Vec<String> {
+        &self.requires_capability
+    }
+
+    /// The command to run, before variables and specifiers are expanded.
+    pub fn exec_start(&self) -> &String {
+        &self.exec_start
+    }
+
+    pub fn requires(&self) -> &Vec<UnitName> {
+        &self.requires
+    }
+
+    pub fn suggests(&self) -> &Vec<UnitName> {
+        &self.suggests
+    }
+
+    pub fn provides(&self) -> &Vec<UnitName> {
+        &self.provides
+    }
+
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    pub fn after(&self) -> &Vec<UnitName> {
+        &self.after
+    }
+
+    pub fn before(&self) -> &Vec<UnitName> {
+        &self.before
+    }
+
+    pub fn conflicts(&self) -> &Vec<UnitName> {
+        &self.conflicts
+    }
+
+    /// Returns true if this test is supported on the named jig.  A test that
+    /// only requires capabilities is supported if one of the `versions` of
+    /// the jig, such as before and after it changed, has all of them.
+    pub fn supports_jig(&self, name: &UnitName, versions: &[JigDescription]) -> bool {
         self.jigs.contains(name)
+            || (self.jigs.is_empty()
+                && !self.requires_capability.is_empty()
+                && versions.iter().any(|j| j.missing_capabilities(&self.requires_capability).is_empty()))
     }
 
     pub fn load(&self, 
@@ -274,18 +411,15 @@ impl Test {
     }
 
     pub fn select(&self, manager: &UnitManager) -> Result<(), UnitSelectError> {
-        // If there is at least one jig in the description list, then make sure
-        // that jig is loaded.
-        if self.description.jigs.len() > 0 {
-            let mut compatible = false;
-            for jig_name in &self.description.jigs {
-                if manager.jig_is_loaded(&jig_name) {
-                    compatible = true;
-                    break;
+        // If there is at least one jig in the description list, or a required
+        // capability, then make sure a suitable jig is loaded.
+        if !self.description.jigs.is_empty() || !self.description.requires_capability.is_empty() {
+            match manager.check_capabilities(&self.description.jigs, &self.description.requires_capability) {
+                Ok(()) => (),
+                Err(UnitIncompatibleReason::MissingCapabilities(jig, missing)) => {
+                    return Err(UnitSelectError::MissingCapabilities(jig, missing))
                 }
-            }
-            if ! compatible {
-                return Err(UnitSelectError::NoCompatibleJig);
+                Err(_) => return Err(UnitSelectError::NoCompatibleJig),
             }
         }
 
@@ -327,18 +461,37 @@ impl Test {
         // Announce to the world that we've started considering this test.
         ctrl.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::TestStarted)).ok();
 
-        let cmd = &self.description.exec_start;
-        let timeout = &self.description.timeout;
+        let expansion = config.expansion(&id);
+        let expanded = expansion.expand(&self.description.exec_start).and_then(|cmd| {
+            expansion
+                .expand_path(&config.working_directory(&self.description.working_directory))
+                .map(|dir| (cmd, dir))
+        });
+        let (cmd, directory) = match expanded {
+            Ok(e) => e,
+            Err(e) => {
+                ctrl.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::Log(LogType::Error, LogSource::Internal, format!("unable to start test: {}", e)))).unwrap();
+                ctrl.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::TestFinished(-3, format!("unable to start test: {}", e)))).ok();
+                ctrl.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::AdvanceScenario(-3))).ok();
+                return Err(UnitActivateError::ExpansionFailed(e));
+            }
+        };
+        let timeout = self.description.timeout.or(*config.test_timeout());
+
+        let mut test_env = config.environment();
+        test_env.extend(manager.test_environment(&id));
 
-        let mut cmd = Runny::new(cmd);
-        if let Some(timeout) = *timeout {
+        let mut cmd = command_with_environment(&cmd, &test_env);
+        if let Some(timeout) = timeout {
             cmd.timeout(timeout);
         }
-        cmd.directory(&Some(config.working_directory(&self.description.working_directory)));
-        let mut running = match cmd.start() {
+        cmd.directory(&Some(directory));
+        let start_result = cmd.start();
+
+        let mut running = match start_result {
             Ok(r) => r,
             Err(e) => {
-                ctrl.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::LogError(format!("unable to start test: {:?}", e)))).unwrap();
+                ctrl.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::Log(LogType::Error, LogSource::Internal, format!("unable to start test: {:?}", e)))).unwrap();
                 ctrl.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::TestFinished(-3, format!("unable to start test: {:?}", e)))).ok();
                 ctrl.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::AdvanceScenario(-3))).ok();
                 return Err(UnitActivateError::ExecFailed(e));
@@ -364,21 +517,21 @@ impl Test {
                             let mut line = String::new();
                             match buf_reader.read_line(&mut line) {
                                 Err(e) => {
-                                    thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::LogError(format!("test daemon raised error: {}", e.description())))).unwrap();
+                                    thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::Log(LogType::Error, LogSource::Internal, format!("test daemon raised error: {}", e.description())))).unwrap();
                                     thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::AdvanceScenario(-2))).ok();
                                     running.terminate(Some(Duration::from_secs(1))).ok();
                                     thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::TestFinished(-2, thr_last_line.lock().unwrap().clone()))).ok();
                                     return;
                                 }
                                 Ok(0) => {
-                                    thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::LogError(format!("test daemon exited early")))).unwrap();
+                                    thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::Log(LogType::Error, LogSource::Internal, format!("test daemon exited early")))).unwrap();
                                     thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::AdvanceScenario(-1))).ok();
                                     running.terminate(Some(Duration::from_secs(1))).ok();
                                     thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::TestFinished(-1, thr_last_line.lock().unwrap().clone()))).ok();
                                     return;
                                 }
                                 Ok(_) => {
-                                    thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::Log(line.clone()))).unwrap();
+                                    thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::Log(LogType::Info, LogSource::Stdout, line.clone()))).unwrap();
                                     if r.is_match(line.as_str()) {
                                         break;
                                     }
@@ -395,7 +548,7 @@ impl Test {
                         for line in buf_reader.lines() {
                             let line = line.expect("Unable to get next line");
                             *thr_thr_last_line.lock().unwrap() = line.clone();
-                            if let Err(_) = thr_thr_control.send(ManagerControlMessage::new(&thr_id, ManagerControlMessageContents::Log(line))) {
+                            if let Err(_) = thr_thr_control.send(ManagerControlMessage::new(&thr_id, ManagerControlMessageContents::Log(LogType::Info, LogSource::Stdout, line))) {
                                 break;
                             }
                         }
@@ -454,6 +607,10 @@ impl Test {
         &self.description.description
     }
 
+    pub fn test_description(&self) -> &TestDescription {
+        &self.description
+    }
+
     pub fn timeout(&self) -> &Option<Duration> {
         &self.description.timeout
     }
@@ -466,7 +623,7 @@ impl Test {
             for line in BufReader::new(stdout).lines() {
                 let line = line.expect("Unable to get next line");
                 *thr_last_line.lock().unwrap() = line.clone();
-                if let Err(_) = thr_control.send(ManagerControlMessage::new(&thr_id, ManagerControlMessageContents::Log(line))) {
+                if let Err(_) = thr_control.send(ManagerControlMessage::new(&thr_id, ManagerControlMessageContents::Log(LogType::Info, LogSource::Stdout, line))) {
                     break;
                 }
             }
@@ -481,7 +638,7 @@ impl Test {
             for line in BufReader::new(stderr).lines() {
                 let line = line.expect("Unable to get next line");
                 *thr_last_line.lock().unwrap() = line.clone();
-                if let Err(_) = thr_control.send(ManagerControlMessage::new(&thr_id, ManagerControlMessageContents::LogError(line))) {
+                if let Err(_) = thr_control.send(ManagerControlMessage::new(&thr_id, ManagerControlMessageContents::Log(LogType::Info, LogSource::Stderr, line))) {
                     break;
                 }
             }
@@ -489,17 +646,17 @@ impl Test {
     }
 }
 
-impl Dependency<UnitName> for Test {
+impl Dependency<UnitName> for TestDescription {
     fn name(&self) -> &UnitName {
-        &self.description.id
+        &self.id
     }
     fn requirements(&self) -> &Vec<UnitName> {
-        &self.description.requires
+        &self.requires
     }
     fn suggestions(&self) -> &Vec<UnitName> {
-        &self.description.suggests
+        &self.suggests
     }
     fn provides(&self) -> &Vec<UnitName> {
-        &self.description.provides
+        &self.provides
     }
 }
\ No newline at end of file
@@ -2,19 +2,19 @@ extern crate runny;
 extern crate systemd_parser;
 
 use std::cell::RefCell;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Read};
+use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
 use std::thread;
 
-use config::Config;
+use config::{command_with_environment, Config};
+use inherit;
 use unit::{UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitIncompatibleReason, UnitSelectError, UnitDeselectError,
            UnitName};
+use unitbroadcaster::{LogSource, LogType};
 use unitmanager::{ManagerControlMessage, ManagerControlMessageContents, UnitManager};
 
 use self::systemd_parser::items::DirectiveEntry;
-use self::runny::Runny;
 use self::runny::running::{Running, RunningOutput};
 
 #[derive(Clone, Copy)]
@@ -23,6 +23,9 @@ enum TriggerFormat {
     JSON,
 }
 
+/// Directives recognized in the [Trigger] section.
+pub const DIRECTIVES: &[&str] = &["Name", "Description", "Jigs", "WorkingDirectory", "ExecStart", "Format"];
+
 /// A struct defining an in-memory representation of a .Trigger file
 #[derive(Clone)]
 pub struct TriggerDescription {
@@ -52,9 +55,12 @@ impl TriggerDescription {
     pub fn from_path(path: &Path) -> Result<TriggerDescription, UnitDescriptionError> {
         let unit_name = UnitName::from_path(path)?;
 
-        // Parse the file into a systemd unit_file object
-        let mut contents = String::with_capacity(8192);
-        File::open(path)?.read_to_string(&mut contents)?;
+        // Read the file, along with any drop-in fragments and the units it inherits from
+        let contents = inherit::read_unit(path)?;
+        Self::from_string(&contents, unit_name)
+    }
+
+    pub fn from_string(contents: &str, unit_name: UnitName) -> Result<TriggerDescription, UnitDescriptionError> {
         let unit_file = systemd_parser::parse_string(&contents)?;
 
         if !unit_file.has_category("Trigger") {
@@ -127,6 +133,10 @@ impl TriggerDescription {
         Ok(interface_description)
     }
 
+    pub fn jigs(&self) -> &Vec<UnitName> {
+        &self.jigs
+    }
+
     /// Returns true if this test is supported on the named jig.
     pub fn supports_jig(&self, name: &UnitName) -> bool {
         self.jigs.contains(name)
@@ -194,9 +204,10 @@ impl Trigger {
         manager: &UnitManager,
         config: &Config,
     ) -> Result<(), UnitActivateError> {
-        let mut running = Runny::new(self.description.exec_start.as_str())
-                    .directory(&Some(config.working_directory(&self.description.working_directory)))
-                    .start()?;
+        let expansion = config.expansion(self.id());
+        let mut cmd = command_with_environment(&expansion.expand(&self.description.exec_start)?, &config.environment());
+        cmd.directory(&Some(expansion.expand_path(&config.working_directory(&self.description.working_directory))?));
+        let mut running = cmd.start()?;
 
         let stdout = running.take_output();
         let stderr = running.take_error();
@@ -253,7 +264,7 @@ impl Trigger {
         for line in BufReader::new(output).lines() {
             let line = line.expect("Unable to get next line");
             // If the send fails, that means the other end has closed the pipe.
-            if let Err(_) = control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::LogError(line))) {
+            if let Err(_) = control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::Log(LogType::Info, LogSource::Stderr, line))) {
                 break;
             }
         }
@@ -287,6 +298,17 @@ impl Trigger {
                             ManagerControlMessageContents::StartScenario(None)
                         }
                     },
+                    "serial" => {
+                        if !words.is_empty() {
+                            ManagerControlMessageContents::Serial(words.join(" "))
+                        } else {
+                            ManagerControlMessageContents::Error("SERIAL requires a serial number".to_owned())
+                        }
+                    },
+                    "log" => match LogType::from_log_words(&mut words) {
+                        Ok(kind) => ManagerControlMessageContents::Log(kind, LogSource::Protocol, words.join(" ")),
+                        Err(e) => ManagerControlMessageContents::Error(e),
+                    },
                     v => ManagerControlMessageContents::Unimplemented(v.to_owned(), words.join(" ")),
                 }
             };
@@ -2,10 +2,14 @@ extern crate notify;
 
 use std::path::{Path, PathBuf};
 use std::io;
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc::channel;
 use std::time::Duration;
 use std::thread;
 
+use dropin;
+use layers::{ConfigLayers, Precedence};
+use unit::UnitName;
 use unitbroadcaster::*;
 
 use self::notify::{RecommendedWatcher, Watcher, RecursiveMode};
@@ -14,6 +18,7 @@ pub struct UnitWatcher {
     paths: Vec<PathBuf>,
     watcher: RecommendedWatcher,
     broadcaster: UnitBroadcaster,
+    layers: Arc<Mutex<ConfigLayers>>,
 }
 
 impl UnitWatcher {
@@ -25,25 +30,39 @@ impl UnitWatcher {
         let watcher: RecommendedWatcher = Watcher::new(watcher_tx, Duration::from_secs(0))
             .expect("Unable to create file watcher");
 
+        let layers = Arc::new(Mutex::new(ConfigLayers::new(Precedence::LastWins)));
+
         // This is a simple loop, but you may want to use more complex logic here,
         // for example to handle I/O.
         let thread_broadcaster = broadcaster.clone();
+        let thread_layers = layers.clone();
         thread::spawn(move || {
             loop {
                 match watcher_rx.recv() {
                     Ok(event) => {
-                        // Convert the DebouncedEvent into a UnitEvent
+                        let layers = thread_layers.lock().unwrap();
+
+                        // Convert the DebouncedEvent into a UnitEvent.  Changes to
+                        // drop-in fragments are treated as updates to the unit they modify.
                         let status_event = match event {
-                            notify::DebouncedEvent::Create(path) => UnitStatusEvent::new_added(&path),
-                            notify::DebouncedEvent::Write(path) => UnitStatusEvent::new_updated(&path),
-                            notify::DebouncedEvent::Remove(path) => UnitStatusEvent::new_removed(&path),
+                            notify::DebouncedEvent::Create(path) => {
+                                Self::fragment_changed(&layers, &path).unwrap_or_else(|| Self::unit_added(&layers, &path))
+                            }
+                            notify::DebouncedEvent::Write(path) => {
+                                Self::fragment_changed(&layers, &path).unwrap_or_else(|| Self::unit_updated(&layers, &path))
+                            }
+                            notify::DebouncedEvent::Remove(path) => {
+                                Self::fragment_changed(&layers, &path).unwrap_or_else(|| Self::unit_removed(&layers, &path))
+                            }
                             // Convert Rename() into removed/added
                             notify::DebouncedEvent::Rename(old_name, new_name) => {
-                                if let Some(evt) = UnitStatusEvent::new_removed(&old_name) {
+                                let removed = Self::fragment_changed(&layers, &old_name)
+                                    .unwrap_or_else(|| Self::unit_removed(&layers, &old_name));
+                                if let Some(evt) = removed {
                                     thread_broadcaster.broadcast(&UnitEvent::Status(evt));
                                 }
-                                UnitStatusEvent::new_added(&new_name)
-                            } 
+                                Self::fragment_changed(&layers, &new_name).unwrap_or_else(|| Self::unit_added(&layers, &new_name))
+                            }
                             _ => None,
                         };
 
@@ -61,15 +80,26 @@ impl UnitWatcher {
             paths: vec![],
             broadcaster: broadcaster.clone(),
             watcher: watcher,
+            layers: layers,
         }
     }
 
-    pub fn add_path(&mut self, config_dir: &str) -> Result<(), io::Error> {
-        let dir = Path::new(config_dir);
+    /// Decide whether directories added later override earlier ones, or the reverse.
+    /// This must be called before any paths are added.
+    pub fn set_precedence(&mut self, precedence: Precedence) {
+        self.layers.lock().unwrap().set_precedence(precedence);
+    }
+
+    pub fn add_path(&mut self, dir: &Path) -> Result<(), io::Error> {
+        self.layers.lock().unwrap().add_dir(dir);
         for entry in dir.read_dir()? {
-           if let Some(evt) = UnitStatusEvent::new_added(&entry?.path()) {
-               self.broadcaster.broadcast(&UnitEvent::Status(evt));
-           }
+            let path = entry?.path();
+            if !Self::is_effective(&self.layers.lock().unwrap(), &path) {
+                continue;
+            }
+            if let Some(evt) = UnitStatusEvent::new_added(&path) {
+                self.broadcaster.broadcast(&UnitEvent::Status(evt));
+            }
         }
 
         self.watch(&dir).expect("Unable to watch directory");
@@ -77,6 +107,70 @@ impl UnitWatcher {
         Ok(())
     }
 
+    /// Returns false if `path` is a unit that is shadowed by a file with the
+    /// same name in a directory with higher precedence.
+    fn is_effective(layers: &ConfigLayers, path: &Path) -> bool {
+        let name = match UnitName::from_path(path) {
+            Ok(n) => n,
+            Err(_) => return true,
+        };
+        // Files outside of the configuration directories themselves
+        // aren't subject to layering.
+        let rank = match layers.rank_of(path) {
+            Some(r) => r,
+            None => return true,
+        };
+        match layers.effective(&name) {
+            Some(ref effective) => layers.rank_of(effective) == Some(rank),
+            None => true,
+        }
+    }
+
+    fn unit_added(layers: &ConfigLayers, path: &Path) -> Option<UnitStatusEvent> {
+        if Self::is_effective(layers, path) {
+            UnitStatusEvent::new_added(path)
+        } else {
+            None
+        }
+    }
+
+    fn unit_updated(layers: &ConfigLayers, path: &Path) -> Option<UnitStatusEvent> {
+        if Self::is_effective(layers, path) {
+            UnitStatusEvent::new_updated(path)
+        } else {
+            None
+        }
+    }
+
+    /// A unit file was removed.  If it was shadowing another file, that file
+    /// is loaded in its place.
+    fn unit_removed(layers: &ConfigLayers, path: &Path) -> Option<UnitStatusEvent> {
+        let name = UnitName::from_path(path).ok()?;
+        let rank = match layers.rank_of(path) {
+            Some(r) => r,
+            None => return UnitStatusEvent::new_removed(path),
+        };
+        match layers.effective(&name) {
+            None => UnitStatusEvent::new_removed(path),
+            Some(ref next) if layers.rank_of(next).map(|r| r > rank).unwrap_or(false) => {
+                UnitStatusEvent::new_updated(next)
+            }
+            // The removed file was itself shadowed, so nothing changes.
+            Some(_) => None,
+        }
+    }
+
+    /// If `path` is a drop-in fragment (or directory of them), the update
+    /// event for the unit it belongs to, as long as that unit is in use.
+    fn fragment_changed(layers: &ConfigLayers, path: &Path) -> Option<Option<UnitStatusEvent>> {
+        let unit = dropin::parent_unit(path)?;
+        if unit.is_file() && Self::is_effective(layers, &unit) {
+            Some(UnitStatusEvent::new_updated(&unit))
+        } else {
+            Some(None)
+        }
+    }
+
     fn watch(&mut self, path: &Path) -> notify::Result<()> {
 
         // Add a path to be watched. All files and directories at that path and
@@ -0,0 +1,173 @@
+// Fixtures wear out: pogo pins and clamps are only good for so many
+// cycles.  Each jig counts how many scenarios have been started on it, and
+// how many times each test named in its WearWarning or WearLimit has run.
+// Counters are kept in the state directory, so that they survive restarts,
+// and only go back to zero when someone resets them after maintenance.
+//
+//   <state-dir>/wear.json
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use state;
+use unit::UnitName;
+
+/// Name of the wear counter file within the state directory.
+pub const STATE_FILE: &str = "wear.json";
+
+/// When a counter should be looked at, and when it must be.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Thresholds {
+    /// Log a warning once the counter reaches this
+    pub warning: Option<u64>,
+
+    /// Refuse to start scenarios once the counter reaches this
+    pub limit: Option<u64>,
+}
+
+/// Parse a WearWarning or WearLimit value, which is a list of counts.  A
+/// bare count applies to the jig itself, while TEST=COUNT applies to a test.
+pub fn parse_thresholds(jig: &UnitName, value: &str) -> Result<Vec<(UnitName, u64)>, String> {
+    let mut thresholds = vec![];
+    for item in value.split(|c: char| c == ',' || c.is_whitespace()).filter(|i| !i.is_empty()) {
+        let (counter, count) = match item.find('=') {
+            None => (jig.clone(), item),
+            Some(pos) => match UnitName::from_str(&item[..pos], "test") {
+                Ok(test) => (test, &item[pos + 1..]),
+                Err(e) => return Err(format!("{}: {}", item, e)),
+            },
+        };
+        match count.parse() {
+            Ok(count) => thresholds.push((counter, count)),
+            Err(_) => return Err(format!("{}: count must be a whole number", item)),
+        }
+    }
+    Ok(thresholds)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WearError {
+    /// A counter has reached its limit and needs to be reset.
+    LimitReached(UnitName /* counter */, u64 /* count */, u64 /* limit */),
+
+    /// The counter file couldn't be read.
+    StateUnreadable(PathBuf, String),
+}
+
+impl fmt::Display for WearError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WearError::LimitReached(counter, count, limit) => write!(
+                f,
+                "{} has been used {} times, reaching its limit of {}; after maintenance, reset it with \"exclave wear --reset\"",
+                counter, count, limit
+            ),
+            WearError::StateUnreadable(path, err) => {
+                write!(f, "unable to read wear counters {}: {}", path.display(), err)
+            }
+        }
+    }
+}
+
+pub struct WearCounters {
+    path: PathBuf,
+
+    /// For each jig, how many times each counter has been used
+    jigs: BTreeMap<String, BTreeMap<String, u64>>,
+}
+
+impl WearCounters {
+    /// Load the counters from `state_dir`.  A missing file means nothing
+    /// has been counted yet.
+    pub fn open(state_dir: &Path) -> io::Result<WearCounters> {
+        let path = state_dir.join(STATE_FILE);
+        let jigs = state::load(&path)?.unwrap_or_default();
+        Ok(WearCounters {
+            path,
+            jigs,
+        })
+    }
+
+    /// Like `open()`, but reports failure as a reason not to run.
+    pub fn open_for_check(state_dir: &Path) -> Result<WearCounters, WearError> {
+        WearCounters::open(state_dir)
+            .map_err(|e| WearError::StateUnreadable(state_dir.join(STATE_FILE), format!("{}", e)))
+    }
+
+    pub fn count(&self, jig: &UnitName, counter: &UnitName) -> u64 {
+        self.jigs
+            .get(&jig.to_string())
+            .and_then(|counters| counters.get(&counter.to_string()))
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// Every counter `jig` has, in order.
+    pub fn counters(&self, jig: &UnitName) -> Vec<(String, u64)> {
+        match self.jigs.get(&jig.to_string()) {
+            None => vec![],
+            Some(counters) => counters.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+        }
+    }
+
+    /// Every jig that has counters.
+    pub fn jigs(&self) -> Vec<String> {
+        self.jigs.keys().cloned().collect()
+    }
+
+    /// Add one to a counter, returning the new count.
+    pub fn increment(&mut self, jig: &UnitName, counter: &UnitName) -> io::Result<u64> {
+        let count = {
+            let entry = self.jigs
+                .entry(jig.to_string())
+                .or_default()
+                .entry(counter.to_string())
+                .or_insert(0);
+            *entry += 1;
+            *entry
+        };
+        state::save(&self.path, &self.jigs)?;
+        Ok(count)
+    }
+
+    /// Set one of a jig's counters back to zero, or all of them if no
+    /// counter is given.  Returns the counters that were reset.
+    pub fn reset(&mut self, jig: &UnitName, counter: Option<&UnitName>) -> io::Result<Vec<String>> {
+        let jig = jig.to_string();
+        let reset = match self.jigs.get_mut(&jig) {
+            None => vec![],
+            Some(counters) => match counter {
+                None => {
+                    let names = counters.keys().cloned().collect();
+                    counters.clear();
+                    names
+                }
+                Some(name) => match counters.remove(&name.to_string()) {
+                    None => vec![],
+                    Some(_) => vec![name.to_string()],
+                },
+            },
+        };
+        if self.jigs.get(&jig).map(|c| c.is_empty()).unwrap_or(false) {
+            self.jigs.remove(&jig);
+        }
+        if !reset.is_empty() {
+            state::save(&self.path, &self.jigs)?;
+        }
+        Ok(reset)
+    }
+
+    /// Make sure none of `counters` has reached its limit.
+    pub fn check(&self, jig: &UnitName, counters: &[(&UnitName, &Thresholds)]) -> Result<(), WearError> {
+        for &(counter, thresholds) in counters {
+            if let Some(limit) = thresholds.limit {
+                let count = self.count(jig, counter);
+                if count >= limit {
+                    return Err(WearError::LimitReached(counter.clone(), count, limit));
+                }
+            }
+        }
+        Ok(())
+    }
+}